@@ -0,0 +1,103 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use wasmer::{Module, Store};
+
+use crate::{loader::Packages, manifest::MANIFEST};
+
+pub const EXTENSION: &str = "wasmu";
+
+pub fn artifact_path(directory: &Path, name: &str, optimization: bool) -> PathBuf {
+    let level = match optimization {
+        true => "",
+        false => ".O0",
+    };
+    directory
+        .join("build")
+        .join(format!("{}{}.{}", name, level, EXTENSION))
+}
+
+fn files(path: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .flat_map(|entry| files(&entry.path()))
+            .collect(),
+        Err(_) if path.is_file() => vec![path.to_path_buf()],
+        Err(_) => vec![],
+    }
+}
+
+pub fn source_files(packages: &Packages) -> Vec<PathBuf> {
+    packages
+        .directories
+        .iter()
+        .map(|directory| directory.join(MANIFEST))
+        .chain(
+            packages
+                .sources
+                .iter()
+                .flatten()
+                .flat_map(|source| files(source)),
+        )
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+pub fn fresh(artifact: &Path, sources: &[PathBuf]) -> bool {
+    match modified(artifact) {
+        Some(built) => sources
+            .iter()
+            .all(|source| modified(source).is_some_and(|changed| changed < built)),
+        None => false,
+    }
+}
+
+pub fn stamps(sources: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    sources.iter().map(|source| modified(source)).collect()
+}
+
+pub fn save(module: &Module, path: &Path) {
+    let bytes = module
+        .serialize()
+        .unwrap_or_else(|error| panic!("Could not serialize {}: {}", path.display(), error));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .unwrap_or_else(|error| panic!("Could not create {}: {}", parent.display(), error));
+    }
+    fs::write(path, bytes)
+        .unwrap_or_else(|error| panic!("Could not write {}: {}", path.display(), error));
+}
+
+pub fn restore(store: &Store, path: &Path) -> Option<Module> {
+    let bytes = fs::read(path).ok()?;
+    unsafe { Module::deserialize(store, &bytes) }.ok()
+}
+
+pub fn load_module(
+    store: &Store,
+    artifact: &Path,
+    sources: &[PathBuf],
+    aot: bool,
+    compile: impl FnOnce() -> String,
+) -> Module {
+    let restored = match fresh(artifact, sources) {
+        true => restore(store, artifact),
+        false => None,
+    };
+    restored.unwrap_or_else(|| {
+        let module = Module::new(store, compile()).unwrap();
+        if aot || modified(artifact).is_some() {
+            save(&module, artifact);
+        }
+        module
+    })
+}
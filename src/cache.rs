@@ -0,0 +1,305 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write,
+    fs,
+    hash::{Hash, Hasher},
+    path::Path,
+    str::Lines,
+};
+
+use crate::{
+    codegen::{Function, Instruction, Message, OperandKind, Signature, Type, Variant, TYPES},
+    parser,
+    tokenizer::Fnv,
+};
+
+const FORMAT: &str = "mongoose-cache-2";
+
+const INSTRUCTIONS: &[Instruction] = &[
+    Instruction::I64Const,
+    Instruction::F64Const,
+    Instruction::I64Add,
+    Instruction::I64Sub,
+    Instruction::I64Mul,
+    Instruction::I64DivS,
+    Instruction::I64RemS,
+    Instruction::I64And,
+    Instruction::I64Xor,
+    Instruction::I64Or,
+    Instruction::I64Eq,
+    Instruction::I64Neq,
+    Instruction::I64Shl,
+    Instruction::I64ShrS,
+    Instruction::I64LtS,
+    Instruction::I64LeS,
+    Instruction::I64GtS,
+    Instruction::I64GeS,
+    Instruction::F64Add,
+    Instruction::F64Sub,
+    Instruction::F64Mul,
+    Instruction::F64Div,
+    Instruction::F64Eq,
+    Instruction::F64Ne,
+    Instruction::F64Lt,
+    Instruction::F64Le,
+    Instruction::F64Gt,
+    Instruction::F64Ge,
+    Instruction::I64Eqz,
+    Instruction::I32Eqz,
+    Instruction::I32And,
+    Instruction::I64GeU,
+    Instruction::SetLocal,
+    Instruction::GetLocal,
+    Instruction::TeeLocal,
+    Instruction::GlobalGet,
+    Instruction::GlobalSet,
+    Instruction::Call,
+    Instruction::CallIndirect,
+    Instruction::If,
+    Instruction::Else,
+    Instruction::Block,
+    Instruction::Loop,
+    Instruction::End,
+    Instruction::BrIf,
+    Instruction::Br,
+    Instruction::BrTable,
+    Instruction::Return,
+    Instruction::Unreachable,
+    Instruction::Drop,
+    Instruction::I32WrapI64,
+    Instruction::I64ExtendI32U,
+    Instruction::I64Load,
+    Instruction::I64Store,
+    Instruction::MemorySize,
+    Instruction::MemoryGrow,
+];
+
+const OPERAND_KINDS: &[OperandKind] = &[
+    OperandKind::IntLiteral,
+    OperandKind::FloatLiteral,
+    OperandKind::Local,
+    OperandKind::Symbol,
+    OperandKind::Label,
+    OperandKind::Result,
+    OperandKind::Offset,
+    OperandKind::Function,
+    OperandKind::Type,
+];
+
+pub(crate) fn key(
+    ast_func: &parser::Function,
+    type_arguments: &[Type],
+    globals: &HashSet<String>,
+    variants: &HashMap<String, Variant>,
+    functions: &HashMap<String, Signature>,
+) -> u64 {
+    let mut hasher = Fnv::default();
+    FORMAT.hash(&mut hasher);
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    ast_func.hash.hash(&mut hasher);
+    ast_func.kinds.hash(&mut hasher);
+    ast_func.symbols.hash(&mut hasher);
+    ast_func.ints.hash(&mut hasher);
+    ast_func.floats.hash(&mut hasher);
+    type_arguments.hash(&mut hasher);
+    for symbol in ast_func.symbols.iter() {
+        globals.contains(symbol).hash(&mut hasher);
+        variants.get(symbol).hash(&mut hasher);
+        functions.get(symbol).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn position<T: PartialEq>(values: &[T], value: &T) -> usize {
+    values
+        .iter()
+        .position(|other| other == value)
+        .expect("Value missing from cache encoding table")
+}
+
+fn encode_line(mut code: String, line: impl std::fmt::Display) -> String {
+    writeln!(code, "{}", line).unwrap();
+    code
+}
+
+fn encode_list<T>(code: String, values: &[T], encode: impl Fn(String, &T) -> String) -> String {
+    let code = encode_line(code, values.len());
+    values.iter().fold(code, encode)
+}
+
+fn encode_usizes(code: String, values: &[usize]) -> String {
+    encode_list(code, values, |code, &value| encode_line(code, value))
+}
+
+fn encode_strings(code: String, values: &[String]) -> String {
+    encode_list(code, values, |code, value| encode_line(code, value))
+}
+
+fn encode_types(code: String, values: &[Type]) -> String {
+    encode_list(code, values, |code, value| {
+        encode_line(code, position(&TYPES, value))
+    })
+}
+
+fn encode_option(code: String, value: Option<usize>) -> String {
+    match value {
+        Some(value) => encode_line(encode_line(code, 1), value),
+        None => encode_line(code, 0),
+    }
+}
+
+fn encode_function(code: String, func: &Function) -> String {
+    let code = encode_line(code, func.name);
+    let code = encode_list(code, &func.instructions, |code, instruction| {
+        encode_line(code, position(INSTRUCTIONS, instruction))
+    });
+    let code = encode_list(code, &func.operand_kinds, |code, operand_kinds| {
+        encode_list(code, operand_kinds, |code, operand_kind| {
+            encode_line(code, position(OPERAND_KINDS, operand_kind))
+        })
+    });
+    let code = encode_list(code, &func.operands, |code, operands| {
+        encode_usizes(code, operands)
+    });
+    let code = encode_strings(code, &func.locals);
+    let code = encode_types(code, &func.types);
+    let mut name_to_local = func
+        .name_to_local
+        .iter()
+        .collect::<Vec<(&String, &usize)>>();
+    name_to_local.sort();
+    let code = encode_list(code, &name_to_local, |code, (name, &local)| {
+        encode_line(encode_line(code, name), local)
+    });
+    let code = encode_strings(code, &func.symbols);
+    let code = encode_strings(code, &func.ints);
+    let code = encode_strings(code, &func.floats);
+    let code = encode_line(code, func.arguments);
+    let code = encode_line(code, position(&TYPES, &func.result));
+    let code = encode_line(code, func.next_label);
+    let mut tail_calls = func.tail_calls.iter().copied().collect::<Vec<usize>>();
+    tail_calls.sort_unstable();
+    let code = encode_usizes(code, &tail_calls);
+    let code = encode_option(code, func.tail_loop);
+    let code = encode_option(code, func.inline.map(usize::from));
+    encode_line(code, usize::from(func.memory))
+}
+
+fn encode(func: &Function, messages: &[Message]) -> String {
+    let code = encode_function(String::new(), func);
+    encode_list(code, messages, |code, message| match message {
+        Message::Spawn(name, type_arguments) => {
+            encode_types(encode_line(encode_line(code, 0), name), type_arguments)
+        }
+        Message::Closure(name, func) => {
+            encode_function(encode_line(encode_line(code, 1), name), func)
+        }
+        Message::Done(..) => panic!("Cannot cache a finished function message"),
+    })
+}
+
+fn decode_line(lines: &mut Lines) -> Option<String> {
+    lines.next().map(str::to_string)
+}
+
+fn decode_usize(lines: &mut Lines) -> Option<usize> {
+    lines.next()?.parse().ok()
+}
+
+fn decode_list<T>(lines: &mut Lines, decode: impl Fn(&mut Lines) -> Option<T>) -> Option<Vec<T>> {
+    let length = decode_usize(lines)?;
+    (0..length).map(|_| decode(lines)).collect()
+}
+
+fn decode_type(lines: &mut Lines) -> Option<Type> {
+    TYPES.get(decode_usize(lines)?).copied()
+}
+
+fn decode_option(lines: &mut Lines) -> Option<Option<usize>> {
+    match decode_usize(lines)? {
+        0 => Some(None),
+        _ => decode_usize(lines).map(Some),
+    }
+}
+
+fn decode_function(lines: &mut Lines) -> Option<Function> {
+    let name = decode_usize(lines)?;
+    let instructions = decode_list(lines, |lines| {
+        INSTRUCTIONS.get(decode_usize(lines)?).copied()
+    })?;
+    let operand_kinds = decode_list(lines, |lines| {
+        decode_list(lines, |lines| {
+            OPERAND_KINDS.get(decode_usize(lines)?).copied()
+        })
+    })?;
+    let operands = decode_list(lines, |lines| decode_list(lines, decode_usize))?;
+    let locals = decode_list(lines, decode_line)?;
+    let types = decode_list(lines, decode_type)?;
+    let name_to_local = decode_list(lines, |lines| {
+        Some((decode_line(lines)?, decode_usize(lines)?))
+    })?;
+    Some(Function {
+        name,
+        instructions,
+        operand_kinds,
+        operands,
+        locals,
+        types,
+        name_to_local: name_to_local.into_iter().collect(),
+        globals: HashSet::new(),
+        variants: HashMap::new(),
+        functions: HashMap::new(),
+        symbols: decode_list(lines, decode_line)?,
+        ints: decode_list(lines, decode_line)?,
+        floats: decode_list(lines, decode_line)?,
+        arguments: decode_usize(lines)?,
+        result: decode_type(lines)?,
+        next_label: decode_usize(lines)?,
+        loops: vec![],
+        tail_calls: decode_list(lines, decode_usize)?.into_iter().collect(),
+        tail_loop: decode_option(lines)?,
+        inline: decode_option(lines)?.map(|inline| inline != 0),
+        memory: decode_usize(lines)? != 0,
+    })
+}
+
+fn decode(code: &str) -> Option<(Function, Vec<Message>)> {
+    let mut lines = code.lines();
+    let func = decode_function(&mut lines)?;
+    let messages = decode_list(&mut lines, |lines| match decode_usize(lines)? {
+        0 => Some(Message::Spawn(
+            decode_line(lines)?,
+            decode_list(lines, decode_type)?,
+        )),
+        _ => Some(Message::Closure(
+            decode_line(lines)?,
+            Box::new(decode_function(lines)?),
+        )),
+    })?;
+    match lines.next() {
+        None => Some((func, messages)),
+        Some(_) => None,
+    }
+}
+
+fn header(key: u64) -> String {
+    format!("{} {} {:016x}", FORMAT, env!("CARGO_PKG_VERSION"), key)
+}
+
+pub(crate) fn read(directory: &Path, key: u64) -> Option<(Function, Vec<Message>)> {
+    let code = fs::read_to_string(directory.join(format!("{:016x}", key))).ok()?;
+    let (first, code) = code.split_once('\n')?;
+    match first == header(key) {
+        true => decode(code),
+        false => None,
+    }
+}
+
+pub(crate) fn save(directory: &Path, key: u64, func: &Function, messages: &[Message]) {
+    fs::create_dir_all(directory)
+        .unwrap_or_else(|error| panic!("Could not create {}: {}", directory.display(), error));
+    let path = directory.join(format!("{:016x}", key));
+    let code = format!("{}\n{}", header(key), encode(func, messages));
+    fs::write(&path, code)
+        .unwrap_or_else(|error| panic!("Could not write {}: {}", path.display(), error));
+}
@@ -1,13 +1,18 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
+    path::Path,
     sync::mpsc::{self, Sender},
 };
 
-use crate::parser::{self, Ast};
+use crate::{
+    cache,
+    parser::{self, Ast, GlobalKind, Pattern},
+};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Instruction {
     I64Const,
+    F64Const,
     I64Add,
     I64Sub,
     I64Mul,
@@ -24,10 +29,27 @@ pub enum Instruction {
     I64LeS,
     I64GtS,
     I64GeS,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Le,
+    F64Gt,
+    F64Ge,
+    I64Eqz,
     I32Eqz,
+    I32And,
+    I64GeU,
     SetLocal,
     GetLocal,
+    TeeLocal,
+    GlobalGet,
+    GlobalSet,
     Call,
+    CallIndirect,
     If,
     Else,
     Block,
@@ -35,14 +57,61 @@ pub enum Instruction {
     End,
     BrIf,
     Br,
+    BrTable,
+    Return,
+    Unreachable,
+    Drop,
+    I32WrapI64,
+    I64ExtendI32U,
+    I64Load,
+    I64Store,
+    MemorySize,
+    MemoryGrow,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum OperandKind {
     IntLiteral,
+    FloatLiteral,
     Local,
     Symbol,
     Label,
+    Result,
+    Offset,
+    Function,
+    Type,
+}
+
+#[derive(Debug, PartialEq, Hash, Copy, Clone)]
+pub enum Type {
+    I32,
+    I64,
+    F64,
+}
+
+pub const TYPES: [Type; 3] = [Type::I32, Type::I64, Type::F64];
+
+pub fn type_name(value_type: Type) -> &'static str {
+    match value_type {
+        Type::I32 => "i32",
+        Type::I64 => "i64",
+        Type::F64 => "f64",
+    }
+}
+
+#[derive(Debug, PartialEq, Hash, Clone)]
+pub struct Signature {
+    pub type_parameters: Vec<String>,
+    pub parameters: Vec<Option<String>>,
+    pub result: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Hash, Copy, Clone)]
+pub struct Variant {
+    pub enumeration: usize,
+    pub tag: usize,
+    pub fields: usize,
+    pub variants: usize,
 }
 
 #[derive(Debug, PartialEq)]
@@ -52,22 +121,53 @@ pub struct Function {
     pub operand_kinds: Vec<Vec<OperandKind>>,
     pub operands: Vec<Vec<usize>>,
     pub locals: Vec<String>,
+    pub types: Vec<Type>,
     pub name_to_local: HashMap<String, usize>,
+    pub globals: HashSet<String>,
+    pub variants: HashMap<String, Variant>,
+    pub functions: HashMap<String, Signature>,
     pub symbols: Vec<String>,
     pub ints: Vec<String>,
+    pub floats: Vec<String>,
     pub arguments: usize,
+    pub result: Type,
     pub next_label: usize,
+    pub loops: Vec<usize>,
+    pub tail_calls: HashSet<usize>,
+    pub tail_loop: Option<usize>,
+    pub inline: Option<bool>,
+    pub memory: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Exports {
+    pub names: Vec<String>,
+    pub functions: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Globals {
+    pub names: Vec<String>,
+    pub values: Vec<i64>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Wasm {
     pub functions: Vec<Function>,
     pub name_to_function: HashMap<String, usize>,
+    pub exports: Exports,
+    pub globals: Globals,
+    pub memory: bool,
+    pub table: Vec<String>,
+    pub types: Vec<usize>,
+    pub warnings: Vec<String>,
+    pub ast: Option<Ast>,
 }
 
-enum Message {
-    Spawn(String),
-    Done(usize, Function),
+pub(crate) enum Message {
+    Spawn(String, Vec<Type>),
+    Done(usize, Box<Function>),
+    Closure(String, Box<Function>),
 }
 
 fn codegen_int(mut wasm_func: Function, ast_func: &parser::Function, entity: usize) -> Function {
@@ -77,6 +177,136 @@ fn codegen_int(mut wasm_func: Function, ast_func: &parser::Function, entity: usi
     wasm_func
 }
 
+fn codegen_float(mut wasm_func: Function, ast_func: &parser::Function, entity: usize) -> Function {
+    wasm_func.instructions.push(Instruction::F64Const);
+    wasm_func
+        .operand_kinds
+        .push(vec![OperandKind::FloatLiteral]);
+    wasm_func.operands.push(vec![ast_func.indices[entity]]);
+    wasm_func
+}
+
+// Comparisons produce i32 values, which are still passed and stored where i64 is expected.
+fn compatible(left: Type, right: Type) -> bool {
+    left == right || (left != Type::F64 && right != Type::F64)
+}
+
+pub fn widen(value_type: Type) -> Type {
+    match value_type {
+        Type::I32 => Type::I64,
+        value_type => value_type,
+    }
+}
+
+fn resolve_type(name: &str, type_parameters: &[String], type_arguments: &[Type]) -> Type {
+    match name {
+        "i64" => Type::I64,
+        "f64" => Type::F64,
+        _ => type_parameters
+            .iter()
+            .position(|type_parameter| type_parameter == name)
+            .map(|type_parameter| type_arguments[type_parameter])
+            .unwrap_or_else(|| panic!("Unknown type {}", name)),
+    }
+}
+
+fn instance_name(name: &str, type_arguments: &[Type]) -> String {
+    if type_arguments.is_empty() {
+        return name.to_string();
+    }
+    let type_arguments = type_arguments
+        .iter()
+        .map(|&type_argument| type_name(type_argument))
+        .collect::<Vec<&str>>();
+    format!("{}<{}>", name, type_arguments.join("."))
+}
+
+fn call_signature(
+    wasm_func: &Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> (Vec<Type>, Vec<Option<Type>>, Type) {
+    let function_call = ast_func.indices[entity];
+    let callee = match callee_name(ast_func, function_call) {
+        Some(callee) => callee,
+        None => return (vec![], vec![], Type::I64),
+    };
+    let signature = match wasm_func.functions.get(callee) {
+        Some(signature) if !wasm_func.name_to_local.contains_key(callee) => signature,
+        _ => return (vec![], vec![], Type::I64),
+    };
+    let argument_types = ast_func.function_calls.parameters[function_call]
+        .iter()
+        .map(|&parameter| widen(expression_type(wasm_func, ast_func, parameter)))
+        .collect::<Vec<Type>>();
+    let type_arguments = signature
+        .type_parameters
+        .iter()
+        .map(|type_parameter| {
+            signature
+                .parameters
+                .iter()
+                .zip(argument_types.iter())
+                .filter(|(parameter, _)| parameter.as_deref() == Some(type_parameter.as_str()))
+                .fold(None, |bound, (_, &argument_type)| match bound {
+                    Some(bound) => {
+                        assert_eq!(
+                            bound,
+                            argument_type,
+                            "{} called with {} = {} and {} = {}",
+                            callee,
+                            type_parameter,
+                            type_name(bound),
+                            type_parameter,
+                            type_name(argument_type)
+                        );
+                        Some(bound)
+                    }
+                    None => Some(argument_type),
+                })
+                .unwrap_or_else(|| panic!("Cannot infer {} in call to {}", type_parameter, callee))
+        })
+        .collect::<Vec<Type>>();
+    let resolve = |name: &String| resolve_type(name, &signature.type_parameters, &type_arguments);
+    let parameters = signature
+        .parameters
+        .iter()
+        .map(|parameter| parameter.as_ref().map(resolve))
+        .collect();
+    let result = signature.result.as_ref().map(resolve).unwrap_or(Type::I64);
+    (type_arguments, parameters, result)
+}
+
+fn expression_type(wasm_func: &Function, ast_func: &parser::Function, entity: usize) -> Type {
+    let index = ast_func.indices[entity];
+    let last = |expressions: &[usize]| match expressions.last() {
+        Some(&last) => expression_type(wasm_func, ast_func, last),
+        None => Type::I64,
+    };
+    match ast_func.kinds[entity] {
+        parser::Kind::Float => Type::F64,
+        parser::Kind::Symbol => wasm_func
+            .name_to_local
+            .get(&ast_func.symbols[index])
+            .map(|&local| wasm_func.types[local])
+            .unwrap_or(Type::I64),
+        parser::Kind::BinaryOp => match ast_func.binary_ops.ops[index] {
+            parser::BinaryOp::Equal
+            | parser::BinaryOp::NotEqual
+            | parser::BinaryOp::LessThan
+            | parser::BinaryOp::LessThanEqual
+            | parser::BinaryOp::GreaterThan
+            | parser::BinaryOp::GreaterThanEqual => Type::I32,
+            _ => expression_type(wasm_func, ast_func, ast_func.binary_ops.lefts[index]),
+        },
+        parser::Kind::FunctionCall => call_signature(wasm_func, ast_func, entity).2,
+        parser::Kind::If => last(&ast_func.ifs.then_branches[index]),
+        parser::Kind::Match => last(&ast_func.matches.bodies[index][0]),
+        parser::Kind::Grouping => expression_type(wasm_func, ast_func, ast_func.groupings[index]),
+        _ => Type::I64,
+    }
+}
+
 fn codegen_binary_op(
     tx: Sender<Message>,
     wasm_func: Function,
@@ -84,38 +314,105 @@ fn codegen_binary_op(
     entity: usize,
 ) -> Function {
     let index = ast_func.indices[entity];
-    let wasm_func = codegen_expression(
-        tx.clone(),
-        wasm_func,
-        ast_func,
-        ast_func.binary_ops.lefts[index],
+    let op = ast_func.binary_ops.ops[index];
+    let left = ast_func.binary_ops.lefts[index];
+    let right = ast_func.binary_ops.rights[index];
+    let left_type = expression_type(&wasm_func, ast_func, left);
+    let right_type = expression_type(&wasm_func, ast_func, right);
+    assert!(
+        compatible(left_type, right_type),
+        "Cannot apply {:?} to {} and {} in {}",
+        op,
+        type_name(left_type),
+        type_name(right_type),
+        ast_func.symbols[ast_func.name]
     );
-    let mut wasm_func =
-        codegen_expression(tx, wasm_func, ast_func, ast_func.binary_ops.rights[index]);
-    let instruction = match ast_func.binary_ops.ops[index] {
-        parser::BinaryOp::Add => Instruction::I64Add,
-        parser::BinaryOp::Subtract => Instruction::I64Sub,
-        parser::BinaryOp::Multiply => Instruction::I64Mul,
-        parser::BinaryOp::Divide => Instruction::I64DivS,
-        parser::BinaryOp::Modulo => Instruction::I64RemS,
-        parser::BinaryOp::ShiftLeft => Instruction::I64Shl,
-        parser::BinaryOp::ShiftRight => Instruction::I64ShrS,
-        parser::BinaryOp::BitwiseAnd => Instruction::I64And,
-        parser::BinaryOp::BitwiseXor => Instruction::I64Xor,
-        parser::BinaryOp::BitwiseOr => Instruction::I64Or,
-        parser::BinaryOp::Equal => Instruction::I64Eq,
-        parser::BinaryOp::NotEqual => Instruction::I64Neq,
-        parser::BinaryOp::LessThan => Instruction::I64LtS,
-        parser::BinaryOp::LessThanEqual => Instruction::I64LeS,
-        parser::BinaryOp::GreaterThan => Instruction::I64GtS,
-        parser::BinaryOp::GreaterThanEqual => Instruction::I64GeS,
+    let wasm_func = codegen_expression(tx.clone(), wasm_func, ast_func, left);
+    let mut wasm_func = codegen_expression(tx, wasm_func, ast_func, right);
+    let instruction = match (left_type, op) {
+        (Type::F64, parser::BinaryOp::Add) => Instruction::F64Add,
+        (Type::F64, parser::BinaryOp::Subtract) => Instruction::F64Sub,
+        (Type::F64, parser::BinaryOp::Multiply) => Instruction::F64Mul,
+        (Type::F64, parser::BinaryOp::Divide) => Instruction::F64Div,
+        (Type::F64, parser::BinaryOp::Equal) => Instruction::F64Eq,
+        (Type::F64, parser::BinaryOp::NotEqual) => Instruction::F64Ne,
+        (Type::F64, parser::BinaryOp::LessThan) => Instruction::F64Lt,
+        (Type::F64, parser::BinaryOp::LessThanEqual) => Instruction::F64Le,
+        (Type::F64, parser::BinaryOp::GreaterThan) => Instruction::F64Gt,
+        (Type::F64, parser::BinaryOp::GreaterThanEqual) => Instruction::F64Ge,
+        (Type::F64, op) => panic!("{:?} is not supported for f64", op),
+        (_, op) => i64_instruction(op),
+    };
+    wasm_func.instructions.push(instruction);
+    wasm_func.operand_kinds.push(vec![]);
+    wasm_func.operands.push(vec![]);
+    wasm_func
+}
+
+pub(crate) fn local(mut wasm_func: Function, name: &str, value_type: Type) -> (Function, usize) {
+    let local = match wasm_func.name_to_local.entry(name.to_string()) {
+        Entry::Occupied(entry) => {
+            let local = *entry.get();
+            assert_eq!(
+                wasm_func.types[local],
+                value_type,
+                "{} is {}, cannot assign {}",
+                name,
+                type_name(wasm_func.types[local]),
+                type_name(value_type)
+            );
+            local
+        }
+        Entry::Vacant(entry) => {
+            let local = *entry.insert(wasm_func.locals.len());
+            wasm_func.locals.push(format!("${}", name));
+            wasm_func.types.push(value_type);
+            local
+        }
     };
+    (wasm_func, local)
+}
+
+pub(crate) fn set_local(mut wasm_func: Function, local: usize) -> Function {
+    wasm_func.instructions.push(Instruction::SetLocal);
+    wasm_func.operand_kinds.push(vec![OperandKind::Local]);
+    wasm_func.operands.push(vec![local]);
+    wasm_func
+}
+
+pub(crate) fn get_local(mut wasm_func: Function, local: usize) -> Function {
+    wasm_func.instructions.push(Instruction::GetLocal);
+    wasm_func.operand_kinds.push(vec![OperandKind::Local]);
+    wasm_func.operands.push(vec![local]);
+    wasm_func
+}
+
+pub(crate) fn i64_const(mut wasm_func: Function, value: i64) -> Function {
+    wasm_func.instructions.push(Instruction::I64Const);
+    wasm_func.operand_kinds.push(vec![OperandKind::IntLiteral]);
+    wasm_func.operands.push(vec![wasm_func.ints.len()]);
+    wasm_func.ints.push(value.to_string());
+    wasm_func
+}
+
+pub(crate) fn instruction(mut wasm_func: Function, instruction: Instruction) -> Function {
     wasm_func.instructions.push(instruction);
     wasm_func.operand_kinds.push(vec![]);
     wasm_func.operands.push(vec![]);
     wasm_func
 }
 
+pub(crate) fn labelled(
+    mut wasm_func: Function,
+    instruction: Instruction,
+    label: usize,
+) -> Function {
+    wasm_func.instructions.push(instruction);
+    wasm_func.operand_kinds.push(vec![OperandKind::Label]);
+    wasm_func.operands.push(vec![label]);
+    wasm_func
+}
+
 fn codegen_assignment(
     tx: Sender<Message>,
     wasm_func: Function,
@@ -125,33 +422,87 @@ fn codegen_assignment(
     let index = ast_func.indices[entity];
     let name_index = ast_func.assignments.names[index];
     assert_eq!(ast_func.kinds[name_index], parser::Kind::Symbol);
-    let mut wasm_func =
-        codegen_expression(tx, wasm_func, ast_func, ast_func.assignments.values[index]);
+    let value = ast_func.assignments.values[index];
+    let value_type = widen(expression_type(&wasm_func, ast_func, value));
+    let mut wasm_func = codegen_expression(tx, wasm_func, ast_func, value);
     let name = &ast_func.symbols[ast_func.indices[name_index]];
-    let local = match wasm_func.name_to_local.entry(name.clone()) {
-        Entry::Occupied(entry) => entry.get().clone(),
-        Entry::Vacant(entry) => {
-            let local = entry.insert(wasm_func.locals.len()).clone();
-            wasm_func.locals.push(format!("${}", name));
-            local
-        }
-    };
-    wasm_func.instructions.push(Instruction::SetLocal);
-    wasm_func.operand_kinds.push(vec![OperandKind::Local]);
-    wasm_func.operands.push(vec![local]);
-    wasm_func
+    if !wasm_func.name_to_local.contains_key(name) && wasm_func.globals.contains(name) {
+        wasm_func.instructions.push(Instruction::GlobalSet);
+        wasm_func.operand_kinds.push(vec![OperandKind::Symbol]);
+        wasm_func.operands.push(vec![ast_func.indices[name_index]]);
+        return wasm_func;
+    }
+    let (wasm_func, local) = local(wasm_func, name, value_type);
+    set_local(wasm_func, local)
 }
 
-fn codegen_symbol(mut wasm_func: Function, ast_func: &parser::Function, entity: usize) -> Function {
+fn codegen_symbol(
+    tx: Sender<Message>,
+    mut wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
     assert_eq!(ast_func.kinds[entity], parser::Kind::Symbol);
     let index = ast_func.indices[entity];
-    let local = wasm_func
-        .name_to_local
-        .get(&ast_func.symbols[index])
-        .unwrap();
-    wasm_func.instructions.push(Instruction::GetLocal);
-    wasm_func.operand_kinds.push(vec![OperandKind::Local]);
-    wasm_func.operands.push(vec![*local]);
+    let name = &ast_func.symbols[index];
+    match wasm_func.name_to_local.get(name) {
+        Some(&local) => {
+            wasm_func.instructions.push(Instruction::GetLocal);
+            wasm_func.operand_kinds.push(vec![OperandKind::Local]);
+            wasm_func.operands.push(vec![local]);
+        }
+        None if wasm_func.variants.contains_key(name) => {
+            return codegen_variant(tx, wasm_func, ast_func, name, &[]);
+        }
+        None if wasm_func.functions.contains_key(name) => {
+            return codegen_function_value(tx, wasm_func, ast_func, name);
+        }
+        None => {
+            assert!(wasm_func.globals.contains(name), "Unknown name {}", name);
+            wasm_func.instructions.push(Instruction::GlobalGet);
+            wasm_func.operand_kinds.push(vec![OperandKind::Symbol]);
+            wasm_func.operands.push(vec![index]);
+        }
+    }
+    wasm_func
+}
+
+pub(crate) fn callee_name(ast_func: &parser::Function, function_call: usize) -> Option<&String> {
+    let name = ast_func.function_calls.names[function_call];
+    match ast_func.kinds[name] {
+        parser::Kind::Symbol => Some(&ast_func.symbols[ast_func.indices[name]]),
+        _ => None,
+    }
+}
+
+fn codegen_indirect_call(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    closure: usize,
+    parameters: &[usize],
+) -> Function {
+    let wasm_func = get_local(wasm_func, closure);
+    let wasm_func = parameters.iter().fold(wasm_func, |wasm_func, &parameter| {
+        let argument_type = expression_type(&wasm_func, ast_func, parameter);
+        assert_eq!(
+            argument_type,
+            Type::I64,
+            "Closures can only take i64 arguments, found {} in {}",
+            type_name(argument_type),
+            ast_func.symbols[ast_func.name]
+        );
+        codegen_expression(tx.clone(), wasm_func, ast_func, parameter)
+    });
+    let wasm_func = get_local(wasm_func, closure);
+    let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+    let mut wasm_func = memory(wasm_func, Instruction::I64Load, 0);
+    wasm_func.instructions.push(Instruction::I32WrapI64);
+    wasm_func.operand_kinds.push(vec![]);
+    wasm_func.operands.push(vec![]);
+    wasm_func.instructions.push(Instruction::CallIndirect);
+    wasm_func.operand_kinds.push(vec![OperandKind::Type]);
+    wasm_func.operands.push(vec![parameters.len() + 1]);
     wasm_func
 }
 
@@ -163,52 +514,123 @@ fn codegen_function_call(
 ) -> Function {
     assert_eq!(ast_func.kinds[entity], parser::Kind::FunctionCall);
     let function_call = ast_func.indices[entity];
-    let name = ast_func.function_calls.names[function_call];
-    assert_eq!(ast_func.kinds[name], parser::Kind::Symbol);
-    let mut wasm_func = ast_func.function_calls.parameters[function_call]
-        .iter()
-        .fold(wasm_func, |wasm_func, &parameter| {
-            codegen_expression(tx.clone(), wasm_func, ast_func, parameter)
-        });
+    let parameters = &ast_func.function_calls.parameters[function_call];
+    let callee = match callee_name(ast_func, function_call) {
+        Some(callee) => callee,
+        None => {
+            let name = ast_func.function_calls.names[function_call];
+            let wasm_func = codegen_expression(tx.clone(), wasm_func, ast_func, name);
+            let local_name = format!(".callee.{}", wasm_func.locals.len());
+            let (wasm_func, closure) = local(wasm_func, &local_name, Type::I64);
+            let wasm_func = set_local(wasm_func, closure);
+            return codegen_indirect_call(tx, wasm_func, ast_func, closure, parameters);
+        }
+    };
+    if let Some(&closure) = wasm_func.name_to_local.get(callee) {
+        return codegen_indirect_call(tx, wasm_func, ast_func, closure, parameters);
+    }
+    if wasm_func.variants.contains_key(callee) {
+        let fields = &ast_func.function_calls.parameters[function_call];
+        return codegen_variant(tx, wasm_func, ast_func, callee, fields);
+    }
+    let (type_arguments, parameter_types, _) = call_signature(&wasm_func, ast_func, entity);
+    let parameters = &ast_func.function_calls.parameters[function_call];
+    for (&parameter, parameter_type) in parameters.iter().zip(parameter_types) {
+        let argument_type = expression_type(&wasm_func, ast_func, parameter);
+        let parameter_type = parameter_type.unwrap_or(Type::I64);
+        assert!(
+            compatible(parameter_type, argument_type),
+            "{} expects {}, found {}",
+            callee,
+            type_name(parameter_type),
+            type_name(argument_type)
+        );
+    }
+    if is_tail_call(&wasm_func, ast_func, entity) {
+        return codegen_tail_call(tx, wasm_func, ast_func, entity);
+    }
+    let wasm_func = parameters.iter().fold(wasm_func, |wasm_func, &parameter| {
+        codegen_expression(tx.clone(), wasm_func, ast_func, parameter)
+    });
+    let (mut wasm_func, instance) = symbol(wasm_func, &instance_name(callee, &type_arguments));
     wasm_func.instructions.push(Instruction::Call);
     wasm_func.operand_kinds.push(vec![OperandKind::Symbol]);
-    wasm_func.operands.push(vec![ast_func.indices[name]]);
-    tx.send(Message::Spawn(
-        ast_func.symbols[ast_func.indices[name]].clone(),
-    ))
-    .unwrap();
+    wasm_func.operands.push(vec![instance]);
+    tx.send(Message::Spawn(callee.clone(), type_arguments))
+        .unwrap();
     wasm_func
 }
 
+fn if_instruction(mut wasm_func: Function, result: Option<Type>) -> Function {
+    wasm_func.instructions.push(Instruction::If);
+    match result {
+        Some(result) => {
+            wasm_func.operand_kinds.push(vec![OperandKind::Result]);
+            wasm_func.operands.push(vec![result as usize]);
+        }
+        None => {
+            wasm_func.operand_kinds.push(vec![]);
+            wasm_func.operands.push(vec![]);
+        }
+    }
+    wasm_func
+}
+
+fn codegen_condition(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    let condition_type = expression_type(&wasm_func, ast_func, entity);
+    let wasm_func = codegen_expression(tx, wasm_func, ast_func, entity);
+    match condition_type {
+        Type::I64 => {
+            let wasm_func = i64_const(wasm_func, 0);
+            instruction(wasm_func, Instruction::I64Neq)
+        }
+        _ => wasm_func,
+    }
+}
+
+pub(crate) fn check_if(ast_func: &parser::Function, index: usize, value: bool) {
+    assert!(
+        !value || !ast_func.ifs.else_branches[index].is_empty(),
+        "if without else used as a value in {}, add an else branch so it produces a value on every path",
+        ast_func.symbols[ast_func.name]
+    );
+}
+
 fn codegen_if(
     tx: Sender<Message>,
     wasm_func: Function,
     ast_func: &parser::Function,
     entity: usize,
+    result: Option<Type>,
 ) -> Function {
     let index = ast_func.indices[entity];
-    let mut wasm_func = codegen_expression(
+    check_if(ast_func, index, result.is_some());
+    let else_branch = &ast_func.ifs.else_branches[index];
+    let wasm_func = codegen_condition(
         tx.clone(),
         wasm_func,
         ast_func,
         ast_func.ifs.conditionals[index],
     );
-    wasm_func.instructions.push(Instruction::If);
-    wasm_func.operand_kinds.push(vec![]);
-    wasm_func.operands.push(vec![]);
-    let mut wasm_func = ast_func.ifs.then_branches[index]
-        .iter()
-        .fold(wasm_func, |wasm_func, &expression| {
-            codegen_expression(tx.clone(), wasm_func, ast_func, expression)
-        });
-    wasm_func.instructions.push(Instruction::Else);
-    wasm_func.operand_kinds.push(vec![]);
-    wasm_func.operands.push(vec![]);
-    let mut wasm_func = ast_func.ifs.else_branches[index]
-        .iter()
-        .fold(wasm_func, |wasm_func, &expression| {
-            codegen_expression(tx.clone(), wasm_func, ast_func, expression)
-        });
+    let wasm_func = if_instruction(wasm_func, result);
+    let mut wasm_func = codegen_block(
+        tx.clone(),
+        wasm_func,
+        ast_func,
+        &ast_func.ifs.then_branches[index],
+        result.is_some(),
+    );
+    if !else_branch.is_empty() {
+        wasm_func.instructions.push(Instruction::Else);
+        wasm_func.operand_kinds.push(vec![]);
+        wasm_func.operands.push(vec![]);
+    }
+    let mut wasm_func = codegen_block(tx, wasm_func, ast_func, else_branch, result.is_some());
     wasm_func.instructions.push(Instruction::End);
     wasm_func.operand_kinds.push(vec![]);
     wasm_func.operands.push(vec![]);
@@ -231,7 +653,7 @@ fn codegen_while(
     wasm_func.instructions.push(Instruction::Loop);
     wasm_func.operand_kinds.push(vec![OperandKind::Label]);
     wasm_func.operands.push(vec![loop_label]);
-    let mut wasm_func = codegen_expression(
+    let mut wasm_func = codegen_condition(
         tx.clone(),
         wasm_func,
         ast_func,
@@ -243,11 +665,15 @@ fn codegen_while(
     wasm_func.instructions.push(Instruction::BrIf);
     wasm_func.operand_kinds.push(vec![OperandKind::Label]);
     wasm_func.operands.push(vec![block_label]);
-    let mut wasm_func = ast_func.whiles.bodies[index]
-        .iter()
-        .fold(wasm_func, |wasm_func, &expression| {
-            codegen_expression(tx.clone(), wasm_func, ast_func, expression)
-        });
+    wasm_func.loops.push(block_label);
+    let mut wasm_func = codegen_block(
+        tx,
+        wasm_func,
+        ast_func,
+        &ast_func.whiles.bodies[index],
+        false,
+    );
+    wasm_func.loops.pop();
     wasm_func.instructions.push(Instruction::Br);
     wasm_func.operand_kinds.push(vec![OperandKind::Label]);
     wasm_func.operands.push(vec![loop_label]);
@@ -260,86 +686,1609 @@ fn codegen_while(
     wasm_func
 }
 
-fn codegen_grouping(
+const HEAP: &str = ".heap";
+
+pub(crate) fn symbol(mut wasm_func: Function, name: &str) -> (Function, usize) {
+    match wasm_func.symbols.iter().position(|symbol| symbol == name) {
+        Some(symbol) => (wasm_func, symbol),
+        None => {
+            wasm_func.symbols.push(name.to_string());
+            let symbol = wasm_func.symbols.len() - 1;
+            (wasm_func, symbol)
+        }
+    }
+}
+
+fn heap(wasm_func: Function, instruction: Instruction) -> Function {
+    let (mut wasm_func, symbol) = symbol(wasm_func, HEAP);
+    wasm_func.memory = true;
+    wasm_func.instructions.push(instruction);
+    wasm_func.operand_kinds.push(vec![OperandKind::Symbol]);
+    wasm_func.operands.push(vec![symbol]);
+    wasm_func
+}
+
+fn memory(mut wasm_func: Function, instruction: Instruction, offset: usize) -> Function {
+    wasm_func.memory = true;
+    wasm_func.instructions.push(instruction);
+    if offset > 0 {
+        wasm_func.operand_kinds.push(vec![OperandKind::Offset]);
+        wasm_func.operands.push(vec![offset]);
+    } else {
+        wasm_func.operand_kinds.push(vec![]);
+        wasm_func.operands.push(vec![]);
+    }
+    wasm_func
+}
+
+const ALLOCATE: &str = ".allocate";
+
+const PAGE_BITS: i64 = 16;
+
+fn memory_bytes(wasm_func: Function) -> Function {
+    let wasm_func = instruction(wasm_func, Instruction::MemorySize);
+    let wasm_func = instruction(wasm_func, Instruction::I64ExtendI32U);
+    let wasm_func = i64_const(wasm_func, PAGE_BITS);
+    instruction(wasm_func, Instruction::I64Shl)
+}
+
+fn allocator(wasm_func: &Function) -> Function {
+    let allocator = Function {
+        locals: vec![String::from("$.size")],
+        ..closure_function(wasm_func, ALLOCATE, &[])
+    };
+    let (allocator, object) = local(allocator, ".object", Type::I64);
+    let allocator = heap(allocator, Instruction::GlobalGet);
+    let allocator = set_local(allocator, object);
+    let allocator = heap(allocator, Instruction::GlobalGet);
+    let allocator = get_local(allocator, 0);
+    let allocator = instruction(allocator, Instruction::I64Add);
+    let allocator = heap(allocator, Instruction::GlobalSet);
+    let allocator = heap(allocator, Instruction::GlobalGet);
+    let allocator = memory_bytes(allocator);
+    let allocator = instruction(allocator, Instruction::I64GtS);
+    let allocator = if_instruction(allocator, None);
+    let allocator = heap(allocator, Instruction::GlobalGet);
+    let allocator = memory_bytes(allocator);
+    let allocator = instruction(allocator, Instruction::I64Sub);
+    let allocator = i64_const(allocator, (1 << PAGE_BITS) - 1);
+    let allocator = instruction(allocator, Instruction::I64Add);
+    let allocator = i64_const(allocator, PAGE_BITS);
+    let allocator = instruction(allocator, Instruction::I64ShrS);
+    let allocator = instruction(allocator, Instruction::I32WrapI64);
+    let allocator = instruction(allocator, Instruction::MemoryGrow);
+    let allocator = instruction(allocator, Instruction::I64ExtendI32U);
+    let allocator = i64_const(allocator, u32::MAX as i64);
+    let allocator = instruction(allocator, Instruction::I64Eq);
+    let allocator = if_instruction(allocator, None);
+    let allocator = instruction(allocator, Instruction::Unreachable);
+    let allocator = instruction(allocator, Instruction::End);
+    let allocator = instruction(allocator, Instruction::End);
+    get_local(allocator, object)
+}
+
+fn allocate(
     tx: Sender<Message>,
     wasm_func: Function,
     ast_func: &parser::Function,
-    entity: usize,
+    kind: &str,
+    header: impl FnOnce(Function) -> Function,
+    values: &[usize],
 ) -> Function {
-    let index = ast_func.indices[entity];
-    codegen_expression(tx.clone(), wasm_func, ast_func, ast_func.groupings[index])
+    tx.send(Message::Closure(
+        ALLOCATE.to_string(),
+        Box::new(allocator(&wasm_func)),
+    ))
+    .unwrap();
+    let name = format!(".{}.{}", kind, wasm_func.locals.len());
+    let (wasm_func, object) = local(wasm_func, &name, Type::I64);
+    let wasm_func = i64_const(wasm_func, 8 * (values.len() as i64 + 1));
+    let (mut wasm_func, callee) = symbol(wasm_func, ALLOCATE);
+    wasm_func.memory = true;
+    wasm_func.instructions.push(Instruction::Call);
+    wasm_func.operand_kinds.push(vec![OperandKind::Symbol]);
+    wasm_func.operands.push(vec![callee]);
+    let wasm_func = set_local(wasm_func, object);
+    let wasm_func = get_local(wasm_func, object);
+    let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+    let wasm_func = header(wasm_func);
+    let wasm_func = memory(wasm_func, Instruction::I64Store, 0);
+    let wasm_func = values
+        .iter()
+        .enumerate()
+        .fold(wasm_func, |wasm_func, (i, &value)| {
+            let value_type = expression_type(&wasm_func, ast_func, value);
+            assert!(
+                compatible(value_type, Type::I64),
+                "Only i64 values can be stored in a {}, found {}",
+                kind,
+                type_name(value_type)
+            );
+            let wasm_func = get_local(wasm_func, object);
+            let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+            let wasm_func = codegen_expression(tx.clone(), wasm_func, ast_func, value);
+            memory(wasm_func, Instruction::I64Store, 8 * (i + 1))
+        });
+    get_local(wasm_func, object)
 }
 
-fn codegen_expression(
+fn codegen_array(
     tx: Sender<Message>,
     wasm_func: Function,
     ast_func: &parser::Function,
     entity: usize,
 ) -> Function {
-    match ast_func.kinds[entity] {
-        parser::Kind::Int => codegen_int(wasm_func, ast_func, entity),
-        parser::Kind::BinaryOp => codegen_binary_op(tx, wasm_func, ast_func, entity),
-        parser::Kind::Assign => codegen_assignment(tx, wasm_func, ast_func, entity),
-        parser::Kind::Symbol => codegen_symbol(wasm_func, ast_func, entity),
-        parser::Kind::FunctionCall => codegen_function_call(tx, wasm_func, ast_func, entity),
-        parser::Kind::If => codegen_if(tx, wasm_func, ast_func, entity),
-        parser::Kind::While => codegen_while(tx, wasm_func, ast_func, entity),
-        parser::Kind::Grouping => codegen_grouping(tx, wasm_func, ast_func, entity),
-    }
+    let elements = &ast_func.arrays[ast_func.indices[entity]];
+    let length = elements.len() as i64;
+    allocate(
+        tx,
+        wasm_func,
+        ast_func,
+        "array",
+        |wasm_func| i64_const(wasm_func, length),
+        elements,
+    )
 }
 
-fn codegen_function(tx: Sender<Message>, ast_func: &parser::Function) -> Function {
-    let locals = ast_func
-        .arguments
-        .iter()
-        .map(|&argument| format!("${}", ast_func.symbols[argument].clone()))
+fn codegen_variant(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    name: &str,
+    fields: &[usize],
+) -> Function {
+    let variant = wasm_func.variants[name];
+    assert_eq!(
+        variant.fields,
+        fields.len(),
+        "{} expects {} fields, found {}",
+        name,
+        variant.fields,
+        fields.len()
+    );
+    allocate(
+        tx,
+        wasm_func,
+        ast_func,
+        "variant",
+        |wasm_func| i64_const(wasm_func, variant.tag as i64),
+        fields,
+    )
+}
+
+const ENV: &str = ".env";
+
+fn closure_function(wasm_func: &Function, name: &str, parameters: &[String]) -> Function {
+    let locals = std::iter::once(ENV)
+        .chain(parameters.iter().map(String::as_str))
+        .map(|parameter| format!("${}", parameter))
         .collect::<Vec<String>>();
-    let name_to_local = ast_func.arguments.iter().enumerate().fold(
-        HashMap::new(),
-        |mut name_to_local, (i, &argument)| {
-            name_to_local
-                .try_insert(ast_func.symbols[argument].clone(), i)
-                .unwrap();
-            name_to_local
+    let name_to_local =
+        parameters
+            .iter()
+            .enumerate()
+            .fold(HashMap::new(), |mut name_to_local, (i, parameter)| {
+                name_to_local.insert(parameter.clone(), i + 1);
+                name_to_local
+            });
+    let (closure, name) = symbol(
+        Function {
+            name: 0,
+            instructions: vec![],
+            operand_kinds: vec![],
+            operands: vec![],
+            types: locals.iter().map(|_| Type::I64).collect(),
+            locals,
+            name_to_local,
+            globals: wasm_func.globals.clone(),
+            variants: wasm_func.variants.clone(),
+            functions: wasm_func.functions.clone(),
+            symbols: wasm_func.symbols.clone(),
+            ints: wasm_func.ints.clone(),
+            floats: wasm_func.floats.clone(),
+            arguments: parameters.len() + 1,
+            result: Type::I64,
+            next_label: 0,
+            loops: vec![],
+            tail_calls: HashSet::new(),
+            tail_loop: None,
+            inline: None,
+            memory: false,
         },
+        name,
     );
-    let wasm_func = Function {
-        name: ast_func.name,
-        instructions: vec![],
-        operand_kinds: vec![],
-        operands: vec![],
-        locals,
-        name_to_local,
-        symbols: vec![],
-        ints: vec![],
-        arguments: ast_func.arguments.len(),
-        next_label: 0,
-    };
-    let mut wasm_func = ast_func
-        .expressions
+    Function { name, ..closure }
+}
+
+fn function_reference(wasm_func: Function, name: &str) -> Function {
+    let (mut wasm_func, symbol) = symbol(wasm_func, name);
+    wasm_func.instructions.push(Instruction::I64Const);
+    wasm_func.operand_kinds.push(vec![OperandKind::Function]);
+    wasm_func.operands.push(vec![symbol]);
+    wasm_func
+}
+
+fn codegen_function_value(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    name: &str,
+) -> Function {
+    let signature = &wasm_func.functions[name];
+    assert!(
+        signature.type_parameters.is_empty(),
+        "Generic function {} cannot be used as a value",
+        name
+    );
+    assert!(
+        signature
+            .parameters
+            .iter()
+            .chain(std::iter::once(&signature.result))
+            .all(|value_type| value_type.as_deref().unwrap_or("i64") == "i64"),
+        "Only functions over i64 can be used as values, {} is not",
+        name
+    );
+    let closure = format!("{}.closure", name);
+    let parameters = (0..signature.parameters.len())
+        .map(|i| format!(".argument.{}", i))
+        .collect::<Vec<String>>();
+    let trampoline = closure_function(&wasm_func, &closure, &parameters);
+    let trampoline = (1..=parameters.len()).fold(trampoline, get_local);
+    let (mut trampoline, callee) = symbol(trampoline, name);
+    trampoline.instructions.push(Instruction::Call);
+    trampoline.operand_kinds.push(vec![OperandKind::Symbol]);
+    trampoline.operands.push(vec![callee]);
+    tx.send(Message::Spawn(name.to_string(), vec![])).unwrap();
+    tx.send(Message::Closure(closure.clone(), Box::new(trampoline)))
+        .unwrap();
+    allocate(
+        tx,
+        wasm_func,
+        ast_func,
+        "closure",
+        |wasm_func| function_reference(wasm_func, &closure),
+        &[],
+    )
+}
+
+fn codegen_lambda(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    let index = ast_func.indices[entity];
+    let parameters = ast_func.lambdas.parameters[index]
+        .iter()
+        .map(|&parameter| ast_func.symbols[parameter].clone())
+        .collect::<Vec<String>>();
+    let name_of = |entity: usize| &ast_func.symbols[ast_func.indices[entity]];
+    let captures = (ast_func.lambdas.starts[index]..entity)
+        .filter(|&entity| ast_func.kinds[entity] == parser::Kind::Symbol)
+        .fold(vec![], |mut captures: Vec<usize>, entity| {
+            let name = name_of(entity);
+            let captured = captures.iter().any(|&capture| name_of(capture) == name);
+            if !captured && !parameters.contains(name) && wasm_func.name_to_local.contains_key(name)
+            {
+                captures.push(entity);
+            }
+            captures
+        });
+    let name = format!("{}.lambda.{}", ast_func.symbols[ast_func.name], index);
+    let lambda = closure_function(&wasm_func, &name, &parameters);
+    let lambda = captures
+        .iter()
+        .enumerate()
+        .fold(lambda, |lambda, (i, &capture)| {
+            let name = name_of(capture);
+            let value_type = wasm_func.types[wasm_func.name_to_local[name]];
+            assert_eq!(
+                value_type,
+                Type::I64,
+                "Closures can only capture i64 values, {} is {}",
+                name,
+                type_name(value_type)
+            );
+            let (lambda, local) = local(lambda, name, Type::I64);
+            let lambda = get_local(lambda, 0);
+            let lambda = instruction(lambda, Instruction::I32WrapI64);
+            let lambda = memory(lambda, Instruction::I64Load, 8 * (i + 1));
+            set_local(lambda, local)
+        });
+    let lambda = codegen_block(
+        tx.clone(),
+        lambda,
+        ast_func,
+        &ast_func.lambdas.bodies[index],
+        true,
+    );
+    tx.send(Message::Closure(name.clone(), Box::new(lambda)))
+        .unwrap();
+    allocate(
+        tx,
+        wasm_func,
+        ast_func,
+        "closure",
+        |wasm_func| function_reference(wasm_func, &name),
+        &captures,
+    )
+}
+
+pub(crate) fn range_bounds(
+    ast_func: &parser::Function,
+    iterable: usize,
+) -> Option<(Option<usize>, usize)> {
+    if ast_func.kinds[iterable] != parser::Kind::FunctionCall {
+        return None;
+    }
+    let function_call = ast_func.indices[iterable];
+    if !matches!(callee_name(ast_func, function_call), Some(callee) if callee == "range") {
+        return None;
+    }
+    match ast_func.function_calls.parameters[function_call][..] {
+        [end] => Some((None, end)),
+        [start, end] => Some((Some(start), end)),
+        _ => panic!("range expects range(end) or range(start, end)"),
+    }
+}
+
+fn codegen_for(
+    tx: Sender<Message>,
+    mut wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    let index = ast_func.indices[entity];
+    let block_label = wasm_func.next_label;
+    let body_label = block_label + 1;
+    let loop_label = block_label + 2;
+    wasm_func.next_label += 3;
+    let variable = ast_func.fors.variables[index];
+    let iterable = ast_func.fors.iterables[index];
+    let variable = &ast_func.symbols[ast_func.indices[variable]];
+    let (wasm_func, variable) = local(wasm_func, variable, Type::I64);
+    let (wasm_func, counter, array) = match range_bounds(ast_func, iterable) {
+        Some((start, end)) => {
+            let wasm_func = match start {
+                Some(start) => codegen_expression(tx.clone(), wasm_func, ast_func, start),
+                None => i64_const(wasm_func, 0),
+            };
+            let wasm_func = set_local(wasm_func, variable);
+            let (wasm_func, bound) = local(wasm_func, &format!(".end.{}", block_label), Type::I64);
+            let wasm_func = codegen_expression(tx.clone(), wasm_func, ast_func, end);
+            let wasm_func = set_local(wasm_func, bound);
+            let wasm_func = labelled(wasm_func, Instruction::Block, block_label);
+            let wasm_func = labelled(wasm_func, Instruction::Loop, loop_label);
+            let wasm_func = get_local(wasm_func, variable);
+            let wasm_func = get_local(wasm_func, bound);
+            (wasm_func, variable, None)
+        }
+        None => {
+            let (wasm_func, array) =
+                local(wasm_func, &format!(".array.{}", block_label), Type::I64);
+            let wasm_func = codegen_expression(tx.clone(), wasm_func, ast_func, iterable);
+            let wasm_func = set_local(wasm_func, array);
+            let (wasm_func, counter) =
+                local(wasm_func, &format!(".index.{}", block_label), Type::I64);
+            let wasm_func = i64_const(wasm_func, 0);
+            let wasm_func = set_local(wasm_func, counter);
+            let wasm_func = labelled(wasm_func, Instruction::Block, block_label);
+            let wasm_func = labelled(wasm_func, Instruction::Loop, loop_label);
+            let wasm_func = get_local(wasm_func, counter);
+            let wasm_func = get_local(wasm_func, array);
+            let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+            let wasm_func = memory(wasm_func, Instruction::I64Load, 0);
+            (wasm_func, counter, Some(array))
+        }
+    };
+    let wasm_func = instruction(wasm_func, Instruction::I64LtS);
+    let wasm_func = instruction(wasm_func, Instruction::I32Eqz);
+    let wasm_func = labelled(wasm_func, Instruction::BrIf, block_label);
+    let wasm_func = match array {
+        Some(array) => {
+            let wasm_func = get_local(wasm_func, array);
+            let wasm_func = get_local(wasm_func, counter);
+            let wasm_func = i64_const(wasm_func, 8);
+            let wasm_func = instruction(wasm_func, Instruction::I64Mul);
+            let wasm_func = instruction(wasm_func, Instruction::I64Add);
+            let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+            let wasm_func = memory(wasm_func, Instruction::I64Load, 8);
+            set_local(wasm_func, variable)
+        }
+        None => wasm_func,
+    };
+    let mut wasm_func = labelled(wasm_func, Instruction::Block, body_label);
+    wasm_func.loops.push(block_label);
+    let mut wasm_func = codegen_block(tx, wasm_func, ast_func, &ast_func.fors.bodies[index], false);
+    wasm_func.loops.pop();
+    let wasm_func = labelled(wasm_func, Instruction::End, body_label);
+    let wasm_func = get_local(wasm_func, counter);
+    let wasm_func = i64_const(wasm_func, 1);
+    let wasm_func = instruction(wasm_func, Instruction::I64Add);
+    let wasm_func = set_local(wasm_func, counter);
+    let wasm_func = labelled(wasm_func, Instruction::Br, loop_label);
+    let wasm_func = labelled(wasm_func, Instruction::End, loop_label);
+    labelled(wasm_func, Instruction::End, block_label)
+}
+
+const MIN_TABLE_CASES: usize = 3;
+
+fn int_literal(mut wasm_func: Function, int: usize) -> Function {
+    wasm_func.instructions.push(Instruction::I64Const);
+    wasm_func.operand_kinds.push(vec![OperandKind::IntLiteral]);
+    wasm_func.operands.push(vec![int]);
+    wasm_func
+}
+
+fn pattern_bounds(ast_func: &parser::Function, pattern: Pattern) -> Option<(i64, i64)> {
+    let int = |index: usize| ast_func.ints[index].parse::<i64>().unwrap();
+    match pattern {
+        Pattern::Int(value) => Some((int(value), int(value) + 1)),
+        Pattern::Range(low, high) => Some((int(low), int(high))),
+        Pattern::Wildcard | Pattern::Variant(_) => None,
+    }
+}
+
+fn table_cases(ast_func: &parser::Function, index: usize) -> Option<(i64, Vec<usize>)> {
+    let arms = ast_func.matches.patterns[index].len() - 1;
+    let guarded = ast_func.matches.guards[index][..arms]
+        .iter()
+        .any(|guard| guard.is_some());
+    if arms < MIN_TABLE_CASES || guarded {
+        return None;
+    }
+    let bounds = ast_func.matches.patterns[index][..arms]
+        .iter()
+        .map(|&pattern| pattern_bounds(ast_func, pattern))
+        .collect::<Option<Vec<(i64, i64)>>>()?;
+    let low = bounds.iter().map(|&(low, _)| low).min()?;
+    let high = bounds.iter().map(|&(_, high)| high).max()?;
+    let cases = bounds
+        .iter()
+        .map(|&(low, high)| (high - low).max(0))
+        .sum::<i64>();
+    if high - low > 2 * cases {
+        return None;
+    }
+    let targets = (low..high)
+        .map(|value| {
+            bounds
+                .iter()
+                .position(|&(low, high)| low <= value && value < high)
+                .unwrap_or(arms)
+        })
+        .collect();
+    Some((low, targets))
+}
+
+fn codegen_match_test(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    pattern: Pattern,
+    guard: Option<usize>,
+    value: usize,
+) -> Function {
+    let wasm_func = match pattern {
+        Pattern::Int(int) => {
+            let wasm_func = get_local(wasm_func, value);
+            let wasm_func = int_literal(wasm_func, int);
+            instruction(wasm_func, Instruction::I64Eq)
+        }
+        Pattern::Range(low, high) => {
+            let wasm_func = get_local(wasm_func, value);
+            let wasm_func = int_literal(wasm_func, low);
+            let wasm_func = instruction(wasm_func, Instruction::I64GeS);
+            let wasm_func = get_local(wasm_func, value);
+            let wasm_func = int_literal(wasm_func, high);
+            let wasm_func = instruction(wasm_func, Instruction::I64LtS);
+            instruction(wasm_func, Instruction::I32And)
+        }
+        Pattern::Variant(symbol) => {
+            let tag = wasm_func.variants[&ast_func.symbols[symbol]].tag;
+            let wasm_func = get_local(wasm_func, value);
+            let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+            let wasm_func = memory(wasm_func, Instruction::I64Load, 0);
+            let wasm_func = i64_const(wasm_func, tag as i64);
+            instruction(wasm_func, Instruction::I64Eq)
+        }
+        Pattern::Wildcard => wasm_func,
+    };
+    match (pattern, guard) {
+        (_, None) => wasm_func,
+        (Pattern::Wildcard, Some(guard)) => codegen_condition(tx, wasm_func, ast_func, guard),
+        (_, Some(guard)) => {
+            let wasm_func = codegen_condition(tx, wasm_func, ast_func, guard);
+            instruction(wasm_func, Instruction::I32And)
+        }
+    }
+}
+
+fn codegen_bindings(
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    index: usize,
+    arm: usize,
+    value: usize,
+    guarded: bool,
+) -> Function {
+    let symbol = match ast_func.matches.patterns[index][arm] {
+        Pattern::Variant(symbol) => symbol,
+        _ => return wasm_func,
+    };
+    let name = &ast_func.symbols[symbol];
+    let variant = wasm_func.variants[name];
+    let bindings = &ast_func.matches.bindings[index][arm];
+    assert_eq!(
+        variant.fields,
+        bindings.len(),
+        "{} pattern expects {} fields, found {}",
+        name,
+        variant.fields,
+        bindings.len()
+    );
+    bindings
+        .iter()
+        .enumerate()
+        .filter(|(_, &binding)| ast_func.symbols[binding] != "_")
+        .fold(wasm_func, |wasm_func, (i, &binding)| {
+            let (wasm_func, local) = local(wasm_func, &ast_func.symbols[binding], Type::I64);
+            let wasm_func = if guarded {
+                let wasm_func = get_local(wasm_func, value);
+                let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+                let wasm_func = memory(wasm_func, Instruction::I64Load, 0);
+                let wasm_func = i64_const(wasm_func, variant.tag as i64);
+                let wasm_func = instruction(wasm_func, Instruction::I64Eq);
+                let wasm_func = if_instruction(wasm_func, Some(Type::I64));
+                let wasm_func = get_local(wasm_func, value);
+                let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+                let wasm_func = memory(wasm_func, Instruction::I64Load, 8 * (i + 1));
+                let wasm_func = instruction(wasm_func, Instruction::Else);
+                let wasm_func = i64_const(wasm_func, 0);
+                instruction(wasm_func, Instruction::End)
+            } else {
+                let wasm_func = get_local(wasm_func, value);
+                let wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+                memory(wasm_func, Instruction::I64Load, 8 * (i + 1))
+            };
+            set_local(wasm_func, local)
+        })
+}
+
+fn codegen_match_arms(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    index: usize,
+    arm: usize,
+    value: usize,
+    result: Option<Type>,
+) -> Function {
+    let bodies = &ast_func.matches.bodies[index];
+    if arm + 1 == bodies.len() {
+        let wasm_func = codegen_bindings(wasm_func, ast_func, index, arm, value, false);
+        return codegen_block(tx, wasm_func, ast_func, &bodies[arm], result.is_some());
+    }
+    let guard = ast_func.matches.guards[index][arm];
+    let wasm_func = match guard {
+        Some(_) => codegen_bindings(wasm_func, ast_func, index, arm, value, true),
+        None => wasm_func,
+    };
+    let wasm_func = codegen_match_test(
+        tx.clone(),
+        wasm_func,
+        ast_func,
+        ast_func.matches.patterns[index][arm],
+        guard,
+        value,
+    );
+    let wasm_func = if_instruction(wasm_func, result);
+    let wasm_func = match guard {
+        Some(_) => wasm_func,
+        None => codegen_bindings(wasm_func, ast_func, index, arm, value, false),
+    };
+    let wasm_func = codegen_block(
+        tx.clone(),
+        wasm_func,
+        ast_func,
+        &bodies[arm],
+        result.is_some(),
+    );
+    let wasm_func = instruction(wasm_func, Instruction::Else);
+    let wasm_func = codegen_match_arms(tx, wasm_func, ast_func, index, arm + 1, value, result);
+    instruction(wasm_func, Instruction::End)
+}
+
+fn codegen_match_table(
+    tx: Sender<Message>,
+    mut wasm_func: Function,
+    ast_func: &parser::Function,
+    index: usize,
+    value: usize,
+    result: Option<Type>,
+    (low, targets): (i64, Vec<usize>),
+) -> Function {
+    let bodies = &ast_func.matches.bodies[index];
+    let arms = bodies.len() - 1;
+    let done_label = wasm_func.next_label;
+    let case_label = |arm: usize| done_label + 1 + arm;
+    wasm_func.next_label += 2 + arms;
+    wasm_func.instructions.push(Instruction::Block);
+    match result {
+        Some(result) => {
+            wasm_func
+                .operand_kinds
+                .push(vec![OperandKind::Label, OperandKind::Result]);
+            wasm_func.operands.push(vec![done_label, result as usize]);
+        }
+        None => {
+            wasm_func.operand_kinds.push(vec![OperandKind::Label]);
+            wasm_func.operands.push(vec![done_label]);
+        }
+    }
+    let wasm_func = (0..=arms).rev().fold(wasm_func, |wasm_func, arm| {
+        labelled(wasm_func, Instruction::Block, case_label(arm))
+    });
+    let wasm_func = get_local(wasm_func, value);
+    let wasm_func = i64_const(wasm_func, low);
+    let wasm_func = instruction(wasm_func, Instruction::I64Sub);
+    let wasm_func = set_local(wasm_func, value);
+    let wasm_func = get_local(wasm_func, value);
+    let wasm_func = i64_const(wasm_func, targets.len() as i64);
+    let wasm_func = instruction(wasm_func, Instruction::I64GeU);
+    let wasm_func = labelled(wasm_func, Instruction::BrIf, case_label(arms));
+    let wasm_func = get_local(wasm_func, value);
+    let mut wasm_func = instruction(wasm_func, Instruction::I32WrapI64);
+    wasm_func.instructions.push(Instruction::BrTable);
+    wasm_func
+        .operand_kinds
+        .push((0..=targets.len()).map(|_| OperandKind::Label).collect());
+    wasm_func.operands.push(
+        targets
+            .iter()
+            .chain(std::iter::once(&arms))
+            .map(|&arm| case_label(arm))
+            .collect(),
+    );
+    let wasm_func = (0..=arms).fold(wasm_func, |wasm_func, arm| {
+        let wasm_func = labelled(wasm_func, Instruction::End, case_label(arm));
+        let wasm_func = codegen_block(
+            tx.clone(),
+            wasm_func,
+            ast_func,
+            &bodies[arm],
+            result.is_some(),
+        );
+        if arm < arms {
+            labelled(wasm_func, Instruction::Br, done_label)
+        } else {
+            wasm_func
+        }
+    });
+    labelled(wasm_func, Instruction::End, done_label)
+}
+
+fn exhaustive_arm(
+    variants: &HashMap<String, Variant>,
+    ast_func: &parser::Function,
+    index: usize,
+) -> usize {
+    let name = &ast_func.symbols[ast_func.name];
+    let arms = ast_func.matches.patterns[index]
+        .iter()
+        .zip(ast_func.matches.guards[index].iter());
+    let (arm, enumeration, covered) = arms.enumerate().fold(
+        (None, None, HashSet::new()),
+        |(arm, enumeration, mut covered), (i, (&pattern, guard))| {
+            let variant = match pattern {
+                Pattern::Variant(symbol) => {
+                    let variant = &ast_func.symbols[symbol];
+                    Some(
+                        *variants
+                            .get(variant)
+                            .unwrap_or_else(|| panic!("Unknown variant {} in {}", variant, name)),
+                    )
+                }
+                _ => None,
+            };
+            let enumeration = match (enumeration, variant, pattern) {
+                (_, _, Pattern::Int(_)) | (_, _, Pattern::Range(_, _)) => {
+                    assert!(
+                        enumeration.is_none(),
+                        "match in {} mixes int and variant patterns",
+                        name
+                    );
+                    enumeration
+                }
+                (Some(enumeration), Some(variant), _) => {
+                    assert_eq!(
+                        enumeration, variant.enumeration,
+                        "match in {} mixes variants of different enums",
+                        name
+                    );
+                    Some(enumeration)
+                }
+                (None, Some(variant), _) => Some(variant.enumeration),
+                (enumeration, None, _) => enumeration,
+            };
+            let exhaustive = match (pattern, guard, variant) {
+                (Pattern::Wildcard, None, _) => true,
+                (Pattern::Variant(_), None, Some(variant)) => {
+                    covered.insert(variant.tag);
+                    covered.len() == variant.variants
+                }
+                _ => false,
+            };
+            match arm {
+                None if exhaustive => (Some(i), enumeration, covered),
+                arm => (arm, enumeration, covered),
+            }
+        },
+    );
+    arm.unwrap_or_else(|| match enumeration {
+        Some(enumeration) => {
+            let mut missing = variants
+                .iter()
+                .filter(|(_, variant)| {
+                    variant.enumeration == enumeration && !covered.contains(&variant.tag)
+                })
+                .map(|(name, variant)| (variant.tag, name.as_str()))
+                .collect::<Vec<(usize, &str)>>();
+            missing.sort();
+            let missing = missing
+                .iter()
+                .map(|(_, name)| *name)
+                .collect::<Vec<&str>>()
+                .join(", ");
+            panic!("match in {} is not exhaustive, missing {}", name, missing)
+        }
+        None => panic!("match in {} is not exhaustive, add a _ arm", name),
+    })
+}
+
+pub(crate) fn check_match(
+    variants: &HashMap<String, Variant>,
+    ast_func: &parser::Function,
+    index: usize,
+) {
+    let catch_all = exhaustive_arm(variants, ast_func, index);
+    assert_eq!(
+        catch_all + 1,
+        ast_func.matches.patterns[index].len(),
+        "match in {} has unreachable arms after arm {}",
+        ast_func.symbols[ast_func.name],
+        catch_all + 1
+    );
+}
+
+fn codegen_match(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+    result: Option<Type>,
+) -> Function {
+    let index = ast_func.indices[entity];
+    check_match(&wasm_func.variants, ast_func, index);
+    let local_name = format!(".match.{}", wasm_func.locals.len());
+    let (wasm_func, value) = local(wasm_func, &local_name, Type::I64);
+    let wasm_func = codegen_expression(
+        tx.clone(),
+        wasm_func,
+        ast_func,
+        ast_func.matches.values[index],
+    );
+    let wasm_func = set_local(wasm_func, value);
+    match table_cases(ast_func, index) {
+        Some(cases) => codegen_match_table(tx, wasm_func, ast_func, index, value, result, cases),
+        None => codegen_match_arms(tx, wasm_func, ast_func, index, 0, value, result),
+    }
+}
+
+fn codegen_grouping(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    let index = ast_func.indices[entity];
+    codegen_expression(tx.clone(), wasm_func, ast_func, ast_func.groupings[index])
+}
+
+fn codegen_break(mut wasm_func: Function) -> Function {
+    let block_label = *wasm_func
+        .loops
+        .last()
+        .expect("break can only be used inside a loop");
+    wasm_func.instructions.push(Instruction::Br);
+    wasm_func.operand_kinds.push(vec![OperandKind::Label]);
+    wasm_func.operands.push(vec![block_label]);
+    wasm_func
+}
+
+fn codegen_continue(mut wasm_func: Function) -> Function {
+    let block_label = *wasm_func
+        .loops
+        .last()
+        .expect("continue can only be used inside a loop");
+    wasm_func.instructions.push(Instruction::Br);
+    wasm_func.operand_kinds.push(vec![OperandKind::Label]);
+    wasm_func.operands.push(vec![block_label + 1]);
+    wasm_func
+}
+
+fn codegen_return(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    let index = ast_func.indices[entity];
+    let value = ast_func.returns[index];
+    let mut wasm_func = codegen_expression(tx, wasm_func, ast_func, value);
+    if is_tail_call(&wasm_func, ast_func, value) {
+        return wasm_func;
+    }
+    wasm_func.instructions.push(Instruction::Return);
+    wasm_func.operand_kinds.push(vec![]);
+    wasm_func.operands.push(vec![]);
+    wasm_func
+}
+
+fn codegen_expression(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    match ast_func.kinds[entity] {
+        parser::Kind::Int => codegen_int(wasm_func, ast_func, entity),
+        parser::Kind::Float => codegen_float(wasm_func, ast_func, entity),
+        parser::Kind::BinaryOp => codegen_binary_op(tx, wasm_func, ast_func, entity),
+        parser::Kind::Assign => codegen_assignment(tx, wasm_func, ast_func, entity),
+        parser::Kind::Symbol => codegen_symbol(tx, wasm_func, ast_func, entity),
+        parser::Kind::FunctionCall => codegen_function_call(tx, wasm_func, ast_func, entity),
+        parser::Kind::If => {
+            let result = widen(expression_type(&wasm_func, ast_func, entity));
+            codegen_if(tx, wasm_func, ast_func, entity, Some(result))
+        }
+        parser::Kind::While => codegen_while(tx, wasm_func, ast_func, entity),
+        parser::Kind::For => codegen_for(tx, wasm_func, ast_func, entity),
+        parser::Kind::Array => codegen_array(tx, wasm_func, ast_func, entity),
+        parser::Kind::Match => {
+            let result = widen(expression_type(&wasm_func, ast_func, entity));
+            codegen_match(tx, wasm_func, ast_func, entity, Some(result))
+        }
+        parser::Kind::Lambda => codegen_lambda(tx, wasm_func, ast_func, entity),
+        parser::Kind::Grouping => codegen_grouping(tx, wasm_func, ast_func, entity),
+        parser::Kind::Break => codegen_break(wasm_func),
+        parser::Kind::Continue => codegen_continue(wasm_func),
+        parser::Kind::Return => codegen_return(tx, wasm_func, ast_func, entity),
+    }
+}
+
+fn produces_value(ast_func: &parser::Function, entity: usize) -> bool {
+    match ast_func.kinds[entity] {
+        parser::Kind::Int
+        | parser::Kind::Float
+        | parser::Kind::BinaryOp
+        | parser::Kind::Symbol
+        | parser::Kind::FunctionCall
+        | parser::Kind::Array
+        | parser::Kind::Match
+        | parser::Kind::Lambda
+        | parser::Kind::Grouping => true,
+        parser::Kind::If => !ast_func.ifs.else_branches[ast_func.indices[entity]].is_empty(),
+        parser::Kind::Assign
+        | parser::Kind::While
+        | parser::Kind::For
+        | parser::Kind::Break
+        | parser::Kind::Continue
+        | parser::Kind::Return => false,
+    }
+}
+
+fn codegen_statement(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    match ast_func.kinds[entity] {
+        parser::Kind::If => codegen_if(tx, wasm_func, ast_func, entity, None),
+        parser::Kind::Match => codegen_match(tx, wasm_func, ast_func, entity, None),
+        _ if produces_value(ast_func, entity) => {
+            let mut wasm_func = codegen_expression(tx, wasm_func, ast_func, entity);
+            wasm_func.instructions.push(Instruction::Drop);
+            wasm_func.operand_kinds.push(vec![]);
+            wasm_func.operands.push(vec![]);
+            wasm_func
+        }
+        _ => codegen_expression(tx, wasm_func, ast_func, entity),
+    }
+}
+
+fn codegen_block(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    expressions: &[usize],
+    result: bool,
+) -> Function {
+    let (last, statements) = match (result, expressions.split_last()) {
+        (true, Some((&last, statements))) => (Some(last), statements),
+        _ => (None, expressions),
+    };
+    let wasm_func = statements.iter().fold(wasm_func, |wasm_func, &statement| {
+        codegen_statement(tx.clone(), wasm_func, ast_func, statement)
+    });
+    match last {
+        Some(last) => codegen_expression(tx, wasm_func, ast_func, last),
+        None => wasm_func,
+    }
+}
+
+fn tail_calls(ast_func: &parser::Function, calls: HashSet<usize>, entity: usize) -> HashSet<usize> {
+    let index = ast_func.indices[entity];
+    let last = |calls, expressions: &[usize]| match expressions.last() {
+        Some(&last) => tail_calls(ast_func, calls, last),
+        None => calls,
+    };
+    match ast_func.kinds[entity] {
+        parser::Kind::FunctionCall => {
+            let callee = callee_name(ast_func, index);
+            let shadowed = ast_func
+                .arguments
+                .iter()
+                .any(|&argument| Some(&ast_func.symbols[argument]) == callee);
+            let mut calls = calls;
+            if callee == Some(&ast_func.symbols[ast_func.name]) && !shadowed {
+                calls.insert(entity);
+            }
+            calls
+        }
+        parser::Kind::If => {
+            let calls = last(calls, &ast_func.ifs.then_branches[index]);
+            last(calls, &ast_func.ifs.else_branches[index])
+        }
+        parser::Kind::Match => ast_func.matches.bodies[index]
+            .iter()
+            .fold(calls, |calls, body| last(calls, body)),
+        parser::Kind::Grouping => tail_calls(ast_func, calls, ast_func.groupings[index]),
+        parser::Kind::Return => tail_calls(ast_func, calls, ast_func.returns[index]),
+        _ => calls,
+    }
+}
+
+fn in_lambda(ast_func: &parser::Function, entity: usize) -> bool {
+    ast_func
+        .kinds
+        .iter()
+        .enumerate()
+        .filter(|(_, &kind)| kind == parser::Kind::Lambda)
+        .any(|(lambda, _)| {
+            let start = ast_func.lambdas.starts[ast_func.indices[lambda]];
+            (start..lambda).contains(&entity)
+        })
+}
+
+fn function_tail_calls(ast_func: &parser::Function) -> HashSet<usize> {
+    let calls = match ast_func.expressions.last() {
+        Some(&last) => tail_calls(ast_func, HashSet::new(), last),
+        None => HashSet::new(),
+    };
+    ast_func
+        .kinds
         .iter()
-        .fold(wasm_func, |wasm_func, &expression| {
-            codegen_expression(tx.clone(), wasm_func, ast_func, expression)
+        .enumerate()
+        .filter(|&(entity, &kind)| kind == parser::Kind::Return && !in_lambda(ast_func, entity))
+        .fold(calls, |calls, (entity, _)| {
+            tail_calls(ast_func, calls, entity)
+        })
+}
+
+fn is_tail_call(wasm_func: &Function, ast_func: &parser::Function, entity: usize) -> bool {
+    if !wasm_func.tail_calls.contains(&entity) {
+        return false;
+    }
+    let (type_arguments, _, _) = call_signature(wasm_func, ast_func, entity);
+    callee_name(ast_func, ast_func.indices[entity]).is_some_and(|callee| {
+        instance_name(callee, &type_arguments) == wasm_func.symbols[wasm_func.name]
+    })
+}
+
+fn codegen_tail_call(
+    tx: Sender<Message>,
+    wasm_func: Function,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Function {
+    let parameters = &ast_func.function_calls.parameters[ast_func.indices[entity]];
+    assert_eq!(
+        parameters.len(),
+        wasm_func.arguments,
+        "{} expects {} arguments, found {}",
+        ast_func.symbols[ast_func.name],
+        wasm_func.arguments,
+        parameters.len()
+    );
+    let wasm_func = parameters.iter().fold(wasm_func, |wasm_func, &parameter| {
+        codegen_expression(tx.clone(), wasm_func, ast_func, parameter)
+    });
+    let mut wasm_func = (0..parameters.len())
+        .rev()
+        .fold(wasm_func, |wasm_func, argument| {
+            set_local(wasm_func, argument)
         });
-    wasm_func.symbols = ast_func.symbols.clone();
-    wasm_func.ints = ast_func.ints.clone();
+    let label = wasm_func.tail_loop.unwrap();
+    wasm_func.instructions.push(Instruction::Br);
+    wasm_func.operand_kinds.push(vec![OperandKind::Label]);
+    wasm_func.operands.push(vec![label]);
+    wasm_func
+}
+
+fn codegen_function(
+    tx: Sender<Message>,
+    ast_func: &parser::Function,
+    type_arguments: &[Type],
+    globals: &HashSet<String>,
+    variants: &HashMap<String, Variant>,
+    functions: &HashMap<String, Signature>,
+) -> Function {
+    let name = &ast_func.symbols[ast_func.name];
+    let type_parameters = ast_func
+        .type_parameters
+        .iter()
+        .map(|&type_parameter| ast_func.symbols[type_parameter].clone())
+        .collect::<Vec<String>>();
+    let resolve = |value_type: &Option<usize>| match value_type {
+        Some(value_type) => resolve_type(
+            &ast_func.symbols[*value_type],
+            &type_parameters,
+            type_arguments,
+        ),
+        None => Type::I64,
+    };
+    let locals = ast_func
+        .arguments
+        .iter()
+        .map(|&argument| format!("${}", ast_func.symbols[argument].clone()))
+        .collect::<Vec<String>>();
+    let name_to_local = ast_func.arguments.iter().enumerate().fold(
+        HashMap::new(),
+        |mut name_to_local, (i, &argument)| {
+            name_to_local
+                .try_insert(ast_func.symbols[argument].clone(), i)
+                .unwrap();
+            name_to_local
+        },
+    );
+    let wasm_func = Function {
+        name: ast_func.name,
+        instructions: vec![],
+        operand_kinds: vec![],
+        operands: vec![],
+        locals,
+        types: ast_func.argument_types.iter().map(resolve).collect(),
+        name_to_local,
+        globals: globals.clone(),
+        variants: variants.clone(),
+        functions: functions.clone(),
+        symbols: ast_func.symbols.clone(),
+        ints: ast_func.ints.clone(),
+        floats: ast_func.floats.clone(),
+        arguments: ast_func.arguments.len(),
+        result: resolve(&ast_func.return_type),
+        next_label: 0,
+        loops: vec![],
+        tail_calls: HashSet::new(),
+        tail_loop: None,
+        inline: ast_func.inline,
+        memory: false,
+    };
+    let wasm_func = match type_arguments {
+        [] => wasm_func,
+        _ => {
+            let (wasm_func, name) = symbol(wasm_func, &instance_name(name, type_arguments));
+            Function { name, ..wasm_func }
+        }
+    };
+    let tail_calls = function_tail_calls(ast_func);
+    let wasm_func = match tail_calls.is_empty() {
+        true => wasm_func,
+        false => {
+            let label = wasm_func.next_label;
+            let mut wasm_func = Function {
+                tail_calls,
+                tail_loop: Some(label),
+                next_label: label + 1,
+                ..wasm_func
+            };
+            wasm_func.instructions.push(Instruction::Loop);
+            wasm_func
+                .operand_kinds
+                .push(vec![OperandKind::Label, OperandKind::Result]);
+            wasm_func
+                .operands
+                .push(vec![label, wasm_func.result as usize]);
+            wasm_func
+        }
+    };
+    let mut wasm_func = codegen_block(tx, wasm_func, ast_func, &ast_func.expressions, true);
+    if let Some(label) = wasm_func.tail_loop {
+        wasm_func.instructions.push(Instruction::End);
+        wasm_func.operand_kinds.push(vec![OperandKind::Label]);
+        wasm_func.operands.push(vec![label]);
+    }
+    if let Some(&last) = ast_func.expressions.last() {
+        let found = expression_type(&wasm_func, ast_func, last);
+        assert!(
+            compatible(found, wasm_func.result),
+            "{} returns {} but is declared to return {}",
+            wasm_func.symbols[wasm_func.name],
+            type_name(found),
+            type_name(wasm_func.result)
+        );
+    }
+    wasm_func
+}
+
+fn signatures(ast: &Ast) -> HashMap<String, Signature> {
+    ast.top_level
+        .iter()
+        .map(|(name, &function)| {
+            let func = &ast.functions[function];
+            let symbol = |&symbol: &usize| func.symbols[symbol].clone();
+            let signature = Signature {
+                type_parameters: func.type_parameters.iter().map(symbol).collect(),
+                parameters: func
+                    .argument_types
+                    .iter()
+                    .map(|argument_type| argument_type.as_ref().map(symbol))
+                    .collect(),
+                result: func.return_type.as_ref().map(symbol),
+            };
+            (name.clone(), signature)
+        })
+        .collect()
+}
+
+fn exports(ast: &Ast) -> Exports {
+    let mut exports = Exports {
+        names: vec![],
+        functions: vec![],
+    };
+    if ast.top_level.contains_key("start") {
+        exports.names.push(String::from("_start"));
+        exports.functions.push(String::from("start"));
+    }
+    ast.functions.iter().fold(exports, |mut exports, func| {
+        let name = &func.symbols[func.name];
+        assert!(
+            func.type_parameters.is_empty() || (func.export.is_none() && name != "start"),
+            "Generic function {} cannot be exported",
+            name
+        );
+        if let Some(export) = func.export {
+            let export = &func.symbols[export];
+            match exports.names.iter().position(|other| other == export) {
+                Some(i) => assert!(
+                    &exports.functions[i] == name,
+                    "Export name {} is used by both {} and {}",
+                    export,
+                    exports.functions[i],
+                    name
+                ),
+                None => {
+                    exports.names.push(export.clone());
+                    exports.functions.push(name.clone());
+                }
+            }
+        }
+        exports
+    })
+}
+
+const I64_OPS: [(parser::BinaryOp, Instruction); 16] = [
+    (parser::BinaryOp::Add, Instruction::I64Add),
+    (parser::BinaryOp::Subtract, Instruction::I64Sub),
+    (parser::BinaryOp::Multiply, Instruction::I64Mul),
+    (parser::BinaryOp::Divide, Instruction::I64DivS),
+    (parser::BinaryOp::Modulo, Instruction::I64RemS),
+    (parser::BinaryOp::ShiftLeft, Instruction::I64Shl),
+    (parser::BinaryOp::ShiftRight, Instruction::I64ShrS),
+    (parser::BinaryOp::BitwiseAnd, Instruction::I64And),
+    (parser::BinaryOp::BitwiseXor, Instruction::I64Xor),
+    (parser::BinaryOp::BitwiseOr, Instruction::I64Or),
+    (parser::BinaryOp::Equal, Instruction::I64Eq),
+    (parser::BinaryOp::NotEqual, Instruction::I64Neq),
+    (parser::BinaryOp::LessThan, Instruction::I64LtS),
+    (parser::BinaryOp::LessThanEqual, Instruction::I64LeS),
+    (parser::BinaryOp::GreaterThan, Instruction::I64GtS),
+    (parser::BinaryOp::GreaterThanEqual, Instruction::I64GeS),
+];
+
+pub(crate) fn i64_instruction(op: parser::BinaryOp) -> Instruction {
+    I64_OPS.iter().find(|(other, _)| *other == op).unwrap().1
+}
+
+pub(crate) fn i64_binary_op(instruction: Instruction) -> Option<parser::BinaryOp> {
+    I64_OPS
+        .iter()
+        .find(|(_, other)| *other == instruction)
+        .map(|&(op, _)| op)
+}
+
+pub(crate) fn is_comparison(op: parser::BinaryOp) -> bool {
+    matches!(
+        op,
+        parser::BinaryOp::Equal
+            | parser::BinaryOp::NotEqual
+            | parser::BinaryOp::LessThan
+            | parser::BinaryOp::LessThanEqual
+            | parser::BinaryOp::GreaterThan
+            | parser::BinaryOp::GreaterThanEqual
+    )
+}
+
+pub(crate) fn fold_binary_op(op: parser::BinaryOp, left: i64, right: i64) -> Option<i64> {
+    match op {
+        parser::BinaryOp::Add => Some(left.wrapping_add(right)),
+        parser::BinaryOp::Subtract => Some(left.wrapping_sub(right)),
+        parser::BinaryOp::Multiply => Some(left.wrapping_mul(right)),
+        parser::BinaryOp::Divide => left.checked_div(right),
+        parser::BinaryOp::Modulo if right == 0 => None,
+        parser::BinaryOp::Modulo => Some(left.wrapping_rem(right)),
+        parser::BinaryOp::ShiftLeft => Some(left.wrapping_shl(right as u32)),
+        parser::BinaryOp::ShiftRight => Some(left.wrapping_shr(right as u32)),
+        parser::BinaryOp::BitwiseAnd => Some(left & right),
+        parser::BinaryOp::BitwiseXor => Some(left ^ right),
+        parser::BinaryOp::BitwiseOr => Some(left | right),
+        parser::BinaryOp::Equal => Some((left == right) as i64),
+        parser::BinaryOp::NotEqual => Some((left != right) as i64),
+        parser::BinaryOp::LessThan => Some((left < right) as i64),
+        parser::BinaryOp::LessThanEqual => Some((left <= right) as i64),
+        parser::BinaryOp::GreaterThan => Some((left > right) as i64),
+        parser::BinaryOp::GreaterThanEqual => Some((left >= right) as i64),
+    }
+}
+
+fn fold_constant(op: parser::BinaryOp, left: i64, right: i64) -> i64 {
+    fold_binary_op(op, left, right).unwrap_or_else(|| match op {
+        parser::BinaryOp::Divide => panic!("Constant division {} / {} traps", left, right),
+        _ => panic!("Constant remainder {} % 0 traps", left),
+    })
+}
+
+fn fold_expression(
+    globals: &parser::Globals,
+    ast_func: &parser::Function,
+    entity: usize,
+    stack: &[usize],
+) -> i64 {
+    let index = ast_func.indices[entity];
+    match ast_func.kinds[entity] {
+        parser::Kind::Int => ast_func.ints[index].parse().unwrap(),
+        parser::Kind::BinaryOp => {
+            let left = fold_expression(globals, ast_func, ast_func.binary_ops.lefts[index], stack);
+            let right =
+                fold_expression(globals, ast_func, ast_func.binary_ops.rights[index], stack);
+            fold_constant(ast_func.binary_ops.ops[index], left, right)
+        }
+        parser::Kind::Grouping => {
+            fold_expression(globals, ast_func, ast_func.groupings[index], stack)
+        }
+        parser::Kind::Symbol => {
+            let name = &ast_func.symbols[index];
+            let global = *globals
+                .name_to_global
+                .get(name)
+                .unwrap_or_else(|| panic!("Unknown name {} in constant expression", name));
+            assert_eq!(
+                globals.kinds[global],
+                GlobalKind::Const,
+                "Cannot use var {} in a constant expression",
+                name
+            );
+            fold_global(globals, global, stack)
+        }
+        kind => panic!("{:?} is not allowed in a constant expression", kind),
+    }
+}
+
+pub(crate) fn fold_global(globals: &parser::Globals, global: usize, stack: &[usize]) -> i64 {
+    let value = &globals.values[global];
+    assert!(
+        !stack.contains(&global),
+        "Constant {} is defined in terms of itself",
+        value.symbols[value.name]
+    );
+    let stack = stack
+        .iter()
+        .copied()
+        .chain(std::iter::once(global))
+        .collect::<Vec<usize>>();
+    fold_expression(globals, value, value.expressions[0], &stack)
+}
+
+fn fold_constants(
+    mut ast_func: parser::Function,
+    constants: &HashMap<String, i64>,
+) -> parser::Function {
+    let arguments = ast_func
+        .arguments
+        .iter()
+        .map(|&argument| ast_func.symbols[argument].clone())
+        .collect::<HashSet<String>>();
+    for &name in ast_func
+        .assignments
+        .names
+        .iter()
+        .chain(ast_func.fors.variables.iter())
+    {
+        let symbol = &ast_func.symbols[ast_func.indices[name]];
+        assert!(
+            !constants.contains_key(symbol) || arguments.contains(symbol),
+            "Cannot assign to constant {}",
+            symbol
+        );
+    }
+    for entity in 0..ast_func.kinds.len() {
+        if ast_func.kinds[entity] != parser::Kind::Symbol {
+            continue;
+        }
+        let symbol = &ast_func.symbols[ast_func.indices[entity]];
+        if let (false, Some(value)) = (arguments.contains(symbol), constants.get(symbol)) {
+            ast_func.kinds[entity] = parser::Kind::Int;
+            ast_func.indices[entity] = ast_func.ints.len();
+            ast_func.ints.push(value.to_string());
+        }
+    }
+    ast_func
+}
+
+fn resolve_globals(mut ast: Ast) -> (Ast, Globals, HashSet<String>) {
+    let values = (0..ast.globals.values.len())
+        .map(|global| fold_global(&ast.globals, global, &[]))
+        .collect::<Vec<i64>>();
+    let constants = ast
+        .globals
+        .values
+        .iter()
+        .zip(ast.globals.kinds.iter().zip(values.iter()))
+        .filter(|(_, (&kind, _))| kind == GlobalKind::Const)
+        .map(|(global, (_, &value))| (global.symbols[global.name].clone(), value))
+        .collect::<HashMap<String, i64>>();
+    ast.functions = ast
+        .functions
+        .into_iter()
+        .map(|ast_func| fold_constants(ast_func, &constants))
+        .collect();
+    let globals = ast
+        .globals
+        .values
+        .iter()
+        .zip(ast.globals.kinds.iter().zip(values))
+        .filter(|(_, (&kind, _))| kind == GlobalKind::Var)
+        .fold(
+            Globals {
+                names: vec![],
+                values: vec![],
+            },
+            |mut globals, (global, (_, value))| {
+                globals.names.push(global.symbols[global.name].clone());
+                globals.values.push(value);
+                globals
+            },
+        );
+    let vars = globals.names.iter().cloned().collect();
+    (ast, globals, vars)
+}
+
+pub(crate) fn variants(enums: &parser::Enums) -> HashMap<String, Variant> {
+    enums
+        .variants
+        .iter()
+        .zip(enums.fields.iter())
+        .enumerate()
+        .flat_map(|(enumeration, (variants, fields))| {
+            variants
+                .iter()
+                .zip(fields.iter())
+                .enumerate()
+                .map(move |(tag, (name, fields))| {
+                    let variant = Variant {
+                        enumeration,
+                        tag,
+                        fields: fields.len(),
+                        variants: variants.len(),
+                    };
+                    (name.clone(), variant)
+                })
+        })
+        .collect()
+}
+
+fn function_table(mut wasm: Wasm) -> Wasm {
+    for func in wasm.functions.iter_mut() {
+        for i in 0..func.instructions.len() {
+            match (&func.instructions[i], &func.operand_kinds[i][..]) {
+                (Instruction::I64Const, [OperandKind::Function]) => {
+                    let name = &func.symbols[func.operands[i][0]];
+                    let slot = match wasm.table.iter().position(|other| other == name) {
+                        Some(slot) => slot,
+                        None => {
+                            wasm.table.push(name.clone());
+                            wasm.table.len() - 1
+                        }
+                    };
+                    func.operand_kinds[i] = vec![OperandKind::IntLiteral];
+                    func.operands[i] = vec![func.ints.len()];
+                    func.ints.push(slot.to_string());
+                }
+                (Instruction::CallIndirect, _) => {
+                    let arity = func.operands[i][0];
+                    if !wasm.types.contains(&arity) {
+                        wasm.types.push(arity);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    wasm.types.sort_unstable();
+    wasm
+}
+
+fn unread_locals(func: &Function) -> impl Iterator<Item = String> + '_ {
+    let name = func.symbols[func.name].split('<').next().unwrap();
+    (0..func.locals.len())
+        .filter(move |&local| {
+            !func.locals[local].starts_with("$.")
+                && !func.locals[local].starts_with("$_")
+                && !func
+                    .instructions
+                    .iter()
+                    .zip(&func.operands)
+                    .any(|(&instruction, operands)| {
+                        instruction == Instruction::GetLocal && operands[..] == [local]
+                    })
+        })
+        .map(move |local| match local < func.arguments {
+            true => format!(
+                "parameter {} of {} is never used",
+                &func.locals[local][1..],
+                name
+            ),
+            false => format!(
+                "local {} in {} is never read",
+                &func.locals[local][1..],
+                name
+            ),
+        })
+}
+
+fn warnings(ast: &Ast, wasm: &Wasm) -> Vec<String> {
+    let used = wasm
+        .name_to_function
+        .keys()
+        .map(|name| name.split('<').next().unwrap())
+        .collect::<HashSet<&str>>();
+    let mut unused = ast
+        .top_level
+        .keys()
+        .filter(|name| !used.contains(name.as_str()) && !ast.libraries.contains(*name))
+        .map(|name| format!("function {} is never used", name))
+        .collect::<Vec<String>>();
+    unused.sort();
+    let mut locals = wasm
+        .functions
+        .iter()
+        .flat_map(unread_locals)
+        .collect::<Vec<String>>();
+    locals.sort();
+    locals.dedup();
+    unused.into_iter().chain(locals).collect()
+}
+
+fn cached_function(
+    tx: Sender<Message>,
+    ast_func: &parser::Function,
+    type_arguments: &[Type],
+    globals: &HashSet<String>,
+    variants: &HashMap<String, Variant>,
+    functions: &HashMap<String, Signature>,
+    cache: Option<&Path>,
+) -> Function {
+    let key = cache::key(ast_func, type_arguments, globals, variants, functions);
+    let (wasm_func, messages) = match cache.and_then(|directory| cache::read(directory, key)) {
+        Some((wasm_func, messages)) => {
+            let restore = |wasm_func: Function| Function {
+                globals: globals.clone(),
+                variants: variants.clone(),
+                functions: functions.clone(),
+                ..wasm_func
+            };
+            let messages = messages
+                .into_iter()
+                .map(|message| match message {
+                    Message::Closure(name, closure) => {
+                        Message::Closure(name, Box::new(restore(*closure)))
+                    }
+                    message => message,
+                })
+                .collect::<Vec<Message>>();
+            (restore(wasm_func), messages)
+        }
+        None => {
+            let (local_tx, rx) = mpsc::channel();
+            let wasm_func = codegen_function(
+                local_tx,
+                ast_func,
+                type_arguments,
+                globals,
+                variants,
+                functions,
+            );
+            let messages = rx.into_iter().collect::<Vec<Message>>();
+            if let Some(directory) = cache {
+                cache::save(directory, key, &wasm_func, &messages);
+            }
+            (wasm_func, messages)
+        }
+    };
+    for message in messages {
+        tx.send(message).unwrap();
+    }
     wasm_func
 }
 
 pub fn codegen(ast: Ast) -> Wasm {
+    generate(ast, None)
+}
+
+pub fn codegen_cached(ast: Ast, cache: &Path) -> Wasm {
+    generate(ast, Some(cache))
+}
+
+fn generate(ast: Ast, cache: Option<&Path>) -> Wasm {
+    let (ast, globals, vars) = resolve_globals(ast);
+    let vars = &vars;
+    let variants = &variants(&ast.enums);
+    let functions = &signatures(&ast);
     let mut in_flight = 0;
     let mut wasm = Wasm {
         functions: vec![],
         name_to_function: HashMap::new(),
+        exports: exports(&ast),
+        globals,
+        memory: false,
+        table: vec![],
+        types: vec![],
+        warnings: vec![],
+        ast: None,
     };
+    if wasm.exports.functions.is_empty() {
+        return Wasm {
+            ast: Some(ast),
+            ..wasm
+        };
+    }
     let (tx, rx) = mpsc::channel();
-    tx.send(Message::Spawn(String::from("start"))).unwrap();
+    for name in wasm.exports.functions.iter() {
+        tx.send(Message::Spawn(name.clone(), vec![])).unwrap();
+    }
     loop {
         match rx.recv().unwrap() {
-            Message::Spawn(name) => {
-                if let None = wasm.name_to_function.get(&name) {
+            Message::Spawn(name, type_arguments) => {
+                let instance = instance_name(&name, &type_arguments);
+                if !wasm.name_to_function.contains_key(&instance) {
                     in_flight += 1;
-                    let index = *ast.top_level.get(&name).unwrap();
+                    let index = *ast
+                        .top_level
+                        .get(&name)
+                        .unwrap_or_else(|| panic!("Unknown function {}", name));
                     let ast_func = &ast.functions[index];
                     let i = wasm.functions.len();
                     wasm.functions.push(Function {
@@ -348,24 +2297,51 @@ pub fn codegen(ast: Ast) -> Wasm {
                         operand_kinds: vec![],
                         operands: vec![],
                         locals: vec![],
+                        types: vec![],
                         name_to_local: HashMap::new(),
+                        globals: HashSet::new(),
+                        variants: HashMap::new(),
+                        functions: HashMap::new(),
                         symbols: vec![],
                         ints: vec![],
+                        floats: vec![],
                         arguments: 0,
+                        result: Type::I64,
                         next_label: 0,
+                        loops: vec![],
+                        tail_calls: HashSet::new(),
+                        tail_loop: None,
+                        inline: None,
+                        memory: false,
                     });
-                    wasm.name_to_function.try_insert(name, i).unwrap();
+                    wasm.name_to_function.try_insert(instance, i).unwrap();
                     let local_tx = tx.clone();
                     rayon::scope(|s| {
                         s.spawn(move |_| {
-                            let wasm_func = codegen_function(local_tx.clone(), ast_func);
-                            local_tx.send(Message::Done(i, wasm_func)).unwrap();
+                            let wasm_func = cached_function(
+                                local_tx.clone(),
+                                ast_func,
+                                &type_arguments,
+                                vars,
+                                variants,
+                                functions,
+                                cache,
+                            );
+                            local_tx
+                                .send(Message::Done(i, Box::new(wasm_func)))
+                                .unwrap();
                         });
                     });
                 }
             }
+            Message::Closure(name, wasm_func) => {
+                if let Entry::Vacant(entry) = wasm.name_to_function.entry(name) {
+                    entry.insert(wasm.functions.len());
+                    wasm.functions.push(*wasm_func);
+                }
+            }
             Message::Done(i, wasm_func) => {
-                wasm.functions[i] = wasm_func;
+                wasm.functions[i] = *wasm_func;
                 in_flight -= 1;
                 if in_flight == 0 {
                     break;
@@ -373,5 +2349,15 @@ pub fn codegen(ast: Ast) -> Wasm {
             }
         }
     }
-    wasm
+    if wasm.functions.iter().any(|func| func.memory) {
+        wasm.memory = true;
+        wasm.globals.names.push(HEAP.to_string());
+        wasm.globals.values.push(0);
+    }
+    let warnings = warnings(&ast, &wasm);
+    function_table(Wasm {
+        warnings,
+        ast: Some(ast),
+        ..wasm
+    })
 }
@@ -0,0 +1,368 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    parser::{parse, Ast},
+    tokenizer::{tokenize, Kind, TopLevel},
+};
+
+const INDENT: &str = "    ";
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Group {
+    Import,
+    Global,
+    Other,
+}
+
+struct Comment<'a> {
+    offset: usize,
+    text: &'a str,
+}
+
+fn comments(source: &str) -> Vec<Comment<'_>> {
+    source
+        .split('\n')
+        .scan(0, |start, line| {
+            let offset = *start;
+            *start += line.len() + 1;
+            Some((offset, line))
+        })
+        .filter_map(|(offset, line)| {
+            let column = line.find('#')?;
+            Some(Comment {
+                offset: offset + column,
+                text: line[column..].trim_end(),
+            })
+        })
+        .collect()
+}
+
+fn column(source: &str, offset: usize) -> usize {
+    offset
+        - source[..offset]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1)
+}
+
+fn text(top_level: &TopLevel, token: usize) -> &str {
+    let index = top_level.indices[token];
+    match top_level.kinds[token] {
+        Kind::Def => "def",
+        Kind::Fn => "fn",
+        Kind::Symbol | Kind::Attribute => &top_level.symbols[index],
+        Kind::LeftParen => "(",
+        Kind::RightParen => ")",
+        Kind::LeftBracket => "[",
+        Kind::RightBracket => "]",
+        Kind::Colon => ":",
+        Kind::Plus => "+",
+        Kind::Minus => "-",
+        Kind::Arrow => "->",
+        Kind::Asterisk => "*",
+        Kind::Slash => "/",
+        Kind::Percent => "%",
+        Kind::Equal => "=",
+        Kind::EqualEqual => "==",
+        Kind::ExclamationEqual => "!=",
+        Kind::Ampersand => "&",
+        Kind::VerticalBar => "|",
+        Kind::VerticalBarGreaterThan => "|>",
+        Kind::Caret => "^",
+        Kind::LessThan => "<",
+        Kind::LessThanEqual => "<=",
+        Kind::LessThanLessThan => "<<",
+        Kind::GreaterThan => ">",
+        Kind::GreaterThanEqual => ">=",
+        Kind::GreaterThanGreaterThan => ">>",
+        Kind::Comma => ",",
+        Kind::DotDot => "..",
+        Kind::Indent => "",
+        Kind::Int => &top_level.ints[index],
+        Kind::Float => &top_level.floats[index],
+        Kind::If => "if",
+        Kind::Else => "else",
+        Kind::Elif => "elif",
+        Kind::While => "while",
+        Kind::For => "for",
+        Kind::In => "in",
+        Kind::Match => "match",
+        Kind::Enum => "enum",
+        Kind::Export => "export",
+        Kind::As => "as",
+        Kind::Import => "import",
+        Kind::From => "from",
+        Kind::Const => "const",
+        Kind::Var => "var",
+        Kind::Break => "break",
+        Kind::Continue => "continue",
+        Kind::Return => "return",
+    }
+}
+
+fn spaced(previous: Kind, next: Kind) -> bool {
+    !matches!(
+        (previous, next),
+        (_, Kind::RightParen)
+            | (_, Kind::RightBracket)
+            | (_, Kind::Comma)
+            | (_, Kind::Colon)
+            | (_, Kind::DotDot)
+            | (Kind::LeftParen, _)
+            | (Kind::LeftBracket, _)
+            | (Kind::DotDot, _)
+            | (Kind::Symbol, Kind::LeftParen)
+            | (Kind::Fn, Kind::LeftParen)
+            | (Kind::RightParen, Kind::LeftParen)
+            | (Kind::RightBracket, Kind::LeftParen)
+            | (Kind::Symbol, Kind::LeftBracket)
+    )
+}
+
+fn is_pipeline_line(top_level: &TopLevel, token: usize) -> bool {
+    top_level.kinds[token] == Kind::Indent
+        && top_level.kinds.get(token + 1) == Some(&Kind::VerticalBarGreaterThan)
+}
+
+fn nesting(top_level: &TopLevel) -> Vec<usize> {
+    top_level
+        .kinds
+        .iter()
+        .scan(0usize, |depth, kind| {
+            let current = *depth;
+            *depth = match kind {
+                Kind::LeftParen | Kind::LeftBracket => *depth + 1,
+                Kind::RightParen | Kind::RightBracket => depth.saturating_sub(1),
+                _ => *depth,
+            };
+            Some(current)
+        })
+        .collect()
+}
+
+fn break_pipelines(
+    mut breaks: Vec<Option<usize>>,
+    top_level: &TopLevel,
+    nesting: &[usize],
+    (start, end): (usize, usize),
+    depth: usize,
+) -> Vec<Option<usize>> {
+    if (start..end).any(|token| is_pipeline_line(top_level, token)) {
+        for token in start..end {
+            if top_level.kinds[token] == Kind::VerticalBarGreaterThan && nesting[token] == 0 {
+                breaks[token] = Some(depth.max(1));
+            }
+        }
+    }
+    breaks
+}
+
+fn line_breaks(top_level: &TopLevel) -> Vec<Option<usize>> {
+    let nesting = nesting(top_level);
+    let length = top_level.kinds.len();
+    let (breaks, _, start, depth) = (0..length).fold(
+        (vec![None; length + 1], vec![], 0, 0),
+        |(breaks, mut widths, start, depth), token| match top_level.kinds[token] {
+            Kind::Indent if !is_pipeline_line(top_level, token) => {
+                let breaks = break_pipelines(breaks, top_level, &nesting, (start, token), depth);
+                let width = top_level.indents[top_level.indices[token]];
+                while widths.last().is_some_and(|&last| last > width) {
+                    widths.pop();
+                }
+                if widths.last() != Some(&width) {
+                    widths.push(width);
+                }
+                let mut breaks = breaks;
+                breaks[token + 1] = Some(widths.len());
+                let depth = widths.len();
+                (breaks, widths, token + 1, depth)
+            }
+            Kind::Attribute => {
+                let mut breaks = breaks;
+                breaks[token + 1] = Some(0);
+                (breaks, widths, start, depth)
+            }
+            _ => (breaks, widths, start, depth),
+        },
+    );
+    break_pipelines(breaks, top_level, &nesting, (start, length), depth)
+}
+
+fn write_comment(
+    mut output: String,
+    source: &str,
+    comment: &Comment,
+    previous: usize,
+    depth: usize,
+) -> String {
+    match source[previous..comment.offset].contains('\n') {
+        true => {
+            output.push('\n');
+            output.push_str(&INDENT.repeat(depth));
+        }
+        false => output.push(' '),
+    }
+    output.push_str(comment.text);
+    output
+}
+
+fn format_chunk(source: &str, top_level: &TopLevel, comments: &[&Comment]) -> String {
+    let breaks = line_breaks(top_level);
+    let length = top_level.kinds.len();
+    let mut output = String::new();
+    let mut comments = comments.iter().peekable();
+    let mut previous = top_level.positions[0];
+    let mut last = None;
+    let mut depth = 0;
+    for token in 0..length {
+        let position = top_level.positions[token];
+        let line = (token..length)
+            .find(|&next| top_level.kinds[next] != Kind::Indent)
+            .and_then(|next| breaks[next])
+            .unwrap_or(depth);
+        while let Some(comment) = comments.next_if(|comment| comment.offset < position) {
+            output = write_comment(output, source, comment, previous, line);
+            previous = comment.offset;
+        }
+        let kind = top_level.kinds[token];
+        previous = position;
+        if kind == Kind::Indent {
+            continue;
+        }
+        match (breaks[token], last) {
+            (Some(line), Some(_)) => {
+                output.push('\n');
+                output.push_str(&INDENT.repeat(line));
+                depth = line;
+            }
+            (None, Some(last)) if spaced(last, kind) => output.push(' '),
+            _ => {}
+        }
+        if kind == Kind::Attribute {
+            output.push('@');
+        }
+        output.push_str(text(top_level, token));
+        last = Some(kind);
+    }
+    comments.fold(output, |output, comment| {
+        let output = write_comment(output, source, comment, previous, depth);
+        previous = comment.offset;
+        output
+    })
+}
+
+fn group(top_level: &TopLevel) -> Group {
+    match top_level.kinds[0] {
+        Kind::Import | Kind::From => Group::Import,
+        Kind::Const | Kind::Var => Group::Global,
+        _ => Group::Other,
+    }
+}
+
+fn owner(source: &str, chunks: &[TopLevel], comment: &Comment) -> (usize, bool) {
+    let next = chunks
+        .iter()
+        .position(|top_level| top_level.positions[0] > comment.offset)
+        .unwrap_or(chunks.len());
+    match next {
+        0 => (0, true),
+        next => {
+            let positions = &chunks[next - 1].positions;
+            let inside = comment.offset < positions[positions.len() - 1];
+            match inside || column(source, comment.offset) > 0 {
+                true => (next - 1, false),
+                false => (next, true),
+            }
+        }
+    }
+}
+
+fn normalized(source: &str) -> Ast {
+    let mut ast = parse(tokenize(source));
+    for func in ast
+        .functions
+        .iter_mut()
+        .chain(ast.globals.values.iter_mut())
+    {
+        func.hash = 0;
+    }
+    ast
+}
+
+fn comment_texts(source: &str) -> Vec<&str> {
+    comments(source)
+        .iter()
+        .map(|comment| comment.text)
+        .collect()
+}
+
+pub fn format(source: &str) -> String {
+    let tokens = tokenize(source);
+    let chunks = &tokens.top_level;
+    let comments = comments(source);
+    let mut leading = vec![vec![]; chunks.len() + 1];
+    let mut internal = vec![vec![]; chunks.len()];
+    for comment in comments.iter() {
+        match owner(source, chunks, comment) {
+            (chunk, true) => leading[chunk].push(comment),
+            (chunk, false) => internal[chunk].push(comment),
+        }
+    }
+    let lines = |comments: &[&Comment]| {
+        comments
+            .iter()
+            .map(|comment| format!("{}\n", comment.text))
+            .collect::<String>()
+    };
+    let formatted = chunks
+        .iter()
+        .enumerate()
+        .fold(String::new(), |output, (chunk, top_level)| {
+            let grouped = chunk > 0
+                && group(top_level) != Group::Other
+                && group(top_level) == group(&chunks[chunk - 1]);
+            let separator = if chunk == 0 || grouped { "" } else { "\n" };
+            let body = format_chunk(source, top_level, &internal[chunk]);
+            format!(
+                "{}{}{}{}\n",
+                output,
+                separator,
+                lines(&leading[chunk]),
+                body
+            )
+        });
+    let formatted = match (formatted.is_empty(), lines(&leading[chunks.len()])) {
+        (_, trailer) if trailer.is_empty() => formatted,
+        (true, trailer) => trailer,
+        (false, trailer) => format!("{}\n{}", formatted, trailer),
+    };
+    assert!(
+        normalized(source) == normalized(&formatted),
+        "Formatting would change the meaning of the program"
+    );
+    assert_eq!(
+        comment_texts(source),
+        comment_texts(&formatted),
+        "Formatting would drop comments"
+    );
+    formatted
+}
+
+pub fn sources(path: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(path) {
+        Ok(entries) => {
+            let mut entries = entries
+                .filter_map(Result::ok)
+                .map(|entry| entry.path())
+                .collect::<Vec<PathBuf>>();
+            entries.sort();
+            entries.iter().flat_map(|entry| sources(entry)).collect()
+        }
+        Err(_) if path.extension().is_some_and(|extension| extension == "mon") => {
+            vec![path.to_path_buf()]
+        }
+        Err(_) => vec![],
+    }
+}
@@ -0,0 +1,917 @@
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    panic, thread,
+};
+
+use crate::{
+    codegen::{
+        check_if, check_match, fold_binary_op, fold_global, range_bounds, variants, Variant,
+    },
+    parser::{self, Ast, BinaryOp, GlobalKind, Kind, Pattern},
+};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Value {
+    I64(i64),
+    F64(f64),
+}
+
+enum Exit {
+    Break,
+    Continue,
+    Return(Value),
+    TailCall(usize, Vec<Value>),
+}
+
+type Flow = Result<Value, Exit>;
+
+type Frame = Vec<Option<Value>>;
+
+#[derive(Copy, Clone)]
+enum Target {
+    Variant(Variant),
+    Function(usize),
+    Global(usize),
+    Unknown,
+}
+
+const PAGE: usize = 65536;
+
+const MAX_PAGES: usize = 65536;
+
+const STACK_SIZE: usize = 1 << 30;
+
+const MAX_DEPTH: usize = 1 << 17;
+
+struct Closures {
+    functions: Vec<usize>,
+    lambdas: Vec<Option<usize>>,
+    captures: Vec<Vec<usize>>,
+}
+
+struct Machine<'a> {
+    ast: &'a Ast,
+    slots: Vec<Vec<usize>>,
+    declared: Vec<Vec<bool>>,
+    targets: Vec<Vec<Target>>,
+    globals: Vec<Value>,
+    memory: Vec<u8>,
+    heap: i64,
+    closures: Closures,
+    depth: usize,
+}
+
+fn slots(func: &parser::Function) -> Vec<usize> {
+    let mut names = HashMap::new();
+    func.symbols
+        .iter()
+        .map(|symbol| {
+            let next = names.len();
+            *names.entry(symbol.as_str()).or_insert(next)
+        })
+        .collect()
+}
+
+fn declared(func: &parser::Function, slots: &[usize]) -> Vec<bool> {
+    let length = slots.iter().map(|&slot| slot + 1).max().unwrap_or(0);
+    let symbol = |entity: &usize| func.indices[*entity];
+    func.assignments
+        .names
+        .iter()
+        .chain(func.fors.variables.iter())
+        .map(symbol)
+        .chain(func.arguments.iter().copied())
+        .chain(func.lambdas.parameters.iter().flatten().copied())
+        .chain(func.matches.bindings.iter().flatten().flatten().copied())
+        .fold(vec![false; length], |mut declared, symbol| {
+            declared[slots[symbol]] = true;
+            declared
+        })
+}
+
+fn target(ast: &Ast, variants: &HashMap<String, Variant>, name: &str) -> Target {
+    match (
+        variants.get(name),
+        ast.top_level.get(name),
+        ast.globals.name_to_global.get(name),
+    ) {
+        (Some(&variant), _, _) => Target::Variant(variant),
+        (None, Some(&function), _) => Target::Function(function),
+        (None, None, Some(&global)) => Target::Global(global),
+        (None, None, None) => Target::Unknown,
+    }
+}
+
+fn machine(ast: &Ast) -> Machine<'_> {
+    let slots = ast.functions.iter().map(slots).collect::<Vec<Vec<usize>>>();
+    let declared = ast
+        .functions
+        .iter()
+        .zip(slots.iter())
+        .map(|(func, slots)| declared(func, slots))
+        .collect();
+    let variants = variants(&ast.enums);
+    let targets = ast
+        .functions
+        .iter()
+        .map(|func| {
+            func.symbols
+                .iter()
+                .map(|name| target(ast, &variants, name))
+                .collect()
+        })
+        .collect();
+    let globals = (0..ast.globals.values.len())
+        .map(|global| Value::I64(fold_global(&ast.globals, global, &[])))
+        .collect();
+    Machine {
+        ast,
+        slots,
+        declared,
+        targets,
+        globals,
+        memory: vec![0; PAGE],
+        heap: 0,
+        closures: Closures {
+            functions: vec![],
+            lambdas: vec![],
+            captures: vec![],
+        },
+        depth: 0,
+    }
+}
+
+fn value_type(value: Value) -> &'static str {
+    match value {
+        Value::I64(_) => "i64",
+        Value::F64(_) => "f64",
+    }
+}
+
+fn int(value: Value, context: &str) -> i64 {
+    match value {
+        Value::I64(value) => value,
+        Value::F64(_) => panic!("{} expects i64, found f64", context),
+    }
+}
+
+fn truthy(value: Value) -> bool {
+    int(value, "Condition") != 0
+}
+
+fn address(machine: &Machine, address: i64, offset: usize) -> usize {
+    let effective = address as u32 as usize + offset;
+    assert!(
+        effective + 8 <= machine.memory.len(),
+        "out of bounds memory access"
+    );
+    effective
+}
+
+fn load(machine: &Machine, object: i64, offset: usize) -> i64 {
+    let start = address(machine, object, offset);
+    i64::from_le_bytes(machine.memory[start..start + 8].try_into().unwrap())
+}
+
+fn store(machine: &mut Machine, object: i64, offset: usize, value: i64) {
+    let start = address(machine, object, offset);
+    machine.memory[start..start + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn allocate(machine: &mut Machine, header: i64, length: usize) -> i64 {
+    let object = machine.heap;
+    machine.heap = machine.heap.wrapping_add(8 * (length as i64 + 1));
+    if machine.heap as usize > machine.memory.len() {
+        let pages = (machine.heap as usize).div_ceil(PAGE);
+        assert!(pages <= MAX_PAGES, "out of memory");
+        machine.memory.resize(pages * PAGE, 0);
+    }
+    store(machine, object, 0, header);
+    object
+}
+
+fn allocate_values(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    kind: &str,
+    header: i64,
+    values: &[usize],
+) -> Flow {
+    let object = allocate(machine, header, values.len());
+    for (i, &value) in values.iter().enumerate() {
+        let value = eval(machine, function, frame, value, false)?;
+        let value = match value {
+            Value::I64(value) => value,
+            Value::F64(_) => panic!("Only i64 values can be stored in a {}, found f64", kind),
+        };
+        store(machine, object, 8 * (i + 1), value);
+    }
+    Ok(Value::I64(object))
+}
+
+fn closure(
+    machine: &mut Machine,
+    function: usize,
+    lambda: Option<usize>,
+    captures: Vec<usize>,
+) -> i64 {
+    let closures = &machine.closures;
+    let existing = (0..closures.functions.len()).find(|&closure| {
+        closures.functions[closure] == function
+            && closures.lambdas[closure] == lambda
+            && closures.captures[closure] == captures
+    });
+    let closure = existing.unwrap_or_else(|| {
+        let closures = &mut machine.closures;
+        closures.functions.push(function);
+        closures.lambdas.push(lambda);
+        closures.captures.push(captures);
+        closures.functions.len() - 1
+    });
+    closure as i64
+}
+
+fn integer_op(op: BinaryOp, left: i64, right: i64) -> i64 {
+    fold_binary_op(op, left, right).unwrap_or_else(|| {
+        assert_ne!(right, 0, "integer divide by zero");
+        panic!("integer overflow")
+    })
+}
+
+fn float_op(op: BinaryOp, left: f64, right: f64) -> Value {
+    match op {
+        BinaryOp::Add => Value::F64(left + right),
+        BinaryOp::Subtract => Value::F64(left - right),
+        BinaryOp::Multiply => Value::F64(left * right),
+        BinaryOp::Divide => Value::F64(left / right),
+        BinaryOp::Equal => Value::I64((left == right) as i64),
+        BinaryOp::NotEqual => Value::I64((left != right) as i64),
+        BinaryOp::LessThan => Value::I64((left < right) as i64),
+        BinaryOp::LessThanEqual => Value::I64((left <= right) as i64),
+        BinaryOp::GreaterThan => Value::I64((left > right) as i64),
+        BinaryOp::GreaterThanEqual => Value::I64((left >= right) as i64),
+        op => panic!("{:?} is not supported for f64", op),
+    }
+}
+
+fn eval_binary_op(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entity: usize,
+) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let op = func.binary_ops.ops[index];
+    let left = func.binary_ops.lefts[index];
+    let right = func.binary_ops.rights[index];
+    let left = eval(machine, function, frame, left, false)?;
+    let right = eval(machine, function, frame, right, false)?;
+    match (left, right) {
+        (Value::I64(left), Value::I64(right)) => Ok(Value::I64(integer_op(op, left, right))),
+        (Value::F64(left), Value::F64(right)) => Ok(float_op(op, left, right)),
+        (left, right) => panic!(
+            "Cannot apply {:?} to {} and {} in {}",
+            op,
+            value_type(left),
+            value_type(right),
+            func.symbols[func.name]
+        ),
+    }
+}
+
+fn eval_assignment(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entity: usize,
+) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let symbol = func.indices[func.assignments.names[index]];
+    let value = eval(
+        machine,
+        function,
+        frame,
+        func.assignments.values[index],
+        false,
+    )?;
+    let slot = machine.slots[function][symbol];
+    let name = &func.symbols[symbol];
+    match (frame[slot], machine.targets[function][symbol]) {
+        (None, Target::Global(global)) => {
+            assert_eq!(
+                ast.globals.kinds[global],
+                GlobalKind::Var,
+                "Cannot assign to constant {}",
+                name
+            );
+            machine.globals[global] = value;
+        }
+        (Some(previous), _) => {
+            assert_eq!(
+                value_type(previous),
+                value_type(value),
+                "{} is {}, cannot assign {}",
+                name,
+                value_type(previous),
+                value_type(value)
+            );
+            frame[slot] = Some(value);
+        }
+        (None, _) => frame[slot] = Some(value),
+    }
+    Ok(Value::I64(0))
+}
+
+fn eval_symbol(machine: &mut Machine, function: usize, frame: &mut Frame, entity: usize) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let symbol = func.indices[entity];
+    let slot = machine.slots[function][symbol];
+    let name = &func.symbols[symbol];
+    if let Some(value) = frame[slot] {
+        return Ok(value);
+    }
+    match machine.targets[function][symbol] {
+        Target::Variant(variant) => {
+            allocate_values(machine, function, frame, "variant", variant.tag as i64, &[])
+        }
+        Target::Function(callee) => {
+            let closure = closure(machine, callee, None, vec![]);
+            Ok(Value::I64(allocate(machine, closure, 0)))
+        }
+        Target::Global(global) => Ok(machine.globals[global]),
+        Target::Unknown if machine.declared[function][slot] => Ok(Value::I64(0)),
+        Target::Unknown => panic!("Unknown name {}", name),
+    }
+}
+
+fn eval_all(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entities: &[usize],
+) -> Result<Vec<Value>, Exit> {
+    entities
+        .iter()
+        .map(|&entity| eval(machine, function, frame, entity, false))
+        .collect()
+}
+
+fn eval_function_call(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entity: usize,
+    tail: bool,
+) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let name = func.function_calls.names[index];
+    let parameters = &func.function_calls.parameters[index];
+    if func.kinds[name] != Kind::Symbol {
+        let closure = eval(machine, function, frame, name, false)?;
+        let arguments = eval_all(machine, function, frame, parameters)?;
+        return Ok(call_closure(machine, int(closure, "callee"), arguments));
+    }
+    let symbol = func.indices[name];
+    let callee = &func.symbols[symbol];
+    if let Some(closure) = frame[machine.slots[function][symbol]] {
+        let arguments = eval_all(machine, function, frame, parameters)?;
+        return Ok(call_closure(machine, int(closure, callee), arguments));
+    }
+    let callee = match machine.targets[function][symbol] {
+        Target::Function(callee) => callee,
+        Target::Variant(variant) => {
+            assert_eq!(
+                variant.fields,
+                parameters.len(),
+                "{} expects {} fields, found {}",
+                callee,
+                variant.fields,
+                parameters.len()
+            );
+            let tag = variant.tag as i64;
+            return allocate_values(machine, function, frame, "variant", tag, parameters);
+        }
+        _ => panic!("Unknown function {}", callee),
+    };
+    let arguments = eval_all(machine, function, frame, parameters)?;
+    match tail {
+        true => Err(Exit::TailCall(callee, arguments)),
+        false => Ok(call_function(machine, callee, arguments)),
+    }
+}
+
+fn eval_block(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    expressions: &[usize],
+    tail: bool,
+) -> Flow {
+    match expressions.split_last() {
+        Some((&last, statements)) => {
+            for &statement in statements {
+                eval(machine, function, frame, statement, false)?;
+            }
+            eval(machine, function, frame, last, tail)
+        }
+        None => Ok(Value::I64(0)),
+    }
+}
+
+fn eval_if(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entity: usize,
+    tail: bool,
+) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let condition = eval(
+        machine,
+        function,
+        frame,
+        func.ifs.conditionals[index],
+        false,
+    )?;
+    let branch = match truthy(condition) {
+        true => &func.ifs.then_branches[index],
+        false => &func.ifs.else_branches[index],
+    };
+    eval_block(machine, function, frame, branch, tail)
+}
+
+fn eval_loop_body(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    body: &[usize],
+) -> Result<bool, Exit> {
+    match eval_block(machine, function, frame, body, false) {
+        Ok(_) | Err(Exit::Continue) => Ok(true),
+        Err(Exit::Break) => Ok(false),
+        Err(exit) => Err(exit),
+    }
+}
+
+fn eval_while(machine: &mut Machine, function: usize, frame: &mut Frame, entity: usize) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    loop {
+        let condition = eval(
+            machine,
+            function,
+            frame,
+            func.whiles.conditionals[index],
+            false,
+        )?;
+        if !truthy(condition)
+            || !eval_loop_body(machine, function, frame, &func.whiles.bodies[index])?
+        {
+            return Ok(Value::I64(0));
+        }
+    }
+}
+
+fn eval_for(machine: &mut Machine, function: usize, frame: &mut Frame, entity: usize) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let variable = machine.slots[function][func.indices[func.fors.variables[index]]];
+    let iterable = func.fors.iterables[index];
+    let body = &func.fors.bodies[index];
+    match range_bounds(func, iterable) {
+        Some((start, end)) => {
+            let start = match start {
+                Some(start) => eval(machine, function, frame, start, false)?,
+                None => Value::I64(0),
+            };
+            frame[variable] = Some(start);
+            let end = int(eval(machine, function, frame, end, false)?, "range");
+            loop {
+                let counter = int(frame[variable].unwrap(), "range");
+                if counter >= end || !eval_loop_body(machine, function, frame, body)? {
+                    return Ok(Value::I64(0));
+                }
+                let counter = int(frame[variable].unwrap(), "range");
+                frame[variable] = Some(Value::I64(counter.wrapping_add(1)));
+            }
+        }
+        None => {
+            let array = int(eval(machine, function, frame, iterable, false)?, "for");
+            let mut counter: i64 = 0;
+            loop {
+                if counter >= load(machine, array, 0) {
+                    return Ok(Value::I64(0));
+                }
+                let element = array.wrapping_add(counter.wrapping_mul(8));
+                frame[variable] = Some(Value::I64(load(machine, element, 8)));
+                if !eval_loop_body(machine, function, frame, body)? {
+                    return Ok(Value::I64(0));
+                }
+                counter = counter.wrapping_add(1);
+            }
+        }
+    }
+}
+
+fn eval_array(machine: &mut Machine, function: usize, frame: &mut Frame, entity: usize) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let elements = &func.arrays[func.indices[entity]];
+    let length = elements.len() as i64;
+    allocate_values(machine, function, frame, "array", length, elements)
+}
+
+fn variant(machine: &Machine, function: usize, symbol: usize) -> Variant {
+    match machine.targets[function][symbol] {
+        Target::Variant(variant) => variant,
+        _ => panic!(
+            "Unknown variant {}",
+            machine.ast.functions[function].symbols[symbol]
+        ),
+    }
+}
+
+fn bind(
+    machine: &Machine,
+    function: usize,
+    frame: &mut Frame,
+    (index, arm): (usize, usize),
+    value: i64,
+    guarded: bool,
+) {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let symbol = match func.matches.patterns[index][arm] {
+        Pattern::Variant(symbol) => symbol,
+        _ => return,
+    };
+    let name = &func.symbols[symbol];
+    let variant = variant(machine, function, symbol);
+    let bindings = &func.matches.bindings[index][arm];
+    assert_eq!(
+        variant.fields,
+        bindings.len(),
+        "{} pattern expects {} fields, found {}",
+        name,
+        variant.fields,
+        bindings.len()
+    );
+    let matches = load(machine, value, 0) == variant.tag as i64;
+    for (i, &binding) in bindings.iter().enumerate() {
+        if func.symbols[binding] == "_" {
+            continue;
+        }
+        let field = match !guarded || matches {
+            true => load(machine, value, 8 * (i + 1)),
+            false => 0,
+        };
+        frame[machine.slots[function][binding]] = Some(Value::I64(field));
+    }
+}
+
+fn test_pattern(machine: &Machine, function: usize, pattern: Pattern, value: i64) -> bool {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let int = |index: usize| func.ints[index].parse::<i64>().unwrap();
+    match pattern {
+        Pattern::Int(literal) => value == int(literal),
+        Pattern::Range(low, high) => int(low) <= value && value < int(high),
+        Pattern::Variant(symbol) => {
+            load(machine, value, 0) == variant(machine, function, symbol).tag as i64
+        }
+        Pattern::Wildcard => true,
+    }
+}
+
+fn select_arm(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    (index, arm): (usize, usize),
+    value: i64,
+) -> Result<usize, Exit> {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    assert!(
+        arm < func.matches.patterns[index].len(),
+        "match in {} is not exhaustive, add a _ arm",
+        func.symbols[func.name]
+    );
+    let guard = func.matches.guards[index][arm];
+    if guard.is_some() {
+        bind(machine, function, frame, (index, arm), value, true);
+    }
+    let pattern = func.matches.patterns[index][arm];
+    let matched = test_pattern(machine, function, pattern, value);
+    let matched = match guard {
+        Some(guard) => truthy(eval(machine, function, frame, guard, false)?) && matched,
+        None => matched,
+    };
+    match matched {
+        true => Ok(arm),
+        false => select_arm(machine, function, frame, (index, arm + 1), value),
+    }
+}
+
+fn eval_match(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entity: usize,
+    tail: bool,
+) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let value = eval(machine, function, frame, func.matches.values[index], false)?;
+    let value = int(value, "match");
+    let arm = select_arm(machine, function, frame, (index, 0), value)?;
+    if func.matches.guards[index][arm].is_none() {
+        bind(machine, function, frame, (index, arm), value, false);
+    }
+    eval_block(
+        machine,
+        function,
+        frame,
+        &func.matches.bodies[index][arm],
+        tail,
+    )
+}
+
+fn eval_lambda(machine: &mut Machine, function: usize, frame: &mut Frame, entity: usize) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    let slots = &machine.slots[function];
+    let parameters = func.lambdas.parameters[index]
+        .iter()
+        .map(|&parameter| slots[parameter])
+        .collect::<Vec<usize>>();
+    let captures = (func.lambdas.starts[index]..entity)
+        .filter(|&entity| func.kinds[entity] == Kind::Symbol)
+        .map(|entity| slots[func.indices[entity]])
+        .fold(vec![], |mut captures: Vec<usize>, slot| {
+            if !captures.contains(&slot) && !parameters.contains(&slot) && frame[slot].is_some() {
+                captures.push(slot);
+            }
+            captures
+        });
+    let values = captures
+        .iter()
+        .map(|&slot| match frame[slot] {
+            Some(Value::I64(value)) => value,
+            _ => panic!("Closures can only capture i64 values"),
+        })
+        .collect::<Vec<i64>>();
+    let closure = closure(machine, function, Some(index), captures);
+    let object = allocate(machine, closure, values.len());
+    for (i, value) in values.into_iter().enumerate() {
+        store(machine, object, 8 * (i + 1), value);
+    }
+    Ok(Value::I64(object))
+}
+
+fn eval(
+    machine: &mut Machine,
+    function: usize,
+    frame: &mut Frame,
+    entity: usize,
+    tail: bool,
+) -> Flow {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let index = func.indices[entity];
+    match func.kinds[entity] {
+        Kind::Int => Ok(Value::I64(func.ints[index].parse().unwrap_or_else(|_| {
+            func.ints[index]
+                .parse::<u64>()
+                .unwrap_or_else(|_| panic!("Invalid int literal {}", func.ints[index]))
+                as i64
+        }))),
+        Kind::Float => Ok(Value::F64(func.floats[index].parse().unwrap_or_else(
+            |_| panic!("Invalid float literal {}", func.floats[index]),
+        ))),
+        Kind::BinaryOp => eval_binary_op(machine, function, frame, entity),
+        Kind::Assign => eval_assignment(machine, function, frame, entity),
+        Kind::Symbol => eval_symbol(machine, function, frame, entity),
+        Kind::FunctionCall => eval_function_call(machine, function, frame, entity, tail),
+        Kind::If => eval_if(machine, function, frame, entity, tail),
+        Kind::While => eval_while(machine, function, frame, entity),
+        Kind::For => eval_for(machine, function, frame, entity),
+        Kind::Array => eval_array(machine, function, frame, entity),
+        Kind::Match => eval_match(machine, function, frame, entity, tail),
+        Kind::Lambda => eval_lambda(machine, function, frame, entity),
+        Kind::Grouping => eval(machine, function, frame, func.groupings[index], tail),
+        Kind::Break => Err(Exit::Break),
+        Kind::Continue => Err(Exit::Continue),
+        Kind::Return => Err(Exit::Return(eval(
+            machine,
+            function,
+            frame,
+            func.returns[index],
+            true,
+        )?)),
+    }
+}
+
+fn finish(function: &str, flow: Flow) -> Result<Value, (usize, Vec<Value>)> {
+    match flow {
+        Ok(value) | Err(Exit::Return(value)) => Ok(value),
+        Err(Exit::TailCall(callee, arguments)) => Err((callee, arguments)),
+        Err(Exit::Break) => panic!("break can only be used inside a loop in {}", function),
+        Err(Exit::Continue) => panic!("continue can only be used inside a loop in {}", function),
+    }
+}
+
+fn frame(machine: &Machine, function: usize, parameters: &[usize], arguments: Vec<Value>) -> Frame {
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    assert_eq!(
+        parameters.len(),
+        arguments.len(),
+        "{} expects {} arguments, found {}",
+        func.symbols[func.name],
+        parameters.len(),
+        arguments.len()
+    );
+    let slots = &machine.slots[function];
+    parameters.iter().zip(arguments).fold(
+        vec![None; machine.declared[function].len()],
+        |mut frame, (&parameter, argument)| {
+            frame[slots[parameter]] = Some(argument);
+            frame
+        },
+    )
+}
+
+fn enter(machine: &mut Machine) {
+    machine.depth += 1;
+    assert!(machine.depth <= MAX_DEPTH, "call stack exhausted");
+}
+
+fn call_function(machine: &mut Machine, function: usize, arguments: Vec<Value>) -> Value {
+    enter(machine);
+    let value = run_function(machine, function, arguments);
+    machine.depth -= 1;
+    value
+}
+
+fn run_function(machine: &mut Machine, function: usize, arguments: Vec<Value>) -> Value {
+    let ast = machine.ast;
+    let (mut function, mut arguments) = (function, arguments);
+    loop {
+        let func = &ast.functions[function];
+        let mut frame = frame(machine, function, &func.arguments, arguments);
+        let flow = eval_block(machine, function, &mut frame, &func.expressions, true);
+        match finish(&func.symbols[func.name], flow) {
+            Ok(value) => return value,
+            Err((callee, next)) => {
+                function = callee;
+                arguments = next;
+            }
+        }
+    }
+}
+
+fn call_closure(machine: &mut Machine, object: i64, arguments: Vec<Value>) -> Value {
+    let closure = load(machine, object, 0) as usize;
+    assert!(
+        closure < machine.closures.functions.len(),
+        "undefined element in the function table"
+    );
+    let function = machine.closures.functions[closure];
+    let lambda = match machine.closures.lambdas[closure] {
+        Some(lambda) => lambda,
+        None => return call_function(machine, function, arguments),
+    };
+    let ast = machine.ast;
+    let func = &ast.functions[function];
+    let parameters = &func.lambdas.parameters[lambda];
+    assert_eq!(
+        parameters.len(),
+        arguments.len(),
+        "indirect call type mismatch"
+    );
+    let mut frame = frame(machine, function, parameters, arguments);
+    for (i, &slot) in machine.closures.captures[closure].iter().enumerate() {
+        frame[slot] = Some(Value::I64(load(machine, object, 8 * (i + 1))));
+    }
+    enter(machine);
+    let flow = eval_block(
+        machine,
+        function,
+        &mut frame,
+        &func.lambdas.bodies[lambda],
+        false,
+    );
+    machine.depth -= 1;
+    match finish(&func.symbols[func.name], flow) {
+        Ok(value) => value,
+        Err((callee, arguments)) => call_function(machine, callee, arguments),
+    }
+}
+
+fn blocks<'a>(
+    func: &'a parser::Function,
+    statements: &'a HashSet<usize>,
+) -> impl Iterator<Item = (&'a [usize], bool)> {
+    let entities =
+        move |kind| (0..func.kinds.len()).filter(move |&entity| func.kinds[entity] == kind);
+    let ifs = entities(Kind::If).flat_map(move |entity| {
+        let index = func.indices[entity];
+        let value = !statements.contains(&entity);
+        vec![
+            (&func.ifs.then_branches[index][..], value),
+            (&func.ifs.else_branches[index][..], value),
+        ]
+    });
+    let matches = entities(Kind::Match).flat_map(move |entity| {
+        let value = !statements.contains(&entity);
+        func.matches.bodies[func.indices[entity]]
+            .iter()
+            .map(move |body| (&body[..], value))
+    });
+    std::iter::once((&func.expressions[..], true))
+        .chain(func.whiles.bodies.iter().map(|body| (&body[..], false)))
+        .chain(func.fors.bodies.iter().map(|body| (&body[..], false)))
+        .chain(func.lambdas.bodies.iter().map(|body| (&body[..], true)))
+        .chain(ifs)
+        .chain(matches)
+}
+
+fn statements(func: &parser::Function, current: HashSet<usize>) -> HashSet<usize> {
+    let next = blocks(func, &current)
+        .flat_map(|(block, value)| {
+            let length = match value {
+                true => block.len().saturating_sub(1),
+                false => block.len(),
+            };
+            block[..length].iter().copied()
+        })
+        .collect::<HashSet<usize>>();
+    match next == current {
+        true => current,
+        false => statements(func, next),
+    }
+}
+
+fn reachable(targets: &[Vec<Target>], function: usize, mut reached: Vec<bool>) -> Vec<bool> {
+    if reached[function] {
+        return reached;
+    }
+    reached[function] = true;
+    targets[function]
+        .iter()
+        .fold(reached, |reached, target| match target {
+            Target::Function(callee) => reachable(targets, *callee, reached),
+            _ => reached,
+        })
+}
+
+fn check(machine: &Machine, start: usize) {
+    let ast = machine.ast;
+    let variants = variants(&ast.enums);
+    let reached = reachable(&machine.targets, start, vec![false; ast.functions.len()]);
+    for function in (0..ast.functions.len()).filter(|&function| reached[function]) {
+        let func = &ast.functions[function];
+        let statements = statements(func, HashSet::new());
+        for entity in 0..func.kinds.len() {
+            match func.kinds[entity] {
+                Kind::If => check_if(func, func.indices[entity], !statements.contains(&entity)),
+                Kind::Match => check_match(&variants, func, func.indices[entity]),
+                _ => {}
+            }
+        }
+    }
+}
+
+pub fn interpret(ast: &Ast) -> Value {
+    let start = *ast
+        .top_level
+        .get("start")
+        .expect("No start function to interpret");
+    let mut machine = machine(ast);
+    check(&machine, start);
+    thread::scope(|scope| {
+        let interpreter = thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, || call_function(&mut machine, start, vec![]))
+            .unwrap();
+        interpreter
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
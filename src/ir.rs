@@ -0,0 +1,1176 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    codegen::{
+        self, fold_binary_op, get_local, i64_const, i64_instruction, instruction, labelled,
+        set_local, symbol, Instruction, OperandKind, Type, Wasm,
+    },
+    parser::{self, Ast, BinaryOp},
+};
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Kind {
+    Int,
+    Parameter,
+    BinaryOp,
+    Call,
+    Phi,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BinaryOps {
+    pub ops: Vec<BinaryOp>,
+    pub lefts: Vec<usize>,
+    pub rights: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Calls {
+    pub names: Vec<String>,
+    pub arguments: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Values {
+    pub kinds: Vec<Kind>,
+    pub indices: Vec<usize>,
+    pub blocks: Vec<usize>,
+    pub ints: Vec<i64>,
+    pub binary_ops: BinaryOps,
+    pub calls: Calls,
+    pub phis: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Terminator {
+    Jump(usize),
+    Branch(usize, usize, usize),
+    Return(usize),
+    Unreachable,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Blocks {
+    pub values: Vec<Vec<usize>>,
+    pub terminators: Vec<Terminator>,
+    pub predecessors: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arguments: Vec<String>,
+    pub values: Values,
+    pub blocks: Blocks,
+}
+
+pub type Pass = fn(Function) -> Function;
+
+pub struct PassManager {
+    pub names: Vec<&'static str>,
+    pub passes: Vec<Pass>,
+}
+
+struct Builder {
+    func: Function,
+    block: usize,
+    definitions: Vec<HashMap<String, usize>>,
+    sealed: Vec<bool>,
+    incomplete: Vec<Vec<(String, usize)>>,
+}
+
+fn variables(ast_func: &parser::Function) -> HashSet<String> {
+    ast_func
+        .arguments
+        .iter()
+        .chain(
+            ast_func
+                .assignments
+                .names
+                .iter()
+                .map(|&name| &ast_func.indices[name]),
+        )
+        .map(|&symbol| ast_func.symbols[symbol].clone())
+        .collect()
+}
+
+fn supported(
+    ast_func: &parser::Function,
+    functions: &HashSet<String>,
+    globals: &HashSet<String>,
+) -> bool {
+    let variables = variables(ast_func);
+    let names = ast_func
+        .function_calls
+        .names
+        .iter()
+        .chain(&ast_func.assignments.names)
+        .collect::<HashSet<&usize>>();
+    let symbol = |entity: usize| &ast_func.symbols[ast_func.indices[entity]];
+    ast_func.type_parameters.is_empty()
+        && ast_func.argument_types.iter().all(Option::is_none)
+        && ast_func.return_type.is_none()
+        && (0..ast_func.kinds.len()).all(|entity| match ast_func.kinds[entity] {
+            parser::Kind::Int
+            | parser::Kind::BinaryOp
+            | parser::Kind::Grouping
+            | parser::Kind::If
+            | parser::Kind::While => true,
+            parser::Kind::Symbol => names.contains(&entity) || variables.contains(symbol(entity)),
+            parser::Kind::Assign => {
+                !globals.contains(symbol(ast_func.assignments.names[ast_func.indices[entity]]))
+            }
+            parser::Kind::FunctionCall => {
+                let callee = symbol(ast_func.function_calls.names[ast_func.indices[entity]]);
+                functions.contains(callee) && !variables.contains(callee)
+            }
+            _ => false,
+        })
+}
+
+fn push_value(mut b: Builder, block: usize, kind: Kind, index: usize) -> (Builder, usize) {
+    let value = b.func.values.kinds.len();
+    b.func.values.kinds.push(kind);
+    b.func.values.indices.push(index);
+    b.func.values.blocks.push(block);
+    b.func.blocks.values[block].push(value);
+    (b, value)
+}
+
+fn int(mut b: Builder, block: usize, int: i64) -> (Builder, usize) {
+    let index = b.func.values.ints.len();
+    b.func.values.ints.push(int);
+    push_value(b, block, Kind::Int, index)
+}
+
+fn phi(mut b: Builder, block: usize, operands: Vec<usize>) -> (Builder, usize) {
+    let index = b.func.values.phis.len();
+    b.func.values.phis.push(operands);
+    push_value(b, block, Kind::Phi, index)
+}
+
+fn new_block(mut b: Builder) -> (Builder, usize) {
+    let block = b.func.blocks.values.len();
+    b.func.blocks.values.push(vec![]);
+    b.func.blocks.terminators.push(Terminator::Unreachable);
+    b.func.blocks.predecessors.push(vec![]);
+    b.definitions.push(HashMap::new());
+    b.sealed.push(false);
+    b.incomplete.push(vec![]);
+    (b, block)
+}
+
+fn terminate(mut b: Builder, terminator: Terminator) -> Builder {
+    b.func.blocks.terminators[b.block] = terminator;
+    let successors = match terminator {
+        Terminator::Jump(target) => vec![target],
+        Terminator::Branch(_, then, otherwise) => vec![then, otherwise],
+        Terminator::Return(_) | Terminator::Unreachable => vec![],
+    };
+    for successor in successors {
+        b.func.blocks.predecessors[successor].push(b.block);
+    }
+    b
+}
+
+fn write_variable(mut b: Builder, name: &str, block: usize, value: usize) -> Builder {
+    b.definitions[block].insert(name.to_string(), value);
+    b
+}
+
+fn read_variable(b: Builder, name: &str, block: usize) -> (Builder, usize) {
+    if let Some(&value) = b.definitions[block].get(name) {
+        return (b, value);
+    }
+    let predecessors = b.func.blocks.predecessors[block].clone();
+    let (b, value) = match (b.sealed[block], &predecessors[..]) {
+        (false, _) => {
+            let (mut b, value) = phi(b, block, vec![]);
+            b.incomplete[block].push((name.to_string(), value));
+            (b, value)
+        }
+        (true, []) => int(b, block, 0),
+        (true, &[predecessor]) => read_variable(b, name, predecessor),
+        (true, _) => {
+            let (b, value) = phi(b, block, vec![]);
+            let b = write_variable(b, name, block, value);
+            (phi_operands(b, name, value), value)
+        }
+    };
+    (write_variable(b, name, block, value), value)
+}
+
+fn phi_operands(b: Builder, name: &str, value: usize) -> Builder {
+    let block = b.func.values.blocks[value];
+    let predecessors = b.func.blocks.predecessors[block].clone();
+    predecessors.into_iter().fold(b, |b, predecessor| {
+        let (mut b, operand) = read_variable(b, name, predecessor);
+        let index = b.func.values.indices[value];
+        b.func.values.phis[index].push(operand);
+        b
+    })
+}
+
+fn seal(mut b: Builder, block: usize) -> Builder {
+    let incomplete = std::mem::take(&mut b.incomplete[block]);
+    let mut b = incomplete
+        .into_iter()
+        .fold(b, |b, (name, value)| phi_operands(b, &name, value));
+    b.sealed[block] = true;
+    b
+}
+
+fn build_if(
+    b: Builder,
+    ast_func: &parser::Function,
+    entity: usize,
+    result: bool,
+) -> Option<(Builder, Option<usize>)> {
+    let index = ast_func.indices[entity];
+    let (b, condition) = build_value(b, ast_func, ast_func.ifs.conditionals[index])?;
+    let (b, then) = new_block(b);
+    let (b, otherwise) = new_block(b);
+    let (b, join) = new_block(b);
+    let b = terminate(b, Terminator::Branch(condition, then, otherwise));
+    let b = seal(seal(b, then), otherwise);
+    let (b, values) = [
+        (then, &ast_func.ifs.then_branches[index]),
+        (otherwise, &ast_func.ifs.else_branches[index]),
+    ]
+    .iter()
+    .try_fold((b, vec![]), |(mut b, mut values), &(block, branch)| {
+        b.block = block;
+        let (b, value) = build_block(b, ast_func, branch, result)?;
+        values.push(value);
+        Some((terminate(b, Terminator::Jump(join)), values))
+    })?;
+    let mut b = seal(b, join);
+    b.block = join;
+    match result {
+        true => {
+            let values = values.into_iter().collect::<Option<Vec<usize>>>()?;
+            let (b, value) = phi(b, join, values);
+            Some((b, Some(value)))
+        }
+        false => Some((b, None)),
+    }
+}
+
+fn build_while(b: Builder, ast_func: &parser::Function, entity: usize) -> Option<Builder> {
+    let index = ast_func.indices[entity];
+    let (b, header) = new_block(b);
+    let mut b = terminate(b, Terminator::Jump(header));
+    b.block = header;
+    let (b, condition) = build_value(b, ast_func, ast_func.whiles.conditionals[index])?;
+    let (b, body) = new_block(b);
+    let (b, exit) = new_block(b);
+    let mut b = seal(
+        seal(
+            terminate(b, Terminator::Branch(condition, body, exit)),
+            body,
+        ),
+        exit,
+    );
+    b.block = body;
+    let (b, _) = build_block(b, ast_func, &ast_func.whiles.bodies[index], false)?;
+    let mut b = seal(terminate(b, Terminator::Jump(header)), header);
+    b.block = exit;
+    Some(b)
+}
+
+fn build_value(b: Builder, ast_func: &parser::Function, entity: usize) -> Option<(Builder, usize)> {
+    let (b, value) = build_expression(b, ast_func, entity)?;
+    Some((b, value?))
+}
+
+fn build_expression(
+    b: Builder,
+    ast_func: &parser::Function,
+    entity: usize,
+) -> Option<(Builder, Option<usize>)> {
+    let index = ast_func.indices[entity];
+    let block = b.block;
+    match ast_func.kinds[entity] {
+        parser::Kind::Int => {
+            let (b, value) = int(b, block, ast_func.ints[index].parse().ok()?);
+            Some((b, Some(value)))
+        }
+        parser::Kind::Symbol => {
+            let (b, value) = read_variable(b, &ast_func.symbols[index], block);
+            Some((b, Some(value)))
+        }
+        parser::Kind::Assign => {
+            let name = &ast_func.symbols[ast_func.indices[ast_func.assignments.names[index]]];
+            let (b, value) = build_value(b, ast_func, ast_func.assignments.values[index])?;
+            let block = b.block;
+            Some((write_variable(b, name, block, value), None))
+        }
+        parser::Kind::BinaryOp => {
+            let (b, left) = build_value(b, ast_func, ast_func.binary_ops.lefts[index])?;
+            let (mut b, right) = build_value(b, ast_func, ast_func.binary_ops.rights[index])?;
+            let binary_op = b.func.values.binary_ops.ops.len();
+            b.func
+                .values
+                .binary_ops
+                .ops
+                .push(ast_func.binary_ops.ops[index]);
+            b.func.values.binary_ops.lefts.push(left);
+            b.func.values.binary_ops.rights.push(right);
+            let block = b.block;
+            let (b, value) = push_value(b, block, Kind::BinaryOp, binary_op);
+            Some((b, Some(value)))
+        }
+        parser::Kind::FunctionCall => {
+            let name = &ast_func.symbols[ast_func.indices[ast_func.function_calls.names[index]]];
+            let (mut b, arguments) = ast_func.function_calls.parameters[index].iter().try_fold(
+                (b, vec![]),
+                |(b, mut arguments), &parameter| {
+                    let (b, argument) = build_value(b, ast_func, parameter)?;
+                    arguments.push(argument);
+                    Some((b, arguments))
+                },
+            )?;
+            let call = b.func.values.calls.names.len();
+            b.func.values.calls.names.push(name.clone());
+            b.func.values.calls.arguments.push(arguments);
+            let block = b.block;
+            let (b, value) = push_value(b, block, Kind::Call, call);
+            Some((b, Some(value)))
+        }
+        parser::Kind::Grouping => build_expression(b, ast_func, ast_func.groupings[index]),
+        parser::Kind::If => {
+            let result = !ast_func.ifs.else_branches[index].is_empty();
+            build_if(b, ast_func, entity, result)
+        }
+        parser::Kind::While => Some((build_while(b, ast_func, entity)?, None)),
+        _ => None,
+    }
+}
+
+fn build_block(
+    b: Builder,
+    ast_func: &parser::Function,
+    expressions: &[usize],
+    result: bool,
+) -> Option<(Builder, Option<usize>)> {
+    let (last, statements) = match (result, expressions.split_last()) {
+        (true, Some((&last, statements))) => (Some(last), statements),
+        _ => (None, expressions),
+    };
+    let b = statements
+        .iter()
+        .try_fold(b, |b, &statement| match ast_func.kinds[statement] {
+            parser::Kind::If => Some(build_if(b, ast_func, statement, false)?.0),
+            _ => Some(build_expression(b, ast_func, statement)?.0),
+        })?;
+    match last {
+        Some(last) => build_expression(b, ast_func, last),
+        None => Some((b, None)),
+    }
+}
+
+pub fn build(
+    ast_func: &parser::Function,
+    functions: &HashSet<String>,
+    globals: &HashSet<String>,
+) -> Option<Function> {
+    if !supported(ast_func, functions, globals) {
+        return None;
+    }
+    let arguments = ast_func
+        .arguments
+        .iter()
+        .map(|&argument| ast_func.symbols[argument].clone())
+        .collect::<Vec<String>>();
+    let b = Builder {
+        func: Function {
+            name: ast_func.symbols[ast_func.name].clone(),
+            arguments: arguments.clone(),
+            values: Values {
+                kinds: vec![],
+                indices: vec![],
+                blocks: vec![],
+                ints: vec![],
+                binary_ops: BinaryOps {
+                    ops: vec![],
+                    lefts: vec![],
+                    rights: vec![],
+                },
+                calls: Calls {
+                    names: vec![],
+                    arguments: vec![],
+                },
+                phis: vec![],
+            },
+            blocks: Blocks {
+                values: vec![],
+                terminators: vec![],
+                predecessors: vec![],
+            },
+        },
+        block: 0,
+        definitions: vec![],
+        sealed: vec![],
+        incomplete: vec![],
+    };
+    let (b, entry) = new_block(b);
+    let b = seal(b, entry);
+    let b = arguments.iter().enumerate().fold(b, |b, (i, argument)| {
+        let (b, value) = push_value(b, entry, Kind::Parameter, i);
+        write_variable(b, argument, entry, value)
+    });
+    let (b, value) = build_block(b, ast_func, &ast_func.expressions, true)?;
+    let (b, value) = match value {
+        Some(value) => (b, value),
+        None => {
+            let block = b.block;
+            int(b, block, 0)
+        }
+    };
+    Some(terminate(b, Terminator::Return(value)).func)
+}
+
+fn successors(func: &Function, block: usize) -> Vec<usize> {
+    match func.blocks.terminators[block] {
+        Terminator::Jump(target) => vec![target],
+        Terminator::Branch(_, then, otherwise) => vec![then, otherwise],
+        Terminator::Return(_) | Terminator::Unreachable => vec![],
+    }
+}
+
+fn operands(func: &Function, value: usize) -> Vec<usize> {
+    let index = func.values.indices[value];
+    match func.values.kinds[value] {
+        Kind::Int | Kind::Parameter => vec![],
+        Kind::BinaryOp => vec![
+            func.values.binary_ops.lefts[index],
+            func.values.binary_ops.rights[index],
+        ],
+        Kind::Call => func.values.calls.arguments[index].clone(),
+        Kind::Phi => func.values.phis[index].clone(),
+    }
+}
+
+fn uses(func: &Function, value: usize) -> usize {
+    let values = func
+        .blocks
+        .values
+        .iter()
+        .flatten()
+        .flat_map(|&other| operands(func, other))
+        .filter(|&operand| operand == value)
+        .count();
+    let terminators = func
+        .blocks
+        .terminators
+        .iter()
+        .filter(|terminator| match terminator {
+            Terminator::Branch(condition, _, _) => *condition == value,
+            Terminator::Return(result) => *result == value,
+            _ => false,
+        })
+        .count();
+    values + terminators
+}
+
+fn replace_uses(mut func: Function, old: usize, new: usize) -> Function {
+    let replace = |operand: &mut usize| {
+        if *operand == old {
+            *operand = new;
+        }
+    };
+    func.values.binary_ops.lefts.iter_mut().for_each(replace);
+    func.values.binary_ops.rights.iter_mut().for_each(replace);
+    func.values
+        .calls
+        .arguments
+        .iter_mut()
+        .flatten()
+        .for_each(replace);
+    func.values.phis.iter_mut().flatten().for_each(replace);
+    for terminator in func.blocks.terminators.iter_mut() {
+        match terminator {
+            Terminator::Branch(condition, _, _) => replace(condition),
+            Terminator::Return(result) => replace(result),
+            _ => {}
+        }
+    }
+    func
+}
+
+fn remove_value(mut func: Function, value: usize) -> Function {
+    let block = func.values.blocks[value];
+    func.blocks.values[block].retain(|&other| other != value);
+    func
+}
+
+fn phis(func: &Function, block: usize) -> Vec<usize> {
+    func.blocks.values[block]
+        .iter()
+        .copied()
+        .filter(|&value| func.values.kinds[value] == Kind::Phi)
+        .collect()
+}
+
+fn remove_edge(mut func: Function, from: usize, to: usize) -> Function {
+    let position = func.blocks.predecessors[to]
+        .iter()
+        .position(|&predecessor| predecessor == from)
+        .unwrap();
+    func.blocks.predecessors[to].remove(position);
+    for value in phis(&func, to) {
+        let index = func.values.indices[value];
+        func.values.phis[index].remove(position);
+    }
+    func
+}
+
+fn constant(func: &Function, value: usize) -> Option<i64> {
+    match func.values.kinds[value] {
+        Kind::Int => Some(func.values.ints[func.values.indices[value]]),
+        _ => None,
+    }
+}
+
+pub fn fold_constants(func: Function) -> Function {
+    let live = func
+        .blocks
+        .values
+        .iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<usize>>();
+    let func = live.into_iter().fold(func, |mut func, value| {
+        if func.values.kinds[value] != Kind::BinaryOp {
+            return func;
+        }
+        let index = func.values.indices[value];
+        let left = constant(&func, func.values.binary_ops.lefts[index]);
+        let right = constant(&func, func.values.binary_ops.rights[index]);
+        let op = func.values.binary_ops.ops[index];
+        if let Some(folded) = left.zip(right).and_then(|(l, r)| fold_binary_op(op, l, r)) {
+            func.values.kinds[value] = Kind::Int;
+            func.values.indices[value] = func.values.ints.len();
+            func.values.ints.push(folded);
+        }
+        func
+    });
+    (0..func.blocks.terminators.len()).fold(func, |mut func, block| {
+        match func.blocks.terminators[block] {
+            Terminator::Branch(condition, then, otherwise) => match constant(&func, condition) {
+                Some(condition) => {
+                    let (taken, skipped) = match condition != 0 {
+                        true => (then, otherwise),
+                        false => (otherwise, then),
+                    };
+                    func.blocks.terminators[block] = Terminator::Jump(taken);
+                    match taken == skipped {
+                        true => func,
+                        false => remove_edge(func, block, skipped),
+                    }
+                }
+                None => func,
+            },
+            _ => func,
+        }
+    })
+}
+
+fn reachable(func: &Function) -> HashSet<usize> {
+    fn visit(func: &Function, block: usize, visited: HashSet<usize>) -> HashSet<usize> {
+        successors(func, block)
+            .into_iter()
+            .fold(visited, |mut visited, successor| {
+                match visited.insert(successor) {
+                    true => visit(func, successor, visited),
+                    false => visited,
+                }
+            })
+    }
+    visit(func, 0, [0].iter().copied().collect())
+}
+
+pub fn remove_unreachable_blocks(func: Function) -> Function {
+    let reachable = reachable(&func);
+    (0..func.blocks.values.len()).fold(func, |func, block| {
+        let dead = func.blocks.predecessors[block]
+            .iter()
+            .copied()
+            .filter(|predecessor| !reachable.contains(predecessor))
+            .collect::<Vec<usize>>();
+        let mut func = dead.into_iter().fold(func, |func, predecessor| {
+            remove_edge(func, predecessor, block)
+        });
+        if !reachable.contains(&block) {
+            func.blocks.values[block].clear();
+            func.blocks.terminators[block] = Terminator::Unreachable;
+        }
+        func
+    })
+}
+
+pub fn remove_trivial_phis(func: Function) -> Function {
+    let trivial = func.blocks.values.iter().flatten().find_map(|&value| {
+        if func.values.kinds[value] != Kind::Phi {
+            return None;
+        }
+        let mut operands = operands(&func, value);
+        operands.retain(|&operand| operand != value);
+        operands.dedup();
+        match operands[..] {
+            [operand] => Some((value, operand)),
+            _ => None,
+        }
+    });
+    match trivial {
+        Some((value, operand)) => {
+            remove_trivial_phis(remove_value(replace_uses(func, value, operand), value))
+        }
+        None => func,
+    }
+}
+
+fn pure(func: &Function, value: usize) -> bool {
+    match func.values.kinds[value] {
+        Kind::Int | Kind::Parameter | Kind::Phi => true,
+        Kind::BinaryOp => !matches!(
+            func.values.binary_ops.ops[func.values.indices[value]],
+            BinaryOp::Divide | BinaryOp::Modulo
+        ),
+        Kind::Call => false,
+    }
+}
+
+pub fn eliminate_dead_values(func: Function) -> Function {
+    let dead = func
+        .blocks
+        .values
+        .iter()
+        .flatten()
+        .copied()
+        .find(|&value| pure(&func, value) && uses(&func, value) == 0);
+    match dead {
+        Some(value) => eliminate_dead_values(remove_value(func, value)),
+        None => func,
+    }
+}
+
+pub fn passes() -> PassManager {
+    PassManager {
+        names: vec![],
+        passes: vec![],
+    }
+}
+
+pub fn add_pass(mut manager: PassManager, name: &'static str, pass: Pass) -> PassManager {
+    manager.names.push(name);
+    manager.passes.push(pass);
+    manager
+}
+
+pub fn default_passes() -> PassManager {
+    let manager = add_pass(passes(), "fold-constants", fold_constants);
+    let manager = add_pass(
+        manager,
+        "remove-unreachable-blocks",
+        remove_unreachable_blocks,
+    );
+    let manager = add_pass(manager, "remove-trivial-phis", remove_trivial_phis);
+    add_pass(manager, "eliminate-dead-values", eliminate_dead_values)
+}
+
+pub fn run_passes(manager: &PassManager, func: Function) -> Function {
+    let optimized = manager
+        .passes
+        .iter()
+        .fold(func.clone(), |func, pass| pass(func));
+    match optimized == func {
+        true => optimized,
+        false => run_passes(manager, optimized),
+    }
+}
+
+struct Cfg {
+    order: Vec<usize>,
+    numbers: Vec<usize>,
+    children: Vec<Vec<usize>>,
+    loop_headers: Vec<bool>,
+    merges: Vec<bool>,
+}
+
+fn postorder(func: &Function, block: usize, visited: &mut HashSet<usize>, order: &mut Vec<usize>) {
+    visited.insert(block);
+    for successor in successors(func, block) {
+        if !visited.contains(&successor) {
+            postorder(func, successor, visited, order);
+        }
+    }
+    order.push(block);
+}
+
+fn intersect(idoms: &[usize], numbers: &[usize], a: usize, b: usize) -> usize {
+    match numbers[a].cmp(&numbers[b]) {
+        std::cmp::Ordering::Equal => a,
+        std::cmp::Ordering::Greater => intersect(idoms, numbers, idoms[a], b),
+        std::cmp::Ordering::Less => intersect(idoms, numbers, a, idoms[b]),
+    }
+}
+
+fn dominators(func: &Function, order: &[usize], numbers: &[usize]) -> Vec<usize> {
+    let mut idoms = vec![usize::MAX; func.blocks.values.len()];
+    idoms[0] = 0;
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &block in order.iter().skip(1) {
+            let idom = func.blocks.predecessors[block]
+                .iter()
+                .copied()
+                .filter(|&predecessor| idoms[predecessor] != usize::MAX)
+                .reduce(|a, b| intersect(&idoms, numbers, a, b))
+                .unwrap();
+            if idoms[block] != idom {
+                idoms[block] = idom;
+                changed = true;
+            }
+        }
+    }
+    idoms
+}
+
+fn cfg(func: &Function) -> Cfg {
+    let mut order = vec![];
+    postorder(func, 0, &mut HashSet::new(), &mut order);
+    order.reverse();
+    let mut numbers = vec![usize::MAX; func.blocks.values.len()];
+    for (number, &block) in order.iter().enumerate() {
+        numbers[block] = number;
+    }
+    let idoms = dominators(func, &order, &numbers);
+    let mut children = vec![vec![]; func.blocks.values.len()];
+    for &block in order.iter().skip(1) {
+        children[idoms[block]].push(block);
+    }
+    let forward = |block: usize| {
+        func.blocks.predecessors[block]
+            .iter()
+            .filter(|&&predecessor| numbers[predecessor] < numbers[block])
+            .count()
+    };
+    let loop_headers = (0..func.blocks.values.len())
+        .map(|block| {
+            func.blocks.predecessors[block]
+                .iter()
+                .any(|&predecessor| numbers[predecessor] >= numbers[block])
+        })
+        .collect();
+    let merges = (0..func.blocks.values.len())
+        .map(|block| forward(block) >= 2)
+        .collect();
+    Cfg {
+        order,
+        numbers,
+        children,
+        loop_headers,
+        merges,
+    }
+}
+
+fn value_type(func: &Function, value: usize) -> Type {
+    match func.values.kinds[value] {
+        Kind::BinaryOp => match func.values.binary_ops.ops[func.values.indices[value]] {
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::LessThan
+            | BinaryOp::LessThanEqual
+            | BinaryOp::GreaterThan
+            | BinaryOp::GreaterThanEqual => Type::I32,
+            _ => Type::I64,
+        },
+        _ => Type::I64,
+    }
+}
+
+struct Lowering<'a> {
+    func: &'a Function,
+    cfg: Cfg,
+    locals: HashMap<usize, usize>,
+}
+
+fn lower_value(
+    wasm_func: codegen::Function,
+    l: &Lowering,
+    value: usize,
+    to: Type,
+) -> codegen::Function {
+    let func = l.func;
+    let wasm_func = match func.values.kinds[value] {
+        Kind::Int => i64_const(wasm_func, func.values.ints[func.values.indices[value]]),
+        Kind::Parameter => get_local(wasm_func, func.values.indices[value]),
+        _ => get_local(wasm_func, l.locals[&value]),
+    };
+    match (value_type(func, value), to) {
+        (Type::I32, Type::I64) => instruction(wasm_func, Instruction::I64ExtendI32U),
+        _ => wasm_func,
+    }
+}
+
+fn lower_condition(wasm_func: codegen::Function, l: &Lowering, value: usize) -> codegen::Function {
+    match value_type(l.func, value) {
+        Type::I32 => lower_value(wasm_func, l, value, Type::I32),
+        _ => {
+            let wasm_func = lower_value(wasm_func, l, value, Type::I64);
+            let wasm_func = i64_const(wasm_func, 0);
+            instruction(wasm_func, Instruction::I64Neq)
+        }
+    }
+}
+
+fn lower_definition(wasm_func: codegen::Function, l: &Lowering, value: usize) -> codegen::Function {
+    let func = l.func;
+    let index = func.values.indices[value];
+    let wasm_func = match func.values.kinds[value] {
+        Kind::Int | Kind::Parameter | Kind::Phi => return wasm_func,
+        Kind::BinaryOp => {
+            let wasm_func =
+                lower_value(wasm_func, l, func.values.binary_ops.lefts[index], Type::I64);
+            let wasm_func = lower_value(
+                wasm_func,
+                l,
+                func.values.binary_ops.rights[index],
+                Type::I64,
+            );
+            instruction(
+                wasm_func,
+                i64_instruction(func.values.binary_ops.ops[index]),
+            )
+        }
+        Kind::Call => {
+            let wasm_func = func.values.calls.arguments[index]
+                .iter()
+                .fold(wasm_func, |wasm_func, &argument| {
+                    lower_value(wasm_func, l, argument, Type::I64)
+                });
+            let (mut wasm_func, name) = symbol(wasm_func, &func.values.calls.names[index]);
+            wasm_func.instructions.push(Instruction::Call);
+            wasm_func
+                .operand_kinds
+                .push(vec![codegen::OperandKind::Symbol]);
+            wasm_func.operands.push(vec![name]);
+            wasm_func
+        }
+    };
+    set_local(wasm_func, l.locals[&value])
+}
+
+fn lower_copies(
+    wasm_func: codegen::Function,
+    l: &Lowering,
+    from: usize,
+    to: usize,
+) -> codegen::Function {
+    let func = l.func;
+    let phis = phis(func, to);
+    let position = func.blocks.predecessors[to]
+        .iter()
+        .position(|&predecessor| predecessor == from)
+        .unwrap();
+    let wasm_func = phis.iter().fold(wasm_func, |wasm_func, &phi| {
+        let operand = func.values.phis[func.values.indices[phi]][position];
+        lower_value(wasm_func, l, operand, Type::I64)
+    });
+    phis.iter().rev().fold(wasm_func, |wasm_func, phi| {
+        set_local(wasm_func, l.locals[phi])
+    })
+}
+
+fn lower_branch(
+    wasm_func: codegen::Function,
+    l: &Lowering,
+    context: &[(usize, usize)],
+    from: usize,
+    to: usize,
+) -> codegen::Function {
+    let wasm_func = lower_copies(wasm_func, l, from, to);
+    match l.cfg.numbers[to] <= l.cfg.numbers[from] || l.cfg.merges[to] {
+        true => {
+            let &(_, label) = context
+                .iter()
+                .rev()
+                .find(|&&(target, _)| target == to)
+                .unwrap();
+            labelled(wasm_func, Instruction::Br, label)
+        }
+        false => lower_tree(wasm_func, l, context, to),
+    }
+}
+
+fn lower_node(
+    wasm_func: codegen::Function,
+    l: &Lowering,
+    context: &[(usize, usize)],
+    block: usize,
+    merges: &[usize],
+) -> codegen::Function {
+    if let Some((&merge, merges)) = merges.split_first() {
+        let label = wasm_func.next_label;
+        let mut wasm_func = labelled(wasm_func, Instruction::Block, label);
+        wasm_func.next_label += 1;
+        let inner = [context, &[(merge, label)]].concat();
+        let wasm_func = lower_node(wasm_func, l, &inner, block, merges);
+        let wasm_func = labelled(wasm_func, Instruction::End, label);
+        return lower_tree(wasm_func, l, context, merge);
+    }
+    let wasm_func = l.func.blocks.values[block]
+        .iter()
+        .fold(wasm_func, |wasm_func, &value| {
+            lower_definition(wasm_func, l, value)
+        });
+    match l.func.blocks.terminators[block] {
+        Terminator::Jump(target) => lower_branch(wasm_func, l, context, block, target),
+        Terminator::Branch(condition, then, otherwise) => {
+            let wasm_func = lower_condition(wasm_func, l, condition);
+            let wasm_func = instruction(wasm_func, Instruction::If);
+            let wasm_func = lower_branch(wasm_func, l, context, block, then);
+            let wasm_func = instruction(wasm_func, Instruction::Else);
+            let wasm_func = lower_branch(wasm_func, l, context, block, otherwise);
+            instruction(wasm_func, Instruction::End)
+        }
+        Terminator::Return(value) => {
+            let wasm_func = lower_value(wasm_func, l, value, Type::I64);
+            instruction(wasm_func, Instruction::Return)
+        }
+        Terminator::Unreachable => instruction(wasm_func, Instruction::Unreachable),
+    }
+}
+
+fn lower_tree(
+    wasm_func: codegen::Function,
+    l: &Lowering,
+    context: &[(usize, usize)],
+    block: usize,
+) -> codegen::Function {
+    let mut merges = l.cfg.children[block]
+        .iter()
+        .copied()
+        .filter(|&child| l.cfg.merges[child])
+        .collect::<Vec<usize>>();
+    merges.sort_by_key(|&merge| std::cmp::Reverse(l.cfg.numbers[merge]));
+    match l.cfg.loop_headers[block] {
+        true => {
+            let label = wasm_func.next_label;
+            let mut wasm_func = labelled(wasm_func, Instruction::Loop, label);
+            wasm_func.next_label += 1;
+            let inner = [context, &[(block, label)]].concat();
+            let wasm_func = lower_node(wasm_func, l, &inner, block, &merges);
+            labelled(wasm_func, Instruction::End, label)
+        }
+        false => lower_node(wasm_func, l, context, block, &merges),
+    }
+}
+
+fn operand_label(wasm_func: &codegen::Function, i: usize) -> Option<usize> {
+    match wasm_func.operand_kinds[i][..] {
+        [OperandKind::Label] => Some(wasm_func.operands[i][0]),
+        _ => None,
+    }
+}
+
+fn falls_through(wasm_func: &codegen::Function, i: usize, label: usize) -> bool {
+    match wasm_func.instructions.get(i) {
+        Some(Instruction::End) => match operand_label(wasm_func, i) {
+            Some(end) => end == label,
+            None => falls_through(wasm_func, i + 1, label),
+        },
+        Some(Instruction::Else) => {
+            let end = (i + 1..wasm_func.instructions.len())
+                .scan(0usize, |depth, j| {
+                    let current = *depth;
+                    *depth = match wasm_func.instructions[j] {
+                        Instruction::If | Instruction::Block | Instruction::Loop => *depth + 1,
+                        Instruction::End => depth.wrapping_sub(1),
+                        _ => *depth,
+                    };
+                    Some((j, current))
+                })
+                .find(|&(j, depth)| depth == 0 && wasm_func.instructions[j] == Instruction::End)
+                .map(|(j, _)| j);
+            match end {
+                Some(end) => falls_through(wasm_func, end + 1, label),
+                None => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn remove_fall_through(wasm_func: codegen::Function) -> codegen::Function {
+    let blocks = (0..wasm_func.instructions.len())
+        .filter(|&i| wasm_func.instructions[i] == Instruction::Block)
+        .filter_map(|i| operand_label(&wasm_func, i))
+        .collect::<HashSet<usize>>();
+    let redundant = (0..wasm_func.instructions.len())
+        .filter(|&i| wasm_func.instructions[i] == Instruction::Br)
+        .filter(|&i| {
+            let label = operand_label(&wasm_func, i).unwrap();
+            blocks.contains(&label) && falls_through(&wasm_func, i + 1, label)
+        })
+        .collect::<HashSet<usize>>();
+    let targeted = (0..wasm_func.instructions.len())
+        .filter(|i| !redundant.contains(i))
+        .filter(|&i| {
+            !matches!(
+                wasm_func.instructions[i],
+                Instruction::Block | Instruction::Loop | Instruction::End
+            )
+        })
+        .filter_map(|i| operand_label(&wasm_func, i))
+        .collect::<HashSet<usize>>();
+    let removed = |i: usize| {
+        redundant.contains(&i)
+            || (matches!(
+                wasm_func.instructions[i],
+                Instruction::Block | Instruction::End
+            ) && operand_label(&wasm_func, i)
+                .is_some_and(|label| blocks.contains(&label) && !targeted.contains(&label)))
+    };
+    let kept = (0..wasm_func.instructions.len())
+        .filter(|&i| !removed(i))
+        .collect::<Vec<usize>>();
+    codegen::Function {
+        instructions: kept.iter().map(|&i| wasm_func.instructions[i]).collect(),
+        operand_kinds: kept
+            .iter()
+            .map(|&i| wasm_func.operand_kinds[i].clone())
+            .collect(),
+        operands: kept
+            .iter()
+            .map(|&i| wasm_func.operands[i].clone())
+            .collect(),
+        ..wasm_func
+    }
+}
+
+pub fn lower(func: &Function) -> codegen::Function {
+    let func = &remove_unreachable_blocks(func.clone());
+    let cfg = cfg(func);
+    let defined = cfg
+        .order
+        .iter()
+        .flat_map(|&block| func.blocks.values[block].iter().copied())
+        .filter(|&value| !matches!(func.values.kinds[value], Kind::Int | Kind::Parameter))
+        .collect::<Vec<usize>>();
+    let locals = defined
+        .iter()
+        .enumerate()
+        .map(|(i, &value)| (value, func.arguments.len() + i))
+        .collect::<HashMap<usize, usize>>();
+    let wasm_func = codegen::Function {
+        name: 0,
+        instructions: vec![],
+        operand_kinds: vec![],
+        operands: vec![],
+        locals: func
+            .arguments
+            .iter()
+            .map(|argument| format!("${}", argument))
+            .chain(defined.iter().map(|value| format!("$.value.{}", value)))
+            .collect(),
+        types: func
+            .arguments
+            .iter()
+            .map(|_| Type::I64)
+            .chain(defined.iter().map(|&value| match func.values.kinds[value] {
+                Kind::Phi => Type::I64,
+                _ => value_type(func, value),
+            }))
+            .collect(),
+        name_to_local: func
+            .arguments
+            .iter()
+            .enumerate()
+            .map(|(i, argument)| (argument.clone(), i))
+            .collect(),
+        globals: HashSet::new(),
+        variants: HashMap::new(),
+        functions: HashMap::new(),
+        symbols: vec![func.name.clone()],
+        ints: vec![],
+        floats: vec![],
+        arguments: func.arguments.len(),
+        result: Type::I64,
+        next_label: 0,
+        loops: vec![],
+        tail_calls: HashSet::new(),
+        tail_loop: None,
+        inline: None,
+        memory: false,
+    };
+    let l = Lowering { func, cfg, locals };
+    let mut wasm_func = remove_fall_through(lower_tree(wasm_func, &l, &[], 0));
+    match wasm_func.instructions.last() {
+        Some(Instruction::Return) => {
+            wasm_func.instructions.pop();
+            wasm_func.operand_kinds.pop();
+            wasm_func.operands.pop();
+            wasm_func
+        }
+        _ => instruction(wasm_func, Instruction::Unreachable),
+    }
+}
+
+pub fn build_functions(ast: &Ast) -> HashMap<String, Function> {
+    let functions = ast
+        .top_level
+        .iter()
+        .filter(|(_, &index)| {
+            let func = &ast.functions[index];
+            func.type_parameters.is_empty()
+                && func.argument_types.iter().all(Option::is_none)
+                && func.return_type.is_none()
+        })
+        .map(|(name, _)| name.clone())
+        .collect::<HashSet<String>>();
+    let globals = ast
+        .globals
+        .name_to_global
+        .keys()
+        .cloned()
+        .collect::<HashSet<String>>();
+    functions
+        .iter()
+        .filter_map(|name| {
+            let func = build(&ast.functions[ast.top_level[name]], &functions, &globals)?;
+            Some((name.clone(), func))
+        })
+        .collect()
+}
+
+pub fn lower_functions(mut wasm: Wasm) -> Wasm {
+    let manager = default_passes();
+    let mut ir = wasm
+        .ast
+        .take()
+        .map_or_else(HashMap::new, |ast| build_functions(&ast));
+    let lowered = wasm
+        .functions
+        .into_iter()
+        .map(|wasm_func| {
+            let func = match (wasm_func.result, wasm_func.tail_loop) {
+                (Type::I64, None) => ir.remove(&wasm_func.symbols[wasm_func.name]),
+                _ => None,
+            };
+            match func {
+                Some(func) => codegen::Function {
+                    inline: wasm_func.inline,
+                    ..lower(&run_passes(&manager, func))
+                },
+                None => wasm_func,
+            }
+        })
+        .collect();
+    Wasm {
+        functions: lowered,
+        ..wasm
+    }
+}
@@ -0,0 +1,240 @@
+use std::{char, fmt::Write};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+const NULL: Json = Json::Null;
+
+fn trim(text: &str) -> &str {
+    text.trim_start_matches([' ', '\t', '\n', '\r'])
+}
+
+fn parse_literal<'a>(text: &'a str, literal: &str, value: Json) -> (Json, &'a str) {
+    assert!(
+        text.starts_with(literal),
+        "Invalid JSON, expected {}",
+        literal
+    );
+    (value, &text[literal.len()..])
+}
+
+fn parse_number(text: &str) -> (Json, &str) {
+    let length = text
+        .find(|c: char| !(c.is_ascii_digit() || "+-.eE".contains(c)))
+        .unwrap_or(text.len());
+    let number = text[..length]
+        .parse()
+        .unwrap_or_else(|_| panic!("Invalid JSON number {}", &text[..length]));
+    (Json::Number(number), &text[length..])
+}
+
+fn parse_hex(text: &str) -> u32 {
+    text.get(..4)
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .unwrap_or_else(|| panic!("Invalid JSON unicode escape"))
+}
+
+fn parse_unicode(text: &str) -> (char, &str) {
+    let high = parse_hex(text);
+    match (0xD800..0xDC00).contains(&high) && text[4..].starts_with("\\u") {
+        true => {
+            let low = parse_hex(&text[6..]);
+            let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+            (
+                char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER),
+                &text[10..],
+            )
+        }
+        false => (
+            char::from_u32(high).unwrap_or(char::REPLACEMENT_CHARACTER),
+            &text[4..],
+        ),
+    }
+}
+
+fn parse_string(text: &str, mut string: String) -> (String, &str) {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some('"') => (string, chars.as_str()),
+        Some('\\') => {
+            let escape = chars.next();
+            let rest = chars.as_str();
+            let (c, rest) = match escape {
+                Some('n') => ('\n', rest),
+                Some('t') => ('\t', rest),
+                Some('r') => ('\r', rest),
+                Some('b') => ('\x08', rest),
+                Some('f') => ('\x0C', rest),
+                Some('u') => parse_unicode(rest),
+                Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => (c, rest),
+                c => panic!("Invalid JSON escape {:?}", c),
+            };
+            string.push(c);
+            parse_string(rest, string)
+        }
+        Some(_) => {
+            let length = text.find(['"', '\\']).unwrap_or(text.len());
+            string.push_str(&text[..length]);
+            parse_string(&text[length..], string)
+        }
+        None => panic!("Invalid JSON, unterminated string"),
+    }
+}
+
+fn parse_elements(text: &str, mut elements: Vec<Json>) -> (Json, &str) {
+    let (element, text) = parse_value(text);
+    elements.push(element);
+    let text = trim(text);
+    match text.chars().next() {
+        Some(',') => parse_elements(&text[1..], elements),
+        Some(']') => (Json::Array(elements), &text[1..]),
+        c => panic!("Invalid JSON array, expected , or ] found {:?}", c),
+    }
+}
+
+fn parse_members(text: &str, mut members: Vec<(String, Json)>) -> (Json, &str) {
+    let text = trim(text);
+    assert!(text.starts_with('"'), "Invalid JSON object key");
+    let (key, text) = parse_string(&text[1..], String::new());
+    let text = trim(text);
+    assert!(text.starts_with(':'), "Invalid JSON object, expected :");
+    let (value, text) = parse_value(&text[1..]);
+    members.push((key, value));
+    let text = trim(text);
+    match text.chars().next() {
+        Some(',') => parse_members(&text[1..], members),
+        Some('}') => (Json::Object(members), &text[1..]),
+        c => panic!("Invalid JSON object, expected , or }} found {:?}", c),
+    }
+}
+
+fn parse_value(text: &str) -> (Json, &str) {
+    let text = trim(text);
+    match text.chars().next() {
+        Some('n') => parse_literal(text, "null", Json::Null),
+        Some('t') => parse_literal(text, "true", Json::Bool(true)),
+        Some('f') => parse_literal(text, "false", Json::Bool(false)),
+        Some('"') => {
+            let (string, text) = parse_string(&text[1..], String::new());
+            (Json::String(string), text)
+        }
+        Some('[') if trim(&text[1..]).starts_with(']') => {
+            (Json::Array(vec![]), &trim(&text[1..])[1..])
+        }
+        Some('[') => parse_elements(&text[1..], vec![]),
+        Some('{') if trim(&text[1..]).starts_with('}') => {
+            (Json::Object(vec![]), &trim(&text[1..])[1..])
+        }
+        Some('{') => parse_members(&text[1..], vec![]),
+        Some(c) if c == '-' || c.is_ascii_digit() => parse_number(text),
+        c => panic!("Invalid JSON, unexpected {:?}", c),
+    }
+}
+
+pub fn parse(text: &str) -> Json {
+    let (json, rest) = parse_value(text);
+    assert!(trim(rest).is_empty(), "Invalid JSON, trailing characters");
+    json
+}
+
+fn write_string(mut output: String, string: &str) -> String {
+    output.push('"');
+    for c in string.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(output, "\\u{:04x}", c as u32).unwrap(),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+fn write_value(mut output: String, json: &Json) -> String {
+    match json {
+        Json::Null => output + "null",
+        Json::Bool(value) => output + if *value { "true" } else { "false" },
+        Json::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+            write!(output, "{}", *number as i64).unwrap();
+            output
+        }
+        Json::Number(number) => {
+            write!(output, "{}", number).unwrap();
+            output
+        }
+        Json::String(string) => write_string(output, string),
+        Json::Array(elements) => {
+            output.push('[');
+            let output = elements
+                .iter()
+                .enumerate()
+                .fold(output, |output, (i, element)| {
+                    write_value(if i > 0 { output + "," } else { output }, element)
+                });
+            output + "]"
+        }
+        Json::Object(members) => {
+            output.push('{');
+            let output = members
+                .iter()
+                .enumerate()
+                .fold(output, |output, (i, (key, value))| {
+                    let output = write_string(if i > 0 { output + "," } else { output }, key);
+                    write_value(output + ":", value)
+                });
+            output + "}"
+        }
+    }
+}
+
+pub fn write(json: &Json) -> String {
+    write_value(String::new(), json)
+}
+
+pub fn get<'a>(json: &'a Json, key: &str) -> &'a Json {
+    match json {
+        Json::Object(members) => members
+            .iter()
+            .find(|(name, _)| name == key)
+            .map_or(&NULL, |(_, value)| value),
+        _ => &NULL,
+    }
+}
+
+pub fn path<'a>(json: &'a Json, keys: &[&str]) -> &'a Json {
+    keys.iter().fold(json, |json, key| get(json, key))
+}
+
+pub fn string(json: &Json) -> Option<&str> {
+    match json {
+        Json::String(string) => Some(string),
+        _ => None,
+    }
+}
+
+pub fn number(json: &Json) -> Option<usize> {
+    match json {
+        Json::Number(number) if *number >= 0.0 => Some(*number as usize),
+        _ => None,
+    }
+}
+
+pub fn object(members: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        members
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
@@ -1,6 +1,18 @@
 #![feature(map_try_insert)]
 
+pub mod artifact;
+mod cache;
 pub mod codegen;
+pub mod formatter;
+pub mod interpreter;
+pub mod ir;
+pub mod json;
+pub mod loader;
+pub mod lsp;
+pub mod manifest;
+pub mod optimizer;
 pub mod parser;
+pub mod peephole;
+pub mod repl;
 pub mod tokenizer;
 pub mod writer;
@@ -0,0 +1,545 @@
+use rayon::prelude::*;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::{read_manifest, Manifest},
+    parser::{parse, Ast, Enums, Function, Globals, Imports, Kind, Pattern},
+    tokenizer::tokenize,
+};
+
+#[derive(Debug, PartialEq)]
+pub struct Packages {
+    pub names: Vec<String>,
+    pub directories: Vec<PathBuf>,
+    pub sources: Vec<Vec<PathBuf>>,
+    pub dependencies: Vec<HashMap<String, usize>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Modules {
+    pub names: Vec<String>,
+    pub paths: Vec<PathBuf>,
+    pub packages: Vec<usize>,
+    pub imports: Vec<Vec<String>>,
+    pub asts: Vec<Ast>,
+    pub entries: usize,
+    pub name_to_module: HashMap<String, usize>,
+}
+
+fn module_path(name: &str, search_path: &[PathBuf]) -> PathBuf {
+    let relative = format!("{}.mon", name.replace('.', "/"));
+    search_path
+        .iter()
+        .map(|directory| directory.join(&relative))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| {
+            panic!(
+                "Could not find module {} on search path {:?}",
+                name, search_path
+            )
+        })
+}
+
+fn qualify_package(packages: &Packages, package: usize, name: &str) -> String {
+    if package == 0 {
+        name.to_string()
+    } else {
+        format!("{}.{}", packages.names[package], name)
+    }
+}
+
+fn resolve_module(packages: &Packages, package: usize, name: &str) -> (String, usize, String) {
+    let dependency = name.split_once('.').and_then(|(first, rest)| {
+        packages.dependencies[package]
+            .get(first)
+            .map(|&dependency| (dependency, rest))
+    });
+    match dependency {
+        Some((dependency, rest)) => (
+            qualify_package(packages, dependency, rest),
+            dependency,
+            rest.to_string(),
+        ),
+        None => (
+            qualify_package(packages, package, name),
+            package,
+            name.to_string(),
+        ),
+    }
+}
+
+fn parse_file(path: &Path) -> Ast {
+    let source = fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("Could not read {}: {}", path.display(), error));
+    parse(tokenize(&source))
+}
+
+fn load_modules(
+    modules: Modules,
+    frontier: Vec<(String, PathBuf, usize)>,
+    packages: &Packages,
+) -> Modules {
+    if frontier.is_empty() {
+        return modules;
+    }
+    let asts: Vec<Ast> = frontier
+        .par_iter()
+        .map(|(_, path, _)| parse_file(path))
+        .collect();
+    add_modules(modules, frontier, asts, packages)
+}
+
+fn add_modules(
+    mut modules: Modules,
+    frontier: Vec<(String, PathBuf, usize)>,
+    asts: Vec<Ast>,
+    packages: &Packages,
+) -> Modules {
+    let start = modules.asts.len();
+    for ((name, path, package), ast) in frontier.into_iter().zip(asts) {
+        let imports = ast
+            .imports
+            .modules
+            .iter()
+            .map(|import| resolve_module(packages, package, import).0)
+            .collect();
+        modules
+            .name_to_module
+            .try_insert(name.clone(), modules.names.len())
+            .unwrap();
+        modules.names.push(name);
+        modules.paths.push(path);
+        modules.packages.push(package);
+        modules.imports.push(imports);
+        modules.asts.push(ast);
+    }
+    let frontier = (start..modules.asts.len())
+        .flat_map(|module| {
+            let package = modules.packages[module];
+            modules.asts[module]
+                .imports
+                .modules
+                .iter()
+                .map(move |import| resolve_module(packages, package, import))
+        })
+        .fold(
+            vec![],
+            |mut frontier: Vec<(String, PathBuf, usize)>, (name, package, local)| {
+                let seen = modules.name_to_module.contains_key(&name)
+                    || frontier.iter().any(|(other, _, _)| *other == name);
+                if !seen {
+                    let path = module_path(&local, &packages.sources[package]);
+                    frontier.push((name, path, package));
+                }
+                frontier
+            },
+        );
+    load_modules(modules, frontier, packages)
+}
+
+fn check_cycles(
+    modules: &Modules,
+    module: usize,
+    mut stack: Vec<usize>,
+    visited: HashSet<usize>,
+) -> HashSet<usize> {
+    if let Some(start) = stack.iter().position(|&other| other == module) {
+        let cycle = stack[start..]
+            .iter()
+            .chain(std::iter::once(&module))
+            .map(|&other| modules.names[other].as_str())
+            .collect::<Vec<&str>>()
+            .join(" -> ");
+        panic!("Import cycle detected: {}", cycle);
+    }
+    if visited.contains(&module) {
+        return visited;
+    }
+    stack.push(module);
+    let mut visited = modules.imports[module]
+        .iter()
+        .fold(visited, |visited, name| {
+            let import = modules.name_to_module[name];
+            check_cycles(modules, import, stack.clone(), visited)
+        });
+    visited.insert(module);
+    visited
+}
+
+fn qualify(modules: &Modules, module: usize, name: &str) -> String {
+    if module < modules.entries {
+        name.to_string()
+    } else {
+        format!("{}.{}", modules.names[module], name)
+    }
+}
+
+const PRELUDE: &str = include_str!("prelude.mon");
+
+pub(crate) fn top_level_names(ast: &Ast) -> impl Iterator<Item = &String> {
+    ast.top_level
+        .keys()
+        .chain(ast.globals.name_to_global.keys())
+        .chain(ast.enums.names.iter())
+        .chain(ast.enums.variants.iter().flatten())
+}
+
+fn resolve_imports(
+    modules: &Modules,
+    module: usize,
+    resolutions: HashMap<String, String>,
+) -> HashMap<String, String> {
+    let imports = &modules.asts[module].imports;
+    modules.imports[module]
+        .iter()
+        .zip(imports.aliases.iter().zip(imports.names.iter()))
+        .fold(resolutions, |resolutions, (name, (alias, names))| {
+            let import = modules.name_to_module[name];
+            let ast = &modules.asts[import];
+            let resolutions = match alias {
+                Some(alias) => {
+                    top_level_names(ast).fold(resolutions, |mut resolutions, top_level| {
+                        resolutions.insert(
+                            format!("{}.{}", alias, top_level),
+                            qualify(modules, import, top_level),
+                        );
+                        resolutions
+                    })
+                }
+                None => resolutions,
+            };
+            names
+                .iter()
+                .fold(resolutions, |mut resolutions, top_level| {
+                    assert!(
+                        top_level_names(ast).any(|other| other == top_level),
+                        "Module {} has no function or global {}",
+                        name,
+                        top_level
+                    );
+                    resolutions.insert(top_level.clone(), qualify(modules, import, top_level));
+                    resolutions
+                })
+        })
+}
+
+fn resolutions(modules: &Modules, module: usize, prelude: &Ast) -> HashMap<String, String> {
+    let ast = &modules.asts[module];
+    let resolutions = top_level_names(prelude).fold(HashMap::new(), |mut resolutions, name| {
+        resolutions.insert(name.clone(), name.clone());
+        resolutions
+    });
+    let resolutions = top_level_names(ast).fold(resolutions, |mut resolutions, top_level| {
+        resolutions.insert(top_level.clone(), qualify(modules, module, top_level));
+        resolutions
+    });
+    resolve_imports(modules, module, resolutions)
+}
+
+fn resolve_symbols(mut func: Function, resolutions: &HashMap<String, String>) -> Function {
+    func.symbols[func.name] = resolutions[&func.symbols[func.name]].clone();
+    for entity in 0..func.kinds.len() {
+        if func.kinds[entity] != Kind::Symbol {
+            continue;
+        }
+        let symbol = func.indices[entity];
+        let is_argument = func
+            .arguments
+            .iter()
+            .chain(func.lambdas.parameters.iter().flatten())
+            .any(|&argument| func.symbols[argument] == func.symbols[symbol]);
+        if let (false, Some(resolved)) = (is_argument, resolutions.get(&func.symbols[symbol])) {
+            func.symbols[symbol] = resolved.clone();
+        }
+    }
+    let variants = func
+        .matches
+        .patterns
+        .iter()
+        .flatten()
+        .filter_map(|pattern| match pattern {
+            Pattern::Variant(symbol) => Some(*symbol),
+            _ => None,
+        })
+        .collect::<Vec<usize>>();
+    for symbol in variants {
+        if let Some(resolved) = resolutions.get(&func.symbols[symbol]) {
+            func.symbols[symbol] = resolved.clone();
+        }
+    }
+    func
+}
+
+fn link_enums(ast: Ast, enums: Enums, rename: impl Fn(&str) -> String) -> Ast {
+    let names = enums.names.into_iter();
+    let variants = enums.variants.into_iter().zip(enums.fields);
+    names
+        .zip(variants)
+        .fold(ast, |mut ast, (name, (variants, fields))| {
+            let name = rename(&name);
+            if ast.enums.name_to_enum.contains_key(&name) {
+                return ast;
+            }
+            ast.enums
+                .name_to_enum
+                .insert(name.clone(), ast.enums.names.len());
+            ast.enums.names.push(name);
+            ast.enums
+                .variants
+                .push(variants.iter().map(|variant| rename(variant)).collect());
+            ast.enums.fields.push(fields);
+            ast
+        })
+}
+
+fn link_prelude(ast: Ast, prelude: Ast) -> Ast {
+    let ast = prelude.functions.into_iter().fold(ast, |mut ast, func| {
+        let name = func.symbols[func.name].clone();
+        if let Entry::Vacant(entry) = ast.top_level.entry(name.clone()) {
+            entry.insert(ast.functions.len());
+            ast.functions.push(func);
+            ast.libraries.insert(name);
+        }
+        ast
+    });
+    link_enums(ast, prelude.enums, str::to_string)
+}
+
+pub fn link(modules: Modules) -> Ast {
+    let prelude = parse(tokenize(PRELUDE));
+    let resolutions = (0..modules.asts.len())
+        .map(|module| resolutions(&modules, module, &prelude))
+        .collect::<Vec<HashMap<String, String>>>();
+    let entries = modules.entries;
+    let ast = Ast {
+        functions: vec![],
+        top_level: HashMap::new(),
+        imports: Imports {
+            modules: vec![],
+            aliases: vec![],
+            names: vec![],
+        },
+        globals: Globals {
+            kinds: vec![],
+            values: vec![],
+            name_to_global: HashMap::new(),
+        },
+        enums: Enums {
+            names: vec![],
+            variants: vec![],
+            fields: vec![],
+            name_to_enum: HashMap::new(),
+        },
+        libraries: HashSet::new(),
+    };
+    let ast = modules.asts.into_iter().zip(resolutions).enumerate().fold(
+        ast,
+        |ast, (module, (module_ast, resolutions))| {
+            let ast = link_enums(ast, module_ast.enums, |name| resolutions[name].clone());
+            let ast = module_ast.functions.into_iter().fold(ast, |mut ast, func| {
+                let mut func = resolve_symbols(func, &resolutions);
+                let name = func.symbols[func.name].clone();
+                if module >= entries {
+                    func.export = None;
+                    ast.libraries.insert(name.clone());
+                }
+                ast.top_level.try_insert(name, ast.functions.len()).unwrap();
+                ast.functions.push(func);
+                ast
+            });
+            let globals = module_ast.globals;
+            globals
+                .kinds
+                .into_iter()
+                .zip(globals.values)
+                .fold(ast, |mut ast, (kind, value)| {
+                    let value = resolve_symbols(value, &resolutions);
+                    let name = value.symbols[value.name].clone();
+                    ast.globals
+                        .name_to_global
+                        .try_insert(name, ast.globals.values.len())
+                        .unwrap();
+                    ast.globals.kinds.push(kind);
+                    ast.globals.values.push(value);
+                    ast
+                })
+        },
+    );
+    link_prelude(ast, prelude)
+}
+
+fn load_package(
+    mut packages: Packages,
+    directory: &Path,
+    manifest: Manifest,
+    mut stack: Vec<usize>,
+) -> (Packages, usize) {
+    let package = packages.names.len();
+    packages.names.push(manifest.name);
+    packages.directories.push(directory.to_path_buf());
+    packages.sources.push(
+        manifest
+            .sources
+            .iter()
+            .map(|source| directory.join(source))
+            .collect(),
+    );
+    packages.dependencies.push(HashMap::new());
+    stack.push(package);
+    let names = manifest.dependencies.names.into_iter();
+    let packages =
+        names
+            .zip(manifest.dependencies.paths)
+            .fold(packages, |packages, (name, path)| {
+                let directory = directory.join(path).canonicalize().unwrap_or_else(|error| {
+                    panic!("Could not find dependency {}: {}", name, error)
+                });
+                let existing = packages
+                    .directories
+                    .iter()
+                    .position(|other| *other == directory);
+                let (mut packages, dependency) = match existing {
+                    Some(dependency) if stack.contains(&dependency) => {
+                        let start = stack.iter().position(|&other| other == dependency).unwrap();
+                        let cycle = stack[start..]
+                            .iter()
+                            .chain(std::iter::once(&dependency))
+                            .map(|&other| packages.names[other].as_str())
+                            .collect::<Vec<&str>>()
+                            .join(" -> ");
+                        panic!("Dependency cycle detected: {}", cycle);
+                    }
+                    Some(dependency) => (packages, dependency),
+                    None => {
+                        let manifest = read_manifest(&directory);
+                        load_package(packages, &directory, manifest, stack.clone())
+                    }
+                };
+                packages.dependencies[package].insert(name, dependency);
+                packages
+            });
+    (packages, package)
+}
+
+fn empty_modules(entries: usize) -> Modules {
+    Modules {
+        names: vec![],
+        paths: vec![],
+        packages: vec![],
+        imports: vec![],
+        asts: vec![],
+        entries,
+        name_to_module: HashMap::new(),
+    }
+}
+
+fn check_definitions(modules: &Modules) {
+    (0..modules.entries).fold(HashMap::new(), |mut owners, entry| {
+        let ast = &modules.asts[entry];
+        let names = ast
+            .functions
+            .iter()
+            .chain(ast.globals.values.iter())
+            .map(|func| &func.symbols[func.name]);
+        for name in names {
+            if let Some(&owner) = owners.get(name) {
+                assert!(
+                    owner == entry,
+                    "{} is defined in both {} and {}",
+                    name,
+                    modules.names[owner],
+                    modules.names[entry]
+                );
+            }
+            owners.insert(name, entry);
+        }
+        owners
+    });
+}
+
+fn check_entries(modules: Modules) -> Modules {
+    (0..modules.entries).fold(HashSet::new(), |visited, entry| {
+        check_cycles(&modules, entry, vec![], visited)
+    });
+    check_definitions(&modules);
+    modules
+}
+
+fn load_entries(packages: &Packages, entries: Vec<(String, PathBuf)>) -> Modules {
+    let modules = empty_modules(entries.len());
+    let frontier = entries
+        .into_iter()
+        .map(|(name, path)| (name, path, 0))
+        .collect();
+    check_entries(load_modules(modules, frontier, packages))
+}
+
+fn file_packages(sources: Vec<PathBuf>) -> Packages {
+    Packages {
+        names: vec![String::new()],
+        directories: vec![PathBuf::new()],
+        sources: vec![sources],
+        dependencies: vec![HashMap::new()],
+    }
+}
+
+pub fn load_all(entry: &Path, search_path: &[PathBuf]) -> Modules {
+    let sources = entry
+        .parent()
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(search_path.iter().cloned())
+        .collect::<Vec<PathBuf>>();
+    let packages = file_packages(sources);
+    let name = entry
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    load_entries(&packages, vec![(name, entry.to_path_buf())])
+}
+
+pub fn load(entry: &Path, search_path: &[PathBuf]) -> Ast {
+    link(load_all(entry, search_path))
+}
+
+pub fn load_source(name: &str, source: &str, search_path: &[PathBuf]) -> Ast {
+    let packages = file_packages(search_path.to_vec());
+    let frontier = vec![(name.to_string(), PathBuf::new(), 0)];
+    let asts = vec![parse(tokenize(source))];
+    link(check_entries(add_modules(
+        empty_modules(1),
+        frontier,
+        asts,
+        &packages,
+    )))
+}
+
+pub fn load_packages(directory: &Path, manifest: Manifest) -> Packages {
+    let packages = Packages {
+        names: vec![],
+        directories: vec![],
+        sources: vec![],
+        dependencies: vec![],
+    };
+    let directory = directory
+        .canonicalize()
+        .unwrap_or_else(|error| panic!("Could not find {}: {}", directory.display(), error));
+    load_package(packages, &directory, manifest, vec![]).0
+}
+
+pub fn load_project(directory: &Path, manifest: Manifest) -> Ast {
+    let entries = manifest.entries.clone();
+    let packages = load_packages(directory, manifest);
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let path = module_path(&entry, &packages.sources[0]);
+            (entry, path)
+        })
+        .collect();
+    link(load_entries(&packages, entries))
+}
@@ -0,0 +1,823 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{BufRead, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    codegen::{self, codegen_cached, type_name, widen, Wasm},
+    json::{self, Json},
+    loader::load_source,
+    parser::{parse, Ast, Function},
+    tokenizer::{self, tokenize, Kind, Tokens, TopLevel},
+};
+
+pub struct Server {
+    pub documents: HashMap<String, String>,
+    pub chunks: HashMap<String, HashMap<u64, Result<Ast, String>>>,
+    pub compiled: HashMap<String, Wasm>,
+    pub top_level: HashMap<String, Vec<String>>,
+    pub search_path: Vec<PathBuf>,
+    pub cache: PathBuf,
+    pub exited: bool,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Declaration {
+    Function,
+    Const,
+    Var,
+    Enum,
+    Variant,
+}
+
+const ERROR: f64 = 1.0;
+const WARNING: f64 = 2.0;
+const SYNTAX_ERROR: &str = "syntax error";
+
+pub fn server(search_path: Vec<PathBuf>, cache: PathBuf) -> Server {
+    Server {
+        documents: HashMap::new(),
+        chunks: HashMap::new(),
+        compiled: HashMap::new(),
+        top_level: HashMap::new(),
+        search_path,
+        cache,
+        exited: false,
+    }
+}
+
+fn attempt<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or_else(
+            || String::from("internal error"),
+            |message| message.to_string(),
+        ),
+    })
+}
+
+fn number(value: usize) -> Json {
+    Json::Number(value as f64)
+}
+
+fn position(text: &str, offset: usize) -> Json {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    json::object(vec![
+        ("line", number(before.matches('\n').count())),
+        ("character", number(before[line_start..].chars().count())),
+    ])
+}
+
+fn range(text: &str, (start, end): (usize, usize)) -> Json {
+    json::object(vec![
+        ("start", position(text, start)),
+        ("end", position(text, end)),
+    ])
+}
+
+fn offset(text: &str, position: &Json) -> usize {
+    let line = json::number(json::get(position, "line")).unwrap_or(0);
+    let character = json::number(json::get(position, "character")).unwrap_or(0);
+    let start = text
+        .split('\n')
+        .take(line)
+        .map(|line| line.len() + 1)
+        .sum::<usize>()
+        .min(text.len());
+    let line = text[start..].split('\n').next().unwrap_or("");
+    start
+        + line
+            .char_indices()
+            .nth(character)
+            .map_or(line.len(), |(index, _)| index)
+}
+
+fn location(uri: &str, text: &str, span: (usize, usize)) -> Json {
+    json::object(vec![
+        ("uri", Json::String(uri.to_string())),
+        ("range", range(text, span)),
+    ])
+}
+
+fn symbol(top_level: &TopLevel, token: usize) -> &str {
+    &top_level.symbols[top_level.indices[token]]
+}
+
+fn token_span(top_level: &TopLevel, token: usize) -> (usize, usize) {
+    let start = top_level.positions[token];
+    let index = top_level.indices[token];
+    let length = match top_level.kinds[token] {
+        Kind::Symbol => top_level.symbols[index].len(),
+        Kind::Attribute => top_level.symbols[index].len() + 1,
+        Kind::Int => top_level.ints[index].len(),
+        Kind::Float => top_level.floats[index].len(),
+        _ => 1,
+    };
+    (start, start + length)
+}
+
+fn chunk_span(text: &str, top_level: &TopLevel) -> (usize, usize) {
+    let last = top_level.positions[top_level.positions.len() - 1];
+    let end = text[last..]
+        .find('\n')
+        .map_or(text.len(), |length| last + length);
+    (top_level.positions[0], end)
+}
+
+fn symbol_at(tokens: &Tokens, offset: usize) -> Option<(usize, usize)> {
+    tokens
+        .top_level
+        .iter()
+        .enumerate()
+        .find_map(|(chunk, top_level)| {
+            (0..top_level.kinds.len())
+                .find(|&token| {
+                    let (start, end) = token_span(top_level, token);
+                    top_level.kinds[token] == Kind::Symbol && start <= offset && offset <= end
+                })
+                .map(|token| (chunk, token))
+        })
+}
+
+fn chunk_at(tokens: &Tokens, offset: usize) -> Option<usize> {
+    tokens
+        .top_level
+        .iter()
+        .rposition(|top_level| top_level.positions[0] <= offset)
+}
+
+fn declarations(top_level: &TopLevel) -> Vec<(usize, Declaration)> {
+    let first = top_level
+        .kinds
+        .iter()
+        .position(|&kind| kind != Kind::Attribute && kind != Kind::Export)
+        .unwrap_or(0);
+    let name = first + 1;
+    let declaration = match top_level.kinds.get(name) {
+        Some(Kind::Symbol) => match top_level.kinds[first] {
+            Kind::Def => Some(Declaration::Function),
+            Kind::Const => Some(Declaration::Const),
+            Kind::Var => Some(Declaration::Var),
+            Kind::Enum => Some(Declaration::Enum),
+            _ => None,
+        },
+        _ => None,
+    };
+    let variants = (name + 1..top_level.kinds.len()).filter(|&token| {
+        let depth = top_level.kinds[..token]
+            .iter()
+            .filter(|&&kind| kind == Kind::LeftParen)
+            .count()
+            - top_level.kinds[..token]
+                .iter()
+                .filter(|&&kind| kind == Kind::RightParen)
+                .count();
+        top_level.kinds[token] == Kind::Symbol
+            && depth == 0
+            && [Kind::Colon, Kind::Comma, Kind::Indent].contains(&top_level.kinds[token - 1])
+    });
+    match declaration {
+        Some(Declaration::Enum) => std::iter::once((name, Declaration::Enum))
+            .chain(variants.map(|token| (token, Declaration::Variant)))
+            .collect(),
+        Some(declaration) => vec![(name, declaration)],
+        None => vec![],
+    }
+}
+
+fn symbol_token(top_level: &TopLevel, index: usize) -> Option<usize> {
+    (0..top_level.kinds.len())
+        .find(|&token| top_level.kinds[token] == Kind::Symbol && top_level.indices[token] == index)
+}
+
+fn globals(tokens: &Tokens) -> HashSet<&str> {
+    tokens
+        .top_level
+        .iter()
+        .flat_map(|top_level| {
+            declarations(top_level)
+                .into_iter()
+                .filter(|&(_, declaration)| {
+                    declaration == Declaration::Const || declaration == Declaration::Var
+                })
+                .map(move |(token, _)| symbol(top_level, token))
+        })
+        .collect()
+}
+
+fn local_definitions(func: &Function, globals: &HashSet<&str>) -> Vec<usize> {
+    let assigned = func
+        .assignments
+        .names
+        .iter()
+        .map(|&entity| func.indices[entity])
+        .filter(|&index| !globals.contains(func.symbols[index].as_str()));
+    let mut definitions = func
+        .arguments
+        .iter()
+        .chain(func.type_parameters.iter())
+        .copied()
+        .chain(assigned)
+        .chain(
+            func.fors
+                .variables
+                .iter()
+                .map(|&entity| func.indices[entity]),
+        )
+        .chain(func.lambdas.parameters.iter().flatten().copied())
+        .chain(func.matches.bindings.iter().flatten().flatten().copied())
+        .filter(|&index| func.symbols[index] != "_")
+        .collect::<Vec<usize>>();
+    definitions.sort_unstable();
+    definitions
+}
+
+fn chunk_function<'a>(server: &'a Server, uri: &str, top_level: &TopLevel) -> Option<&'a Function> {
+    let chunks = server.chunks.get(uri)?;
+    match chunks.get(&tokenizer::hash(top_level))? {
+        Ok(ast) => ast.functions.first(),
+        Err(_) => None,
+    }
+}
+
+fn local_definition(
+    server: &Server,
+    uri: &str,
+    tokens: &Tokens,
+    chunk: usize,
+    name: &str,
+) -> Option<usize> {
+    let top_level = &tokens.top_level[chunk];
+    let func = chunk_function(server, uri, top_level)?;
+    local_definitions(func, &globals(tokens))
+        .into_iter()
+        .find(|&index| func.symbols[index] == name)
+        .and_then(|index| symbol_token(top_level, index))
+}
+
+fn declaration<'a>(tokens: &'a Tokens, name: &str) -> Option<(&'a TopLevel, usize, Declaration)> {
+    tokens.top_level.iter().find_map(|top_level| {
+        declarations(top_level)
+            .into_iter()
+            .find(|&(token, _)| symbol(top_level, token) == name)
+            .map(|(token, declaration)| (top_level, token, declaration))
+    })
+}
+
+fn definition(server: &Server, uri: &str, position: &Json) -> Option<Json> {
+    let text = server.documents.get(uri)?;
+    let tokens = attempt(|| tokenize(text)).ok()?;
+    let (chunk, token) = symbol_at(&tokens, offset(text, position))?;
+    let name = symbol(&tokens.top_level[chunk], token);
+    let span = match local_definition(server, uri, &tokens, chunk, name) {
+        Some(local) => token_span(&tokens.top_level[chunk], local),
+        None => {
+            let (top_level, token, _) = declaration(&tokens, name)?;
+            token_span(top_level, token)
+        }
+    };
+    Some(location(uri, text, span))
+}
+
+fn local_type(server: &Server, uri: &str, function: &str, name: &str) -> Option<&'static str> {
+    let wasm = server.compiled.get(uri)?;
+    let func = &wasm.functions[*wasm.name_to_function.get(function)?];
+    let &local = func.name_to_local.get(name)?;
+    Some(type_name(widen(func.types[local])))
+}
+
+fn signature(func: &Function, compiled: Option<&codegen::Function>) -> String {
+    let name = &func.symbols[func.name];
+    let type_parameters = match func.type_parameters.is_empty() {
+        true => String::new(),
+        false => format!(
+            "[{}]",
+            func.type_parameters
+                .iter()
+                .map(|&index| func.symbols[index].as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    };
+    let inferred = |name: &str| {
+        compiled
+            .and_then(|compiled| {
+                let &local = compiled.name_to_local.get(name)?;
+                Some(compiled.types[local])
+            })
+            .map(|value_type| type_name(widen(value_type)))
+    };
+    let arguments = func
+        .arguments
+        .iter()
+        .zip(func.argument_types.iter())
+        .map(|(&argument, &annotation)| {
+            let argument = &func.symbols[argument];
+            match (annotation, inferred(argument)) {
+                (Some(annotation), _) => format!("{}: {}", argument, func.symbols[annotation]),
+                (None, Some(inferred)) => format!("{}: {}", argument, inferred),
+                (None, None) => argument.clone(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(", ");
+    let result = func
+        .return_type
+        .map(|index| func.symbols[index].as_str())
+        .or_else(|| compiled.map(|compiled| type_name(compiled.result)));
+    match result {
+        Some(result) => format!(
+            "def {}{}({}) -> {}",
+            name, type_parameters, arguments, result
+        ),
+        None => format!("def {}{}({})", name, type_parameters, arguments),
+    }
+}
+
+fn describe(
+    server: &Server,
+    uri: &str,
+    top_level: &TopLevel,
+    token: usize,
+    declaration: Declaration,
+) -> String {
+    let name = symbol(top_level, token);
+    match declaration {
+        Declaration::Function => {
+            let compiled = server.compiled.get(uri).and_then(|wasm| {
+                let &function = wasm.name_to_function.get(name)?;
+                Some(&wasm.functions[function])
+            });
+            chunk_function(server, uri, top_level)
+                .map_or_else(|| format!("def {}", name), |func| signature(func, compiled))
+        }
+        Declaration::Const => format!("const {}", name),
+        Declaration::Var => format!("var {}", name),
+        Declaration::Enum => format!("enum {}", name),
+        Declaration::Variant => {
+            let fields = (token + 1..top_level.kinds.len())
+                .take_while(|&field| top_level.kinds[field] != Kind::RightParen)
+                .filter(|&field| top_level.kinds[field] == Kind::Symbol)
+                .map(|field| symbol(top_level, field))
+                .collect::<Vec<&str>>();
+            match top_level.kinds.get(token + 1) {
+                Some(Kind::LeftParen) => format!("{}({})", name, fields.join(", ")),
+                _ => name.to_string(),
+            }
+        }
+    }
+}
+
+fn hover(server: &Server, uri: &str, position: &Json) -> Option<Json> {
+    let text = server.documents.get(uri)?;
+    let tokens = attempt(|| tokenize(text)).ok()?;
+    let (chunk, token) = symbol_at(&tokens, offset(text, position))?;
+    let top_level = &tokens.top_level[chunk];
+    let name = symbol(top_level, token);
+    let value = match local_definition(server, uri, &tokens, chunk, name) {
+        Some(_) => {
+            let function = chunk_function(server, uri, top_level)
+                .map(|func| func.symbols[func.name].as_str())
+                .unwrap_or("");
+            match local_type(server, uri, function, name) {
+                Some(local_type) => format!("{}: {}", name, local_type),
+                None => name.to_string(),
+            }
+        }
+        None => {
+            let (top_level, token, declaration) = declaration(&tokens, name)?;
+            describe(server, uri, top_level, token, declaration)
+        }
+    };
+    Some(json::object(vec![
+        (
+            "contents",
+            json::object(vec![
+                ("kind", Json::String(String::from("plaintext"))),
+                ("value", Json::String(value)),
+            ]),
+        ),
+        ("range", range(text, token_span(top_level, token))),
+    ]))
+}
+
+fn completion_kind(declaration: Declaration) -> f64 {
+    match declaration {
+        Declaration::Function => 3.0,
+        Declaration::Const => 21.0,
+        Declaration::Var => 6.0,
+        Declaration::Enum => 13.0,
+        Declaration::Variant => 20.0,
+    }
+}
+
+fn completion(server: &Server, uri: &str, position: &Json) -> Option<Json> {
+    let text = server.documents.get(uri)?;
+    let tokens = attempt(|| tokenize(text)).ok()?;
+    let offset = offset(text, position);
+    let globals = globals(&tokens);
+    let locals = chunk_at(&tokens, offset)
+        .and_then(|chunk| {
+            let top_level = &tokens.top_level[chunk];
+            let func = chunk_function(server, uri, top_level)?;
+            let locals = local_definitions(func, &globals)
+                .into_iter()
+                .filter_map(|index| symbol_token(top_level, index))
+                .filter(|&token| top_level.positions[token] < offset)
+                .map(|token| (symbol(top_level, token).to_string(), 6.0))
+                .collect::<Vec<(String, f64)>>();
+            Some(locals)
+        })
+        .unwrap_or_default();
+    let declared = tokens.top_level.iter().flat_map(|top_level| {
+        declarations(top_level)
+            .into_iter()
+            .filter(|&(_, declaration)| declaration != Declaration::Enum)
+            .map(move |(token, declaration)| {
+                (
+                    symbol(top_level, token).to_string(),
+                    completion_kind(declaration),
+                )
+            })
+    });
+    let functions = server
+        .top_level
+        .get(uri)
+        .into_iter()
+        .flatten()
+        .map(|name| (name.clone(), 3.0));
+    let items = locals.into_iter().chain(declared).chain(functions).fold(
+        BTreeMap::new(),
+        |mut items, (label, kind)| {
+            items.entry(label).or_insert(kind);
+            items
+        },
+    );
+    Some(Json::Array(
+        items
+            .into_iter()
+            .map(|(label, kind)| {
+                json::object(vec![
+                    ("label", Json::String(label)),
+                    ("kind", Json::Number(kind)),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+fn symbol_kind(declaration: Declaration) -> f64 {
+    match declaration {
+        Declaration::Function => 12.0,
+        Declaration::Const => 14.0,
+        Declaration::Var => 13.0,
+        Declaration::Enum => 10.0,
+        Declaration::Variant => 22.0,
+    }
+}
+
+fn document_symbol(
+    text: &str,
+    top_level: &TopLevel,
+    token: usize,
+    declaration: Declaration,
+    span: (usize, usize),
+    children: Vec<Json>,
+) -> Json {
+    json::object(vec![
+        ("name", Json::String(symbol(top_level, token).to_string())),
+        ("kind", Json::Number(symbol_kind(declaration))),
+        ("range", range(text, span)),
+        ("selectionRange", range(text, token_span(top_level, token))),
+        ("children", Json::Array(children)),
+    ])
+}
+
+fn document_symbols(server: &Server, uri: &str) -> Option<Json> {
+    let text = server.documents.get(uri)?;
+    let tokens = attempt(|| tokenize(text)).ok()?;
+    let symbols = tokens.top_level.iter().filter_map(|top_level| {
+        let declarations = declarations(top_level);
+        let (&(token, declaration), variants) = declarations.split_first()?;
+        let children = variants
+            .iter()
+            .map(|&(variant, declaration)| {
+                let span = token_span(top_level, variant);
+                document_symbol(text, top_level, variant, declaration, span, vec![])
+            })
+            .collect();
+        let span = chunk_span(text, top_level);
+        Some(document_symbol(
+            text,
+            top_level,
+            token,
+            declaration,
+            span,
+            children,
+        ))
+    });
+    Some(Json::Array(symbols.collect()))
+}
+
+fn diagnostic(text: &str, span: (usize, usize), message: &str, severity: f64) -> Json {
+    json::object(vec![
+        ("range", range(text, span)),
+        ("severity", Json::Number(severity)),
+        ("source", Json::String(String::from("mongoose"))),
+        ("message", Json::String(message.to_string())),
+    ])
+}
+
+fn locate(tokens: &Tokens, message: &str) -> (usize, usize) {
+    let words = message
+        .split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .collect::<Vec<&str>>();
+    tokens
+        .top_level
+        .iter()
+        .find_map(|top_level| {
+            (0..top_level.kinds.len())
+                .find(|&token| {
+                    top_level.kinds[token] == Kind::Symbol
+                        && words.contains(&symbol(top_level, token))
+                })
+                .map(|token| token_span(top_level, token))
+        })
+        .unwrap_or((0, 0))
+}
+
+fn uri_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let bytes = path.as_bytes();
+    let decoded = (0..bytes.len())
+        .fold((vec![], 0), |(mut decoded, skip), i| match skip {
+            0 if bytes[i] == b'%' => {
+                match path
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        (decoded, 2)
+                    }
+                    None => {
+                        decoded.push(bytes[i]);
+                        (decoded, 0)
+                    }
+                }
+            }
+            0 => {
+                decoded.push(bytes[i]);
+                (decoded, 0)
+            }
+            skip => (decoded, skip - 1),
+        })
+        .0;
+    PathBuf::from(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn compile(mut server: Server, uri: &str, text: &str, tokens: &Tokens) -> (Server, Vec<Json>) {
+    let path = uri_path(uri);
+    let search_path = path
+        .parent()
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(server.search_path.iter().cloned())
+        .collect::<Vec<PathBuf>>();
+    let name = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("main");
+    let ast = match attempt(|| load_source(name, text, &search_path)) {
+        Ok(ast) => ast,
+        Err(message) => {
+            let error = diagnostic(text, locate(tokens, &message), &message, ERROR);
+            return (server, vec![error]);
+        }
+    };
+    let mut names = ast.top_level.keys().cloned().collect::<Vec<String>>();
+    names.sort();
+    server.top_level.insert(uri.to_string(), names);
+    let cache = &server.cache;
+    match attempt(|| codegen_cached(ast, cache)) {
+        Ok(wasm) => {
+            let warnings = wasm
+                .warnings
+                .iter()
+                .map(|warning| diagnostic(text, locate(tokens, warning), warning, WARNING))
+                .collect();
+            server.compiled.insert(uri.to_string(), wasm);
+            (server, warnings)
+        }
+        Err(message) => {
+            let error = diagnostic(text, locate(tokens, &message), &message, ERROR);
+            (server, vec![error])
+        }
+    }
+}
+
+fn publish(uri: &str, diagnostics: Vec<Json>) -> Json {
+    json::object(vec![
+        ("jsonrpc", Json::String(String::from("2.0"))),
+        (
+            "method",
+            Json::String(String::from("textDocument/publishDiagnostics")),
+        ),
+        (
+            "params",
+            json::object(vec![
+                ("uri", Json::String(uri.to_string())),
+                ("diagnostics", Json::Array(diagnostics)),
+            ]),
+        ),
+    ])
+}
+
+fn analyze(mut server: Server, uri: &str) -> (Server, Json) {
+    let text = server.documents[uri].clone();
+    let tokens = match attempt(|| tokenize(&text)) {
+        Ok(tokens) => tokens,
+        Err(_) => {
+            server.chunks.remove(uri);
+            return (
+                server,
+                publish(uri, vec![diagnostic(&text, (0, 0), SYNTAX_ERROR, ERROR)]),
+            );
+        }
+    };
+    let mut previous = server.chunks.remove(uri).unwrap_or_default();
+    let chunks = tokens
+        .top_level
+        .iter()
+        .map(|top_level| {
+            let hash = tokenizer::hash(top_level);
+            let parsed = previous.remove(&hash).unwrap_or_else(|| {
+                attempt(|| {
+                    parse(Tokens {
+                        top_level: vec![top_level.clone()],
+                    })
+                })
+            });
+            (hash, parsed)
+        })
+        .collect::<HashMap<u64, Result<Ast, String>>>();
+    let errors = tokens
+        .top_level
+        .iter()
+        .filter_map(|top_level| match &chunks[&tokenizer::hash(top_level)] {
+            Ok(_) => None,
+            Err(_) => Some(diagnostic(
+                &text,
+                chunk_span(&text, top_level),
+                SYNTAX_ERROR,
+                ERROR,
+            )),
+        })
+        .collect::<Vec<Json>>();
+    server.chunks.insert(uri.to_string(), chunks);
+    let (server, diagnostics) = match errors.is_empty() {
+        true => compile(server, uri, &text, &tokens),
+        false => (server, errors),
+    };
+    (server, publish(uri, diagnostics))
+}
+
+fn response(id: Json, result: Json) -> Json {
+    json::object(vec![
+        ("jsonrpc", Json::String(String::from("2.0"))),
+        ("id", id),
+        ("result", result),
+    ])
+}
+
+fn error(id: Json, code: f64, message: String) -> Json {
+    json::object(vec![
+        ("jsonrpc", Json::String(String::from("2.0"))),
+        ("id", id),
+        (
+            "error",
+            json::object(vec![
+                ("code", Json::Number(code)),
+                ("message", Json::String(message)),
+            ]),
+        ),
+    ])
+}
+
+fn capabilities() -> Json {
+    json::object(vec![
+        (
+            "capabilities",
+            json::object(vec![
+                ("textDocumentSync", Json::Number(1.0)),
+                ("definitionProvider", Json::Bool(true)),
+                ("hoverProvider", Json::Bool(true)),
+                ("completionProvider", json::object(vec![])),
+                ("documentSymbolProvider", Json::Bool(true)),
+            ]),
+        ),
+        (
+            "serverInfo",
+            json::object(vec![("name", Json::String(String::from("mongoose")))]),
+        ),
+    ])
+}
+
+fn open(mut server: Server, uri: &str, text: Option<&str>) -> (Server, Vec<Json>) {
+    match text {
+        Some(text) => {
+            server.documents.insert(uri.to_string(), text.to_string());
+            let (server, diagnostics) = analyze(server, uri);
+            (server, vec![diagnostics])
+        }
+        None => (server, vec![]),
+    }
+}
+
+fn close(mut server: Server, uri: &str) -> (Server, Vec<Json>) {
+    server.documents.remove(uri);
+    server.chunks.remove(uri);
+    server.compiled.remove(uri);
+    server.top_level.remove(uri);
+    (server, vec![publish(uri, vec![])])
+}
+
+pub fn handle(mut server: Server, message: &Json) -> (Server, Vec<Json>) {
+    let id = json::get(message, "id").clone();
+    let params = json::get(message, "params");
+    let uri = json::string(json::path(params, &["textDocument", "uri"])).unwrap_or("");
+    let position = json::get(params, "position");
+    let result = |result: Option<Json>| vec![response(id.clone(), result.unwrap_or(Json::Null))];
+    match json::string(json::get(message, "method")).unwrap_or("") {
+        "initialize" => (server, result(Some(capabilities()))),
+        "shutdown" => (server, result(None)),
+        "exit" => {
+            server.exited = true;
+            (server, vec![])
+        }
+        "textDocument/didOpen" => {
+            let text = json::string(json::path(params, &["textDocument", "text"]));
+            open(server, uri, text)
+        }
+        "textDocument/didChange" => {
+            let text = match json::get(params, "contentChanges") {
+                Json::Array(changes) => changes
+                    .last()
+                    .and_then(|change| json::string(json::get(change, "text"))),
+                _ => None,
+            };
+            open(server, uri, text)
+        }
+        "textDocument/didClose" => close(server, uri),
+        "textDocument/definition" => {
+            let definition = definition(&server, uri, position);
+            (server, result(definition))
+        }
+        "textDocument/hover" => {
+            let hover = hover(&server, uri, position);
+            (server, result(hover))
+        }
+        "textDocument/completion" => {
+            let completion = completion(&server, uri, position);
+            (server, result(completion))
+        }
+        "textDocument/documentSymbol" => {
+            let symbols = document_symbols(&server, uri);
+            (server, result(symbols))
+        }
+        method if id != Json::Null => {
+            let error = error(id, -32601.0, format!("Unknown method {}", method));
+            (server, vec![error])
+        }
+        _ => (server, vec![]),
+    }
+}
+
+pub fn read_message(reader: &mut impl BufRead) -> Option<Json> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        match line.trim_end().split_once(':') {
+            Some((name, value)) if name.eq_ignore_ascii_case("content-length") => {
+                length = value.trim().parse().ok();
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    attempt(|| json::parse(&String::from_utf8_lossy(&body))).ok()
+}
+
+pub fn write_message(writer: &mut impl Write, message: &Json) {
+    let body = json::write(message);
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+    writer.flush().unwrap();
+}
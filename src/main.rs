@@ -1,21 +1,237 @@
 use std::{
     env,
-    fs::File,
-    io::{Read, Write},
+    fs::{self, File},
+    io::{self, BufRead, Write},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
 };
 
 use wasmer::{imports, Instance, Module, Store};
 
-use mongoose::{codegen::codegen, parser::parse, tokenizer::tokenize, writer::write};
+use mongoose::{
+    artifact::{artifact_path, load_module, save, source_files, stamps},
+    codegen::{codegen, codegen_cached, Wasm},
+    formatter::{format, sources},
+    interpreter::interpret,
+    loader::{link, load, load_all, load_packages, load_project},
+    lsp::{handle, read_message, server, write_message},
+    manifest::read_manifest,
+    optimizer::optimize,
+    parser::Ast,
+    repl::{evaluate, session},
+    writer::write,
+};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    let mut file = File::open(&args[1]).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    let tokens = tokenize(&contents);
-    let ast = parse(tokens);
-    let wasm = codegen(ast);
+fn compile(ast: Ast, optimization: bool, cache: Option<&Path>) -> Wasm {
+    let wasm = match cache {
+        Some(cache) => codegen_cached(ast, cache),
+        None => codegen(ast),
+    };
+    for warning in wasm.warnings.iter() {
+        eprintln!("warning: {}", warning);
+    }
+    match optimization {
+        true => optimize(wasm),
+        false => wasm,
+    }
+}
+
+fn start(module: Module) {
+    let import_object = imports! {};
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    let result = start.call(&[]).unwrap();
+    println!("{:?}", result[0]);
+}
+
+fn build(args: &[String], optimization: bool, aot: bool) {
+    let directory = Path::new(args.get(2).map(String::as_str).unwrap_or("."));
+    let manifest = read_manifest(directory);
+    let name = manifest.name.clone();
+    let ast = load_project(directory, manifest);
+    let output = directory.join("build");
+    let code = write(compile(ast, optimization, Some(&output.join("cache"))));
+    fs::create_dir_all(&output).unwrap();
+    let mut file = File::create(output.join(format!("{}.wat", name))).unwrap();
+    write!(file, "{}", code).unwrap();
+    if aot {
+        let store = Store::default();
+        let module = Module::new(&store, &code).unwrap();
+        save(&module, &artifact_path(directory, &name, optimization));
+    }
+}
+
+fn run_project(args: &[String], optimization: bool, aot: bool) {
+    let directory = Path::new(args.get(2).map(String::as_str).unwrap_or("."));
+    let manifest = read_manifest(directory);
+    let artifact = artifact_path(directory, &manifest.name, optimization);
+    let sources = source_files(&load_packages(directory, manifest));
+    let store = Store::default();
+    let module = load_module(&store, &artifact, &sources, aot, || {
+        let ast = load_project(directory, read_manifest(directory));
+        let cache = directory.join("build").join("cache");
+        write(compile(ast, optimization, Some(&cache)))
+    });
+    start(module);
+}
+
+fn search_path() -> Vec<PathBuf> {
+    env::var_os("MONGOOSE_PATH")
+        .map(|paths| env::split_paths(&paths).collect())
+        .unwrap_or_default()
+}
+
+fn rebuild(entry: &Path, optimization: bool, cache: &Path) -> Vec<PathBuf> {
+    let modules = load_all(entry, &search_path());
+    let sources = modules.paths.clone();
+    let code = write(compile(link(modules), optimization, Some(cache)));
+    let store = Store::default();
+    start(Module::new(&store, &code).unwrap());
+    sources
+}
+
+fn report_panics() {
+    panic::set_hook(Box::new(|info| {
+        match info.payload().downcast_ref::<String>() {
+            Some(message) => eprintln!("error: {}", message),
+            None => match info.payload().downcast_ref::<&str>() {
+                Some(message) => eprintln!("error: {}", message),
+                None => eprintln!("error: {}", info),
+            },
+        }
+    }));
+}
+
+fn watch(args: &[String], optimization: bool) {
+    let entry = PathBuf::from(&args[2]);
+    let cache = env::temp_dir().join("mongoose").join("cache");
+    report_panics();
+    let mut sources = vec![entry.clone()];
+    let mut seen = vec![];
+    loop {
+        let current = stamps(&sources);
+        if current != seen {
+            eprintln!("Compiling {}", entry.display());
+            let rebuilt =
+                panic::catch_unwind(AssertUnwindSafe(|| rebuild(&entry, optimization, &cache)));
+            if let Ok(rebuilt) = rebuilt {
+                sources = rebuilt;
+            }
+            if !sources.contains(&entry) {
+                sources.push(entry.clone());
+            }
+            seen = stamps(&sources);
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+fn read_entry(lines: &mut impl Iterator<Item = io::Result<String>>) -> Option<String> {
+    print!(">>> ");
+    io::stdout().flush().unwrap();
+    let first = lines.next()?.unwrap();
+    let mut entry = first.clone();
+    if first.trim_end().ends_with(':') {
+        loop {
+            print!("... ");
+            io::stdout().flush().unwrap();
+            match lines.next() {
+                Some(line) if !line.as_ref().unwrap().trim().is_empty() => {
+                    entry = format!("{}\n{}", entry, line.unwrap());
+                }
+                _ => break,
+            }
+        }
+    }
+    Some(entry)
+}
+
+fn repl() {
+    report_panics();
+    let search_path = env::current_dir()
+        .into_iter()
+        .chain(search_path())
+        .collect();
+    let mut session = session(search_path);
+    let mut lines = io::stdin().lock().lines();
+    while let Some(entry) = read_entry(&mut lines) {
+        let previous = session.clone();
+        session = match panic::catch_unwind(AssertUnwindSafe(|| evaluate(session, &entry))) {
+            Ok((session, output)) => {
+                if !output.is_empty() {
+                    println!("{}", output);
+                }
+                session
+            }
+            Err(_) => previous,
+        };
+    }
+}
+
+fn lsp() {
+    panic::set_hook(Box::new(|_| {}));
+    let cache = env::temp_dir().join("mongoose").join("lsp");
+    let mut server = server(search_path(), cache);
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    while !server.exited {
+        let message = match read_message(&mut input) {
+            Some(message) => message,
+            None => break,
+        };
+        let (next, responses) = handle(server, &message);
+        server = next;
+        for response in responses.iter() {
+            write_message(&mut output, response);
+        }
+    }
+}
+
+fn fmt(args: &[String]) {
+    report_panics();
+    let check = args.iter().any(|arg| arg == "--check");
+    let paths = args[2..]
+        .iter()
+        .filter(|arg| *arg != "--check")
+        .map(PathBuf::from)
+        .collect::<Vec<PathBuf>>();
+    let paths = match paths.is_empty() {
+        true => vec![PathBuf::from(".")],
+        false => paths,
+    };
+    let unformatted = paths
+        .iter()
+        .flat_map(|path| sources(path))
+        .filter(|path| {
+            let source = fs::read_to_string(path)
+                .unwrap_or_else(|error| panic!("Could not read {}: {}", path.display(), error));
+            let formatted = format(&source);
+            if formatted != source && !check {
+                fs::write(path, &formatted).unwrap_or_else(|error| {
+                    panic!("Could not write {}: {}", path.display(), error)
+                });
+            }
+            formatted != source
+        })
+        .collect::<Vec<PathBuf>>();
+    for path in unformatted.iter() {
+        println!("{}", path.display());
+    }
+    if check && !unformatted.is_empty() {
+        process::exit(1);
+    }
+}
+
+fn eval(args: &[String]) {
+    let ast = load(Path::new(&args[2]), &search_path());
+    println!("{:?}", interpret(&ast));
+}
+
+fn run_file(args: &[String], optimization: bool) {
+    let ast = load(Path::new(&args[1]), &search_path());
+    let wasm = compile(ast, optimization, None);
     match args.get(2) {
         Some(s) if s == "--emit-wasm" => {
             let mut file = File::create(&args[3]).unwrap();
@@ -26,11 +242,24 @@ fn main() {
             let code = write(wasm);
             let store = Store::default();
             let module = Module::new(&store, &code).unwrap();
-            let import_object = imports! {};
-            let instance = Instance::new(&module, &import_object).unwrap();
-            let start = instance.exports.get_function("_start").unwrap();
-            let result = start.call(&[]).unwrap();
-            println!("{:?}", result[0]);
+            start(module);
         }
     }
 }
+
+fn main() {
+    let (flags, args): (Vec<String>, Vec<String>) =
+        env::args().partition(|arg| arg == "-O0" || arg == "--aot");
+    let optimization = !flags.iter().any(|flag| flag == "-O0");
+    let aot = flags.iter().any(|flag| flag == "--aot");
+    match args[1].as_str() {
+        "build" => build(&args, optimization, aot),
+        "run" => run_project(&args, optimization, aot),
+        "watch" => watch(&args, optimization),
+        "repl" => repl(),
+        "eval" => eval(&args),
+        "lsp" => lsp(),
+        "fmt" => fmt(&args),
+        _ => run_file(&args, optimization),
+    }
+}
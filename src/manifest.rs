@@ -0,0 +1,152 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const MANIFEST: &str = "mongoose.toml";
+
+#[derive(Debug, PartialEq)]
+pub struct Dependencies {
+    pub names: Vec<String>,
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub sources: Vec<PathBuf>,
+    pub entries: Vec<String>,
+    pub dependencies: Dependencies,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+enum Section {
+    None,
+    Package,
+    Dependencies,
+}
+
+fn parse_string(value: &str) -> (String, &str) {
+    let value = value.trim_start();
+    assert!(
+        value.starts_with('"'),
+        "Parsing manifest, expected string, found {}",
+        value
+    );
+    let length = value[1..]
+        .find('"')
+        .unwrap_or_else(|| panic!("Parsing manifest, unterminated string {}", value));
+    (value[1..length + 1].to_string(), &value[length + 2..])
+}
+
+fn parse_strings(value: &str, mut strings: Vec<String>) -> Vec<String> {
+    let value = value.trim_start();
+    match value.chars().next() {
+        Some(']') => strings,
+        Some(',') => parse_strings(&value[1..], strings),
+        Some('"') => {
+            let (string, value) = parse_string(value);
+            strings.push(string);
+            parse_strings(value, strings)
+        }
+        _ => panic!("Parsing manifest, expected string or ], found {}", value),
+    }
+}
+
+fn parse_array(value: &str) -> Vec<String> {
+    let value = value.trim_start();
+    assert!(
+        value.starts_with('['),
+        "Parsing manifest, expected array, found {}",
+        value
+    );
+    parse_strings(&value[1..], vec![])
+}
+
+fn parse_dependency(value: &str) -> PathBuf {
+    let value = value.trim();
+    assert!(
+        value.starts_with('{') && value.ends_with('}'),
+        "Parsing manifest, dependencies must be tables like {{ path = \"../utils\" }}, found {}",
+        value
+    );
+    let (key, value) = split_key_value(&value[1..value.len() - 1]);
+    assert_eq!(key, "path", "Only path dependencies are supported");
+    PathBuf::from(parse_string(value).0)
+}
+
+fn split_key_value(line: &str) -> (&str, &str) {
+    let equal = line
+        .find('=')
+        .unwrap_or_else(|| panic!("Parsing manifest, expected key = value, found {}", line));
+    (line[..equal].trim(), &line[equal + 1..])
+}
+
+fn strip_comment(line: &str) -> &str {
+    let quotes = line
+        .char_indices()
+        .filter(|&(_, c)| c == '"')
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>();
+    line.char_indices()
+        .find(|&(i, c)| c == '#' && quotes.iter().filter(|&&quote| quote < i).count() % 2 == 0)
+        .map(|(i, _)| &line[..i])
+        .unwrap_or(line)
+}
+
+fn parse_line(mut manifest: Manifest, section: Section, line: &str) -> Manifest {
+    let (key, value) = split_key_value(line);
+    match (section, key) {
+        (Section::Package, "name") => manifest.name = parse_string(value).0,
+        (Section::Package, "version") => manifest.version = parse_string(value).0,
+        (Section::Package, "sources") => {
+            manifest.sources = parse_array(value).into_iter().map(PathBuf::from).collect()
+        }
+        (Section::Package, "entries") => manifest.entries = parse_array(value),
+        (Section::Dependencies, name) => {
+            manifest.dependencies.names.push(name.to_string());
+            manifest.dependencies.paths.push(parse_dependency(value));
+        }
+        (section, key) => panic!("Parsing manifest, unknown key {} in {:?}", key, section),
+    }
+    manifest
+}
+
+pub fn parse_manifest(source: &str) -> Manifest {
+    let manifest = Manifest {
+        name: String::new(),
+        version: String::new(),
+        sources: vec![PathBuf::from("src")],
+        entries: vec![String::from("main")],
+        dependencies: Dependencies {
+            names: vec![],
+            paths: vec![],
+        },
+    };
+    let (manifest, _) = source
+        .lines()
+        .map(|line| strip_comment(line).trim())
+        .filter(|line| !line.is_empty())
+        .fold(
+            (manifest, Section::None),
+            |(manifest, section), line| match line {
+                "[package]" => (manifest, Section::Package),
+                "[dependencies]" => (manifest, Section::Dependencies),
+                _ if line.starts_with('[') => panic!("Parsing manifest, unknown section {}", line),
+                _ => (parse_line(manifest, section, line), section),
+            },
+        );
+    assert!(
+        !manifest.name.is_empty(),
+        "Manifest is missing a package name"
+    );
+    manifest
+}
+
+pub fn read_manifest(directory: &Path) -> Manifest {
+    let path = directory.join(MANIFEST);
+    let source = fs::read_to_string(&path)
+        .unwrap_or_else(|error| panic!("Could not read {}: {}", path.display(), error));
+    parse_manifest(&source)
+}
@@ -0,0 +1,788 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    codegen::{
+        fold_binary_op, get_local, i64_binary_op, i64_const, instruction, is_comparison, local,
+        set_local, symbol, Function, Instruction, OperandKind, Type, Wasm,
+    },
+    ir::lower_functions,
+    peephole::peephole,
+};
+
+const INLINE_SIZE: usize = 16;
+
+fn int(func: &Function, i: usize) -> Option<i64> {
+    match (&func.instructions[i], &func.operand_kinds[i][..]) {
+        (Instruction::I64Const, [OperandKind::IntLiteral]) => {
+            func.ints[func.operands[i][0]].parse().ok()
+        }
+        _ => None,
+    }
+}
+
+fn pure(func: &Function, i: usize) -> bool {
+    matches!(
+        func.instructions[i],
+        Instruction::I64Const
+            | Instruction::F64Const
+            | Instruction::GetLocal
+            | Instruction::GlobalGet
+    )
+}
+
+fn power_of_two(value: i64) -> Option<i64> {
+    match value > 1 && value & (value - 1) == 0 {
+        true => Some(value.trailing_zeros() as i64),
+        false => None,
+    }
+}
+
+fn fold(op: Instruction, left: i64, right: i64) -> Option<i64> {
+    match i64_binary_op(op)? {
+        op if is_comparison(op) => None,
+        op => fold_binary_op(op, left, right),
+    }
+}
+
+fn truncate(mut func: Function, length: usize) -> Function {
+    func.instructions.truncate(length);
+    func.operand_kinds.truncate(length);
+    func.operands.truncate(length);
+    func
+}
+
+fn remove(mut func: Function, i: usize) -> Function {
+    func.instructions.remove(i);
+    func.operand_kinds.remove(i);
+    func.operands.remove(i);
+    func
+}
+
+fn shift_left(func: Function, length: usize, shift: i64) -> Function {
+    let func = i64_const(truncate(func, length), shift);
+    instruction(func, Instruction::I64Shl)
+}
+
+fn divide(func: Function, length: usize, shift: i64) -> Function {
+    let name = format!(".divide.{}", func.locals.len());
+    let (func, dividend) = local(truncate(func, length), &name, Type::I64);
+    let func = set_local(func, dividend);
+    let func = get_local(func, dividend);
+    let func = get_local(func, dividend);
+    let func = i64_const(func, 63);
+    let func = instruction(func, Instruction::I64ShrS);
+    let func = i64_const(func, (1 << shift) - 1);
+    let func = instruction(func, Instruction::I64And);
+    let func = instruction(func, Instruction::I64Add);
+    let func = i64_const(func, shift);
+    instruction(func, Instruction::I64ShrS)
+}
+
+fn reduce(func: Function) -> Function {
+    let length = func.instructions.len();
+    if length < 2 {
+        return func;
+    }
+    let op = func.instructions[length - 1];
+    let right = int(&func, length - 2);
+    let left = match length {
+        2 => None,
+        _ => int(&func, length - 3),
+    };
+    if let (Some(left), Some(right)) = (left, right) {
+        if let Some(value) = fold(op, left, right) {
+            return reduce(i64_const(truncate(func, length - 3), value));
+        }
+    }
+    match (right, op) {
+        (
+            Some(0),
+            Instruction::I64Add
+            | Instruction::I64Sub
+            | Instruction::I64Or
+            | Instruction::I64Xor
+            | Instruction::I64Shl
+            | Instruction::I64ShrS,
+        )
+        | (Some(1), Instruction::I64Mul | Instruction::I64DivS) => {
+            return reduce(truncate(func, length - 2))
+        }
+        (Some(right), Instruction::I64Mul) => {
+            if let Some(shift) = power_of_two(right) {
+                return shift_left(func, length - 2, shift);
+            }
+        }
+        (Some(right), Instruction::I64DivS) => {
+            if let Some(shift) = power_of_two(right) {
+                return divide(func, length - 2, shift);
+            }
+        }
+        _ => {}
+    }
+    if length < 3 || !pure(&func, length - 2) {
+        return func;
+    }
+    match (int(&func, length - 3), op) {
+        (Some(0), Instruction::I64Add | Instruction::I64Or | Instruction::I64Xor)
+        | (Some(1), Instruction::I64Mul) => reduce(remove(truncate(func, length - 1), length - 3)),
+        (Some(left), Instruction::I64Mul) => match power_of_two(left) {
+            Some(shift) => shift_left(remove(func, length - 3), length - 2, shift),
+            None => func,
+        },
+        _ => func,
+    }
+}
+
+fn simplify(func: Function) -> Function {
+    let instructions = func.instructions;
+    let operand_kinds = func.operand_kinds;
+    let operands = func.operands;
+    let func = Function {
+        instructions: vec![],
+        operand_kinds: vec![],
+        operands: vec![],
+        ..func
+    };
+    instructions
+        .into_iter()
+        .zip(operand_kinds)
+        .zip(operands)
+        .fold(
+            func,
+            |mut func, ((instruction, operand_kinds), operands)| {
+                func.instructions.push(instruction);
+                func.operand_kinds.push(operand_kinds);
+                func.operands.push(operands);
+                reduce(func)
+            },
+        )
+}
+
+fn dominates(func: &Function, set: usize, get: usize) -> bool {
+    set < get
+        && func.instructions[set + 1..get]
+            .iter()
+            .try_fold(0, |depth, instruction| match instruction {
+                Instruction::Block | Instruction::Loop | Instruction::If => Some(depth + 1),
+                Instruction::Else if depth > 0 => Some(depth),
+                Instruction::End if depth > 0 => Some(depth - 1),
+                Instruction::Else | Instruction::End => None,
+                _ => Some(depth),
+            })
+            .is_some()
+}
+
+fn constant_local(func: &Function, local: usize) -> Option<usize> {
+    let accesses = |instruction| {
+        (0..func.instructions.len())
+            .filter(move |&i| func.instructions[i] == instruction && func.operands[i] == [local])
+    };
+    let sets = accesses(Instruction::SetLocal).collect::<Vec<usize>>();
+    let set = match (&sets[..], accesses(Instruction::TeeLocal).next()) {
+        (&[set], None) if set > 0 => set,
+        _ => return None,
+    };
+    let constant = matches!(
+        (
+            &func.instructions[set - 1],
+            &func.operand_kinds[set - 1][..]
+        ),
+        (Instruction::I64Const, [OperandKind::IntLiteral])
+            | (Instruction::F64Const, [OperandKind::FloatLiteral])
+    );
+    match constant && accesses(Instruction::GetLocal).all(|get| dominates(func, set, get)) {
+        true => Some(set),
+        false => None,
+    }
+}
+
+fn propagate_constant(mut func: Function, local: usize, set: usize) -> Function {
+    for i in set + 1..func.instructions.len() {
+        if func.instructions[i] == Instruction::GetLocal && func.operands[i] == [local] {
+            func.instructions[i] = func.instructions[set - 1];
+            func.operand_kinds[i] = func.operand_kinds[set - 1].clone();
+            func.operands[i] = func.operands[set - 1].clone();
+        }
+    }
+    remove(remove(func, set), set - 1)
+}
+
+fn propagate_constants(func: Function) -> Function {
+    let constant = (func.arguments..func.locals.len())
+        .find_map(|local| constant_local(&func, local).map(|set| (local, set)));
+    match constant {
+        Some((local, set)) => propagate_constants(propagate_constant(func, local, set)),
+        None => func,
+    }
+}
+
+fn kept<T>(values: Vec<T>, keep: &impl Fn(usize) -> bool) -> Vec<T> {
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|&(i, _)| keep(i))
+        .map(|(_, value)| value)
+        .collect()
+}
+
+fn keep(func: Function, keep: impl Fn(usize) -> bool) -> Function {
+    let instructions = kept(func.instructions, &keep);
+    let operand_kinds = kept(func.operand_kinds, &keep);
+    let operands = kept(func.operands, &keep);
+    Function {
+        instructions,
+        operand_kinds,
+        operands,
+        ..func
+    }
+}
+
+fn comparison(op: Instruction, left: i64, right: i64) -> Option<bool> {
+    match i64_binary_op(op)? {
+        op if is_comparison(op) => fold_binary_op(op, left, right).map(|value| value != 0),
+        _ => None,
+    }
+}
+
+fn constant_condition(func: &Function, i: usize) -> Option<(usize, bool)> {
+    let (end, negate) = match i > 0 && func.instructions[i - 1] == Instruction::I32Eqz {
+        true => (i - 1, true),
+        false => (i, false),
+    };
+    if end < 3 {
+        return None;
+    }
+    let left = int(func, end - 3)?;
+    let right = int(func, end - 2)?;
+    let value = comparison(func.instructions[end - 1], left, right)?;
+    Some((end - 3, value != negate))
+}
+
+fn block_end(func: &Function, start: usize) -> usize {
+    func.instructions[start + 1..]
+        .iter()
+        .try_fold(
+            (start + 1, 0),
+            |(i, depth), instruction| match instruction {
+                Instruction::Block | Instruction::Loop | Instruction::If => Ok((i + 1, depth + 1)),
+                Instruction::Else | Instruction::End if depth == 0 => Err(i),
+                Instruction::End => Ok((i + 1, depth - 1)),
+                _ => Ok((i + 1, depth)),
+            },
+        )
+        .map_or_else(|i| i, |(i, _)| i)
+}
+
+fn dead_code(func: &Function, i: usize) -> Option<(Vec<bool>, Option<usize>)> {
+    let mask = |keep: &dyn Fn(usize) -> bool| (0..func.instructions.len()).map(keep).collect();
+    match func.instructions[i] {
+        Instruction::If => {
+            let (start, value) = constant_condition(func, i)?;
+            let alternative = block_end(func, i);
+            let end = match func.instructions[alternative] {
+                Instruction::Else => block_end(func, alternative),
+                _ => alternative,
+            };
+            let (low, high) = match value {
+                true => (i + 1, alternative),
+                false => (alternative + 1, end),
+            };
+            Some((
+                mask(&|j| j < start || (low..high).contains(&j) || j > end),
+                None,
+            ))
+        }
+        Instruction::BrIf => match constant_condition(func, i)? {
+            (start, true) => Some((mask(&|j| j < start || j >= i), Some(i))),
+            (start, false) => Some((mask(&|j| j < start || j > i), None)),
+        },
+        Instruction::Br | Instruction::BrTable | Instruction::Return | Instruction::Unreachable => {
+            let end = block_end(func, i);
+            match end > i + 1 {
+                true => Some((mask(&|j| j <= i || j >= end), None)),
+                false => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn eliminate_dead_code(mut func: Function) -> Function {
+    match (0..func.instructions.len()).find_map(|i| dead_code(&func, i)) {
+        Some((mask, branch)) => {
+            if let Some(branch) = branch {
+                func.instructions[branch] = Instruction::Br;
+            }
+            eliminate_dead_code(keep(func, |j| mask[j]))
+        }
+        None => func,
+    }
+}
+
+fn accessed(func: &Function, instruction: Instruction, local: usize) -> bool {
+    func.instructions
+        .iter()
+        .zip(&func.operands)
+        .any(|(&other, operands)| other == instruction && operands[..] == [local])
+}
+
+fn eliminate_dead_stores(mut func: Function) -> Function {
+    let store = (0..func.instructions.len()).find(|&i| {
+        func.instructions[i] == Instruction::SetLocal
+            && !accessed(&func, Instruction::GetLocal, func.operands[i][0])
+    });
+    match store {
+        Some(i) if i > 0 && pure(&func, i - 1) => {
+            eliminate_dead_stores(keep(func, |j| j + 1 != i && j != i))
+        }
+        Some(i) => {
+            func.instructions[i] = Instruction::Drop;
+            func.operand_kinds[i] = vec![];
+            func.operands[i] = vec![];
+            eliminate_dead_stores(func)
+        }
+        None => func,
+    }
+}
+
+fn remove_unused_locals(mut func: Function) -> Function {
+    let kept = (0..func.locals.len())
+        .filter(|&local| {
+            local < func.arguments
+                || accessed(&func, Instruction::SetLocal, local)
+                || accessed(&func, Instruction::TeeLocal, local)
+                || accessed(&func, Instruction::GetLocal, local)
+        })
+        .collect::<Vec<usize>>();
+    let renumbered = kept
+        .iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new))
+        .collect::<HashMap<usize, usize>>();
+    for i in 0..func.instructions.len() {
+        if func.operand_kinds[i] == [OperandKind::Local] {
+            func.operands[i] = vec![renumbered[&func.operands[i][0]]];
+        }
+    }
+    func.locals = kept
+        .iter()
+        .map(|&local| func.locals[local].clone())
+        .collect();
+    func.types = kept.iter().map(|&local| func.types[local]).collect();
+    func.name_to_local = func
+        .name_to_local
+        .into_iter()
+        .filter_map(|(name, local)| Some((name, *renumbered.get(&local)?)))
+        .collect();
+    func
+}
+
+fn labels(func: &Function) -> HashMap<usize, usize> {
+    (0..func.instructions.len())
+        .filter(|&i| {
+            matches!(func.instructions[i], Instruction::Block | Instruction::Loop)
+                && func.operand_kinds[i].first() == Some(&OperandKind::Label)
+        })
+        .map(|i| (func.operands[i][0], i))
+        .collect()
+}
+
+fn successors(func: &Function, labels: &HashMap<usize, usize>, i: usize) -> Vec<usize> {
+    let target = |label: &usize| {
+        let start = labels[label];
+        match func.instructions[start] {
+            Instruction::Loop => start,
+            _ => block_end(func, start),
+        }
+    };
+    let successors = match func.instructions[i] {
+        Instruction::Br => vec![target(&func.operands[i][0])],
+        Instruction::BrIf => vec![i + 1, target(&func.operands[i][0])],
+        Instruction::BrTable => func.operands[i].iter().map(target).collect(),
+        Instruction::Return | Instruction::Unreachable => vec![],
+        Instruction::If => {
+            let alternative = block_end(func, i);
+            match func.instructions[alternative] {
+                Instruction::Else => vec![i + 1, alternative + 1],
+                _ => vec![i + 1, alternative],
+            }
+        }
+        Instruction::Else => vec![block_end(func, i)],
+        _ => vec![i + 1],
+    };
+    successors
+        .into_iter()
+        .filter(|&successor| successor < func.instructions.len())
+        .collect()
+}
+
+fn liveness(func: &Function, successors: &[Vec<usize>]) -> Vec<HashSet<usize>> {
+    let mut live_in = vec![HashSet::new(); func.instructions.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..func.instructions.len()).rev() {
+            let mut live = successors[i]
+                .iter()
+                .flat_map(|&successor| live_in[successor].iter().copied())
+                .collect::<HashSet<usize>>();
+            match func.instructions[i] {
+                Instruction::SetLocal | Instruction::TeeLocal => {
+                    live.remove(&func.operands[i][0]);
+                }
+                Instruction::GetLocal => {
+                    live.insert(func.operands[i][0]);
+                }
+                _ => {}
+            }
+            if live != live_in[i] {
+                live_in[i] = live;
+                changed = true;
+            }
+        }
+    }
+    live_in
+}
+
+fn interference(func: &Function) -> HashSet<(usize, usize)> {
+    let labels = labels(func);
+    let successors = (0..func.instructions.len())
+        .map(|i| successors(func, &labels, i))
+        .collect::<Vec<Vec<usize>>>();
+    let live_in = liveness(func, &successors);
+    let entry = live_in
+        .first()
+        .into_iter()
+        .flatten()
+        .flat_map(|&live| (0..func.arguments).map(move |argument| (argument, live)));
+    let stores = (0..func.instructions.len())
+        .filter(|&i| {
+            matches!(
+                func.instructions[i],
+                Instruction::SetLocal | Instruction::TeeLocal
+            )
+        })
+        .flat_map(|i| {
+            let local = func.operands[i][0];
+            successors[i]
+                .iter()
+                .flat_map(|&successor| live_in[successor].iter())
+                .map(move |&live| (local, live))
+        });
+    entry
+        .chain(stores)
+        .filter(|(local, live)| local != live)
+        .flat_map(|(local, live)| vec![(local, live), (live, local)])
+        .collect()
+}
+
+fn coalesce_locals(mut func: Function) -> Function {
+    let interference = interference(&func);
+    let slots = (0..func.arguments).map(|argument| vec![argument]).collect();
+    let slots =
+        (func.arguments..func.locals.len()).fold(slots, |mut slots: Vec<Vec<usize>>, local| {
+            let slot = slots.iter().position(|members| {
+                func.types[members[0]] == func.types[local]
+                    && members
+                        .iter()
+                        .all(|&member| !interference.contains(&(member, local)))
+            });
+            match slot {
+                Some(slot) => slots[slot].push(local),
+                None => slots.push(vec![local]),
+            }
+            slots
+        });
+    let renumbered = slots
+        .iter()
+        .enumerate()
+        .flat_map(|(slot, members)| members.iter().map(move |&local| (local, slot)))
+        .collect::<HashMap<usize, usize>>();
+    for i in 0..func.instructions.len() {
+        if func.operand_kinds[i] == [OperandKind::Local] {
+            func.operands[i] = vec![renumbered[&func.operands[i][0]]];
+        }
+    }
+    func.locals = slots
+        .iter()
+        .map(|members| func.locals[members[0]].clone())
+        .collect();
+    func.types = slots.iter().map(|members| func.types[members[0]]).collect();
+    func.name_to_local = func
+        .name_to_local
+        .into_iter()
+        .map(|(name, local)| (name, renumbered[&local]))
+        .collect();
+    func
+}
+
+fn eliminate_dead_writes(func: Function) -> Function {
+    let labels = labels(&func);
+    let successors = (0..func.instructions.len())
+        .map(|i| successors(&func, &labels, i))
+        .collect::<Vec<Vec<usize>>>();
+    let live_in = liveness(&func, &successors);
+    let dead = (0..func.instructions.len())
+        .filter(|&i| {
+            matches!(
+                func.instructions[i],
+                Instruction::SetLocal | Instruction::TeeLocal
+            ) && successors[i]
+                .iter()
+                .all(|&successor| !live_in[successor].contains(&func.operands[i][0]))
+        })
+        .collect::<HashSet<usize>>();
+    let tees = dead
+        .iter()
+        .copied()
+        .filter(|&i| func.instructions[i] == Instruction::TeeLocal)
+        .collect::<HashSet<usize>>();
+    let mut func = func;
+    for &i in dead.difference(&tees) {
+        func.instructions[i] = Instruction::Drop;
+        func.operand_kinds[i] = vec![];
+        func.operands[i] = vec![];
+    }
+    keep(func, |i| !tees.contains(&i))
+}
+
+fn optimize_function(func: Function) -> Function {
+    let instructions = func.instructions.clone();
+    let operands = func.operands.clone();
+    let func = simplify(func);
+    let func = eliminate_dead_code(func);
+    let func = eliminate_dead_stores(func);
+    let func = propagate_constants(func);
+    match func.instructions == instructions && func.operands == operands {
+        true => {
+            let func = peephole(coalesce_locals(remove_unused_locals(func)));
+            let func = peephole(remove_unused_locals(eliminate_dead_writes(func)));
+            match func.instructions == instructions && func.operands == operands {
+                true => func,
+                false => optimize_function(func),
+            }
+        }
+        false => optimize_function(func),
+    }
+}
+
+fn callees(func: &Function) -> impl Iterator<Item = &String> {
+    func.instructions
+        .iter()
+        .zip(&func.operands)
+        .filter(|(&instruction, _)| instruction == Instruction::Call)
+        .map(move |(_, operands)| &func.symbols[operands[0]])
+}
+
+fn reaches(wasm: &Wasm, function: usize, target: usize, visited: &mut HashSet<usize>) -> bool {
+    callees(&wasm.functions[function]).any(|callee| {
+        let callee = wasm.name_to_function[callee];
+        callee == target || (visited.insert(callee) && reaches(wasm, callee, target, visited))
+    })
+}
+
+fn inlinable(wasm: &Wasm, function: usize) -> bool {
+    let func = &wasm.functions[function];
+    let small = match func.inline {
+        Some(inline) => inline,
+        None => func.instructions.len() <= INLINE_SIZE,
+    };
+    small
+        && !func.instructions.contains(&Instruction::Return)
+        && !reaches(wasm, function, function, &mut HashSet::new())
+}
+
+fn inline_operand(
+    (caller, base_local, base_label): (Function, usize, usize),
+    callee: &Function,
+    kind: OperandKind,
+    operand: usize,
+) -> (Function, usize) {
+    match kind {
+        OperandKind::Local => (caller, base_local + operand),
+        OperandKind::Label => (caller, base_label + operand),
+        OperandKind::IntLiteral => {
+            let mut caller = caller;
+            caller.ints.push(callee.ints[operand].clone());
+            let int = caller.ints.len() - 1;
+            (caller, int)
+        }
+        OperandKind::FloatLiteral => {
+            let mut caller = caller;
+            caller.floats.push(callee.floats[operand].clone());
+            let float = caller.floats.len() - 1;
+            (caller, float)
+        }
+        OperandKind::Symbol => symbol(caller, &callee.symbols[operand]),
+        OperandKind::Result | OperandKind::Offset | OperandKind::Function | OperandKind::Type => {
+            (caller, operand)
+        }
+    }
+}
+
+fn assigned_before_read(func: &Function, local: usize) -> bool {
+    let accesses = |instruction| {
+        (0..func.instructions.len())
+            .filter(move |&i| func.instructions[i] == instruction && func.operands[i] == [local])
+    };
+    accesses(Instruction::GetLocal).all(|get| {
+        accesses(Instruction::SetLocal)
+            .chain(accesses(Instruction::TeeLocal))
+            .any(|set| dominates(func, set, get))
+    })
+}
+
+type Code = (Instruction, Vec<OperandKind>, Vec<usize>);
+
+fn reset_local(mut caller: Function, local: usize, value_type: Type) -> (Function, [Code; 2]) {
+    let constant = match value_type {
+        Type::F64 => {
+            caller.floats.push(String::from("0"));
+            (
+                Instruction::F64Const,
+                vec![OperandKind::FloatLiteral],
+                vec![caller.floats.len() - 1],
+            )
+        }
+        Type::I64 | Type::I32 => {
+            caller.ints.push(String::from("0"));
+            (
+                Instruction::I64Const,
+                vec![OperandKind::IntLiteral],
+                vec![caller.ints.len() - 1],
+            )
+        }
+    };
+    let set = (Instruction::SetLocal, vec![OperandKind::Local], vec![local]);
+    (caller, [constant, set])
+}
+
+fn inline_call(caller: Function, i: usize, callee: &Function) -> Function {
+    let base_local = caller.locals.len();
+    let base_label = caller.next_label;
+    let mut caller = (0..callee.locals.len()).fold(caller, |mut caller, local| {
+        let name = &callee.locals[local][1..];
+        caller
+            .locals
+            .push(format!("$.inline.{}.{}", base_local, name));
+        caller.types.push(callee.types[local]);
+        caller
+    });
+    caller.next_label += callee.next_label;
+    let body = (0..callee.arguments)
+        .rev()
+        .map(|argument| {
+            (
+                Instruction::SetLocal,
+                vec![OperandKind::Local],
+                vec![base_local + argument],
+            )
+        })
+        .collect::<Vec<_>>();
+    let (caller, body) = (callee.arguments..callee.locals.len())
+        .filter(|&local| !assigned_before_read(callee, local))
+        .fold((caller, body), |(caller, mut body), local| {
+            let (caller, reset) = reset_local(caller, base_local + local, callee.types[local]);
+            body.extend(reset);
+            (caller, body)
+        });
+    let (caller, body) =
+        (0..callee.instructions.len()).fold((caller, body), |(caller, mut body), j| {
+            let kinds = &callee.operand_kinds[j];
+            let (caller, operands) = kinds.iter().zip(&callee.operands[j]).fold(
+                (caller, vec![]),
+                |(caller, mut operands), (&kind, &operand)| {
+                    let (caller, operand) =
+                        inline_operand((caller, base_local, base_label), callee, kind, operand);
+                    operands.push(operand);
+                    (caller, operands)
+                },
+            );
+            body.push((callee.instructions[j], kinds.clone(), operands));
+            (caller, body)
+        });
+    let mut caller = caller;
+    let (instructions, rest): (Vec<_>, Vec<_>) = body
+        .into_iter()
+        .map(|(instruction, kinds, operands)| (instruction, (kinds, operands)))
+        .unzip();
+    let (operand_kinds, operands): (Vec<_>, Vec<_>) = rest.into_iter().unzip();
+    caller.instructions.splice(i..i + 1, instructions);
+    caller.operand_kinds.splice(i..i + 1, operand_kinds);
+    caller.operands.splice(i..i + 1, operands);
+    caller
+}
+
+fn call_site(wasm: &Wasm, inlinable: &HashSet<usize>) -> Option<(usize, usize, usize)> {
+    wasm.functions
+        .iter()
+        .enumerate()
+        .find_map(|(caller, func)| {
+            (0..func.instructions.len()).find_map(|i| {
+                let callee = match func.instructions[i] {
+                    Instruction::Call => wasm.name_to_function[&func.symbols[func.operands[i][0]]],
+                    _ => return None,
+                };
+                match inlinable.contains(&callee) {
+                    true => Some((caller, i, callee)),
+                    false => None,
+                }
+            })
+        })
+}
+
+fn inline(mut wasm: Wasm) -> Wasm {
+    let inlinable = (0..wasm.functions.len())
+        .filter(|&function| inlinable(&wasm, function))
+        .collect::<HashSet<usize>>();
+    match call_site(&wasm, &inlinable) {
+        Some((caller, i, callee)) => {
+            let func = wasm.functions.remove(caller);
+            let callee = match callee > caller {
+                true => callee - 1,
+                false => callee,
+            };
+            let func = inline_call(func, i, &wasm.functions[callee]);
+            wasm.functions.insert(caller, func);
+            inline(wasm)
+        }
+        None => wasm,
+    }
+}
+
+fn remove_uncalled(wasm: Wasm) -> Wasm {
+    let called = wasm
+        .functions
+        .iter()
+        .flat_map(callees)
+        .chain(&wasm.exports.functions)
+        .chain(&wasm.table)
+        .cloned()
+        .collect::<HashSet<String>>();
+    let length = wasm.functions.len();
+    let functions = wasm
+        .functions
+        .into_iter()
+        .filter(|func| called.contains(&func.symbols[func.name]))
+        .collect::<Vec<Function>>();
+    let name_to_function = functions
+        .iter()
+        .enumerate()
+        .map(|(i, func)| (func.symbols[func.name].clone(), i))
+        .collect();
+    let wasm = Wasm {
+        functions,
+        name_to_function,
+        ..wasm
+    };
+    match wasm.functions.len() < length {
+        true => remove_uncalled(wasm),
+        false => wasm,
+    }
+}
+
+pub fn optimize(wasm: Wasm) -> Wasm {
+    let wasm = remove_uncalled(inline(lower_functions(wasm)));
+    let functions = wasm.functions.into_iter().map(optimize_function).collect();
+    Wasm { functions, ..wasm }
+}
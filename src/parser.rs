@@ -1,21 +1,29 @@
 use rayon::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::tokenizer::{self, Tokens};
 
 #[derive(Copy, Clone)]
 struct Token(usize);
 
-#[derive(Debug, PartialEq, Copy, Clone)]
+#[derive(Debug, PartialEq, Hash, Copy, Clone)]
 pub enum Kind {
     Symbol,
     Int,
+    Float,
     BinaryOp,
     Assign,
     FunctionCall,
     If,
     While,
+    For,
+    Array,
+    Match,
+    Lambda,
     Grouping,
+    Break,
+    Continue,
+    Return,
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -70,10 +78,47 @@ pub struct Whiles {
     pub bodies: Vec<Vec<usize>>,
 }
 
+#[derive(Debug, PartialEq)]
+pub struct Fors {
+    pub variables: Vec<usize>,
+    pub iterables: Vec<usize>,
+    pub bodies: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum Pattern {
+    Int(usize),
+    Range(usize, usize),
+    Wildcard,
+    Variant(usize),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Matches {
+    pub values: Vec<usize>,
+    pub patterns: Vec<Vec<Pattern>>,
+    pub guards: Vec<Vec<Option<usize>>>,
+    pub bindings: Vec<Vec<Vec<usize>>>,
+    pub bodies: Vec<Vec<Vec<usize>>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Lambdas {
+    pub parameters: Vec<Vec<usize>>,
+    pub bodies: Vec<Vec<usize>>,
+    pub starts: Vec<usize>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Function {
     pub name: usize,
+    pub hash: u64,
+    pub export: Option<usize>,
+    pub inline: Option<bool>,
+    pub type_parameters: Vec<usize>,
     pub arguments: Vec<usize>,
+    pub argument_types: Vec<Option<usize>>,
+    pub return_type: Option<usize>,
     pub kinds: Vec<Kind>,
     pub indices: Vec<usize>,
     pub binary_ops: BinaryOps,
@@ -82,15 +127,60 @@ pub struct Function {
     pub expressions: Vec<usize>,
     pub symbols: Vec<String>,
     pub ints: Vec<String>,
+    pub floats: Vec<String>,
     pub ifs: Ifs,
     pub whiles: Whiles,
+    pub fors: Fors,
+    pub matches: Matches,
+    pub arrays: Vec<Vec<usize>>,
+    pub lambdas: Lambdas,
     pub groupings: Vec<usize>,
+    pub returns: Vec<usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Imports {
+    pub modules: Vec<String>,
+    pub aliases: Vec<Option<String>>,
+    pub names: Vec<Vec<String>>,
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum GlobalKind {
+    Const,
+    Var,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Globals {
+    pub kinds: Vec<GlobalKind>,
+    pub values: Vec<Function>,
+    pub name_to_global: HashMap<String, usize>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Enums {
+    pub names: Vec<String>,
+    pub variants: Vec<Vec<String>>,
+    pub fields: Vec<Vec<Vec<String>>>,
+    pub name_to_enum: HashMap<String, usize>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct Ast {
     pub functions: Vec<Function>,
     pub top_level: HashMap<String, usize>,
+    pub imports: Imports,
+    pub globals: Globals,
+    pub enums: Enums,
+    pub libraries: HashSet<String>,
+}
+
+enum Item {
+    Function(Box<Function>),
+    Import(String, Option<String>, Vec<String>),
+    Global(GlobalKind, Box<Function>),
+    Enum(String, Vec<String>, Vec<Vec<String>>),
 }
 
 type Precedence = u8;
@@ -155,27 +245,7 @@ fn parse_primitive(
     ParseResult(func, token, entity)
 }
 
-fn parse_then_branch(
-    func: Function,
-    top_level: &tokenizer::TopLevel,
-    token: Token,
-    mut expressions: Vec<usize>,
-) -> (Function, Token, Vec<usize>) {
-    match top_level.kinds[token.0] {
-        tokenizer::Kind::Indent => {
-            parse_then_branch(func, top_level, inc_token(token), expressions)
-        }
-        tokenizer::Kind::Else => (func, token, expressions),
-        _ => {
-            let ParseResult(func, token, expression) =
-                parse_expression(func, top_level, token, LOWEST);
-            expressions.push(expression);
-            parse_then_branch(func, top_level, token, expressions)
-        }
-    }
-}
-
-fn parse_else_branch(
+fn parse_block(
     func: Function,
     top_level: &tokenizer::TopLevel,
     token: Token,
@@ -188,15 +258,16 @@ fn parse_else_branch(
             if indent != 0 && indent != next_indent {
                 (func, token, expressions)
             } else {
-                parse_else_branch(func, top_level, inc_token(token), expressions, next_indent)
+                parse_block(func, top_level, inc_token(token), expressions, next_indent)
             }
         }
+        Some(tokenizer::Kind::Else) | Some(tokenizer::Kind::Elif) => (func, token, expressions),
         Some(_) => {
             let ParseResult(func, token, expression) =
                 parse_expression(func, top_level, token, LOWEST);
             expressions.push(expression);
             if indent > 0 {
-                parse_else_branch(func, top_level, token, expressions, indent)
+                parse_block(func, top_level, token, expressions, indent)
             } else {
                 (func, token, expressions)
             }
@@ -205,66 +276,261 @@ fn parse_else_branch(
     }
 }
 
-fn parse_if(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
-    let token = consume(top_level, token, tokenizer::Kind::If);
-    let ParseResult(func, token, conditional) = parse_expression(func, top_level, token, LOWEST);
-    let token = consume(top_level, token, tokenizer::Kind::Colon);
-    let (func, token, then_branch) = parse_then_branch(func, top_level, token, vec![]);
-    let token = consume(top_level, token, tokenizer::Kind::Else);
-    let token = consume(top_level, token, tokenizer::Kind::Colon);
-    let (mut func, token, else_branch) = parse_else_branch(func, top_level, token, vec![], 0);
-    let entity = fresh_entity(&func);
-    func.kinds.push(Kind::If);
-    func.indices.push(func.ifs.conditionals.len());
-    func.ifs.conditionals.push(conditional);
-    func.ifs.then_branches.push(then_branch);
-    func.ifs.else_branches.push(else_branch);
-    ParseResult(func, token, entity)
+fn line_indent(top_level: &tokenizer::TopLevel, token: Token) -> usize {
+    top_level.kinds[..token.0]
+        .iter()
+        .rposition(|&kind| kind == tokenizer::Kind::Indent)
+        .map(|indent| top_level.indents[top_level.indices[indent]])
+        .unwrap_or(0)
 }
 
-fn parse_while_body(
+fn parse_else_branch(
     func: Function,
     top_level: &tokenizer::TopLevel,
     token: Token,
-    mut expressions: Vec<usize>,
     indent: usize,
 ) -> (Function, Token, Vec<usize>) {
     match top_level.kinds.get(token.0) {
-        Some(tokenizer::Kind::Indent) => {
-            let next_indent = top_level.indents[top_level.indices[token.0]];
-            if indent != 0 && indent != next_indent {
-                (func, token, expressions)
-            } else {
-                parse_while_body(func, top_level, inc_token(token), expressions, next_indent)
-            }
+        Some(tokenizer::Kind::Else) => {
+            let token = consume(top_level, token, tokenizer::Kind::Else);
+            let token = consume(top_level, token, tokenizer::Kind::Colon);
+            parse_block(func, top_level, token, vec![], 0)
         }
-        Some(_) => {
-            let ParseResult(func, token, expression) =
-                parse_expression(func, top_level, token, LOWEST);
-            expressions.push(expression);
-            if indent > 0 {
-                parse_while_body(func, top_level, token, expressions, indent)
-            } else {
-                (func, token, expressions)
-            }
+        Some(tokenizer::Kind::Elif) => {
+            let ParseResult(func, token, entity) = parse_if(func, top_level, token);
+            (func, inc_token(token), vec![entity])
         }
-        None => (func, token, expressions),
+        Some(tokenizer::Kind::Indent)
+            if top_level.indents[top_level.indices[token.0]] == indent
+                && matches!(
+                    top_level.kinds.get(token.0 + 1),
+                    Some(tokenizer::Kind::Else) | Some(tokenizer::Kind::Elif)
+                ) =>
+        {
+            parse_else_branch(func, top_level, inc_token(token), indent)
+        }
+        _ => (func, token, vec![]),
     }
 }
 
+fn parse_if(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
+    let indent = line_indent(top_level, token);
+    let token = match top_level.kinds[token.0] {
+        tokenizer::Kind::Elif => consume(top_level, token, tokenizer::Kind::Elif),
+        _ => consume(top_level, token, tokenizer::Kind::If),
+    };
+    let ParseResult(func, token, conditional) = parse_expression(func, top_level, token, LOWEST);
+    let token = consume(top_level, token, tokenizer::Kind::Colon);
+    let (func, token, then_branch) = parse_block(func, top_level, token, vec![], 0);
+    let (mut func, token, else_branch) = parse_else_branch(func, top_level, token, indent);
+    let entity = fresh_entity(&func);
+    func.kinds.push(Kind::If);
+    func.indices.push(func.ifs.conditionals.len());
+    func.ifs.conditionals.push(conditional);
+    func.ifs.then_branches.push(then_branch);
+    func.ifs.else_branches.push(else_branch);
+    ParseResult(func, Token(token.0 - 1), entity)
+}
+
 fn parse_while(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
     let token = consume(top_level, token, tokenizer::Kind::While);
     let ParseResult(func, token, conditional) = parse_expression(func, top_level, token, LOWEST);
     let token = consume(top_level, token, tokenizer::Kind::Colon);
-    let (mut func, token, body) = parse_while_body(func, top_level, token, vec![], 0);
+    let (mut func, token, body) = parse_block(func, top_level, token, vec![], 0);
     let entity = fresh_entity(&func);
     func.kinds.push(Kind::While);
     func.indices.push(func.whiles.conditionals.len());
     func.whiles.conditionals.push(conditional);
     func.whiles.bodies.push(body);
+    ParseResult(func, Token(token.0 - 1), entity)
+}
+
+fn parse_for(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
+    let token = consume(top_level, token, tokenizer::Kind::For);
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+    let ParseResult(func, token, variable) = parse_primitive(func, top_level, token, Kind::Symbol);
+    let token = consume(top_level, inc_token(token), tokenizer::Kind::In);
+    let ParseResult(func, token, iterable) = parse_expression(func, top_level, token, LOWEST);
+    let token = consume(top_level, token, tokenizer::Kind::Colon);
+    let (mut func, token, body) = parse_block(func, top_level, token, vec![], 0);
+    let entity = fresh_entity(&func);
+    func.kinds.push(Kind::For);
+    func.indices.push(func.fors.variables.len());
+    func.fors.variables.push(variable);
+    func.fors.iterables.push(iterable);
+    func.fors.bodies.push(body);
+    ParseResult(func, Token(token.0 - 1), entity)
+}
+
+fn parse_pattern(top_level: &tokenizer::TopLevel, token: Token) -> (Token, Pattern) {
+    let index = top_level.indices[token.0];
+    match (top_level.kinds[token.0], top_level.kinds.get(token.0 + 1)) {
+        (tokenizer::Kind::Int, Some(tokenizer::Kind::DotDot)) => {
+            let token = inc_token(inc_token(token));
+            assert_eq!(
+                top_level.kinds[token.0],
+                tokenizer::Kind::Int,
+                "Range patterns must end in an int literal"
+            );
+            let high = top_level.indices[token.0];
+            (inc_token(token), Pattern::Range(index, high))
+        }
+        (tokenizer::Kind::Int, _) => (inc_token(token), Pattern::Int(index)),
+        (tokenizer::Kind::Symbol, _) if top_level.symbols[index] == "_" => {
+            (inc_token(token), Pattern::Wildcard)
+        }
+        (tokenizer::Kind::Symbol, _) => (inc_token(token), Pattern::Variant(index)),
+        (kind, _) => panic!("Parsing match, expected a pattern, found {:?}", kind),
+    }
+}
+
+fn parse_bindings(
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    mut bindings: Vec<usize>,
+) -> (Token, Vec<usize>) {
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+    bindings.push(top_level.indices[token.0]);
+    let token = inc_token(token);
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Comma => parse_bindings(top_level, inc_token(token), bindings),
+        tokenizer::Kind::RightParen => (inc_token(token), bindings),
+        kind => panic!(
+            "Parsing variant pattern, expected comma or right paren, found {:?}",
+            kind
+        ),
+    }
+}
+
+fn parse_match_arms(
+    func: Function,
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    index: usize,
+    indent: usize,
+) -> (Function, Token) {
+    let is_arm = top_level.kinds.get(token.0) == Some(&tokenizer::Kind::Indent)
+        && top_level.indents[top_level.indices[token.0]] == indent;
+    if !is_arm {
+        return (func, token);
+    }
+    let (token, pattern) = parse_pattern(top_level, inc_token(token));
+    let (token, bindings) = match (pattern, top_level.kinds[token.0]) {
+        (Pattern::Variant(_), tokenizer::Kind::LeftParen) => {
+            parse_bindings(top_level, inc_token(token), vec![])
+        }
+        _ => (token, vec![]),
+    };
+    let (func, token, guard) = match top_level.kinds[token.0] {
+        tokenizer::Kind::If => {
+            let ParseResult(func, token, guard) =
+                parse_expression(func, top_level, inc_token(token), LOWEST);
+            (func, token, Some(guard))
+        }
+        _ => (func, token, None),
+    };
+    let token = consume(top_level, token, tokenizer::Kind::Colon);
+    let (mut func, token, body) = parse_block(func, top_level, token, vec![], 0);
+    func.matches.patterns[index].push(pattern);
+    func.matches.guards[index].push(guard);
+    func.matches.bindings[index].push(bindings);
+    func.matches.bodies[index].push(body);
+    parse_match_arms(func, top_level, token, index, indent)
+}
+
+fn parse_match(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
+    let token = consume(top_level, token, tokenizer::Kind::Match);
+    let ParseResult(mut func, token, value) = parse_expression(func, top_level, token, LOWEST);
+    let token = consume(top_level, token, tokenizer::Kind::Colon);
+    assert_eq!(
+        top_level.kinds.get(token.0),
+        Some(&tokenizer::Kind::Indent),
+        "Match arms must start on a new line"
+    );
+    let indent = top_level.indents[top_level.indices[token.0]];
+    let index = func.matches.values.len();
+    func.matches.values.push(value);
+    func.matches.patterns.push(vec![]);
+    func.matches.guards.push(vec![]);
+    func.matches.bindings.push(vec![]);
+    func.matches.bodies.push(vec![]);
+    let (mut func, token) = parse_match_arms(func, top_level, token, index, indent);
+    let entity = fresh_entity(&func);
+    func.kinds.push(Kind::Match);
+    func.indices.push(index);
+    ParseResult(func, Token(token.0 - 1), entity)
+}
+
+fn parse_array_elements(
+    func: Function,
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    mut elements: Vec<usize>,
+) -> (Function, Token, Vec<usize>) {
+    let ParseResult(func, token, element) = parse_expression(func, top_level, token, LOWEST);
+    elements.push(element);
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Comma => parse_array_elements(func, top_level, inc_token(token), elements),
+        tokenizer::Kind::RightBracket => (func, token, elements),
+        kind => panic!(
+            "Parsing array, expected comma or right bracket, found {:?}",
+            kind
+        ),
+    }
+}
+
+fn parse_array(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
+    let token = consume(top_level, token, tokenizer::Kind::LeftBracket);
+    let (mut func, token, elements) = match top_level.kinds[token.0] {
+        tokenizer::Kind::RightBracket => (func, token, vec![]),
+        _ => parse_array_elements(func, top_level, token, vec![]),
+    };
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::RightBracket);
+    let entity = fresh_entity(&func);
+    func.kinds.push(Kind::Array);
+    func.indices.push(func.arrays.len());
+    func.arrays.push(elements);
     ParseResult(func, token, entity)
 }
 
+fn parse_lambda_parameters(
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    mut parameters: Vec<usize>,
+) -> (Token, Vec<usize>) {
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+    parameters.push(top_level.indices[token.0]);
+    let token = inc_token(token);
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Comma => parse_lambda_parameters(top_level, inc_token(token), parameters),
+        tokenizer::Kind::RightParen => (token, parameters),
+        kind => panic!(
+            "Parsing lambda parameters, expected comma or right paren, found {:?}",
+            kind
+        ),
+    }
+}
+
+fn parse_lambda(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
+    let token = consume(top_level, token, tokenizer::Kind::Fn);
+    let token = consume(top_level, token, tokenizer::Kind::LeftParen);
+    let (token, parameters) = match top_level.kinds[token.0] {
+        tokenizer::Kind::RightParen => (token, vec![]),
+        _ => parse_lambda_parameters(top_level, token, vec![]),
+    };
+    let token = consume(top_level, token, tokenizer::Kind::RightParen);
+    let token = consume(top_level, token, tokenizer::Kind::Colon);
+    let start = fresh_entity(&func);
+    let (mut func, token, body) = parse_block(func, top_level, token, vec![], 0);
+    let entity = fresh_entity(&func);
+    func.kinds.push(Kind::Lambda);
+    func.indices.push(func.lambdas.bodies.len());
+    func.lambdas.parameters.push(parameters);
+    func.lambdas.bodies.push(body);
+    func.lambdas.starts.push(start);
+    ParseResult(func, Token(token.0 - 1), entity)
+}
+
 fn parse_grouping(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
     let token = consume(top_level, token, tokenizer::Kind::LeftParen);
     let ParseResult(mut func, token, expression) = parse_expression(func, top_level, token, LOWEST);
@@ -276,6 +542,16 @@ fn parse_grouping(func: Function, top_level: &tokenizer::TopLevel, token: Token)
     ParseResult(func, token, entity)
 }
 
+fn parse_return(func: Function, top_level: &tokenizer::TopLevel, token: Token) -> ParseResult {
+    let token = consume(top_level, token, tokenizer::Kind::Return);
+    let ParseResult(mut func, token, value) = parse_expression(func, top_level, token, LOWEST);
+    let entity = fresh_entity(&func);
+    func.kinds.push(Kind::Return);
+    func.indices.push(func.returns.len());
+    func.returns.push(value);
+    ParseResult(func, Token(token.0 - 1), entity)
+}
+
 fn prefix_parser(
     func: Function,
     top_level: &tokenizer::TopLevel,
@@ -285,9 +561,17 @@ fn prefix_parser(
     match kind {
         tokenizer::Kind::Symbol => parse_primitive(func, top_level, token, Kind::Symbol),
         tokenizer::Kind::Int => parse_primitive(func, top_level, token, Kind::Int),
+        tokenizer::Kind::Float => parse_primitive(func, top_level, token, Kind::Float),
         tokenizer::Kind::If => parse_if(func, top_level, token),
         tokenizer::Kind::While => parse_while(func, top_level, token),
+        tokenizer::Kind::For => parse_for(func, top_level, token),
+        tokenizer::Kind::LeftBracket => parse_array(func, top_level, token),
+        tokenizer::Kind::Match => parse_match(func, top_level, token),
+        tokenizer::Kind::Fn => parse_lambda(func, top_level, token),
         tokenizer::Kind::LeftParen => parse_grouping(func, top_level, token),
+        tokenizer::Kind::Break => parse_primitive(func, top_level, token, Kind::Break),
+        tokenizer::Kind::Continue => parse_primitive(func, top_level, token, Kind::Continue),
+        tokenizer::Kind::Return => parse_return(func, top_level, token),
         token => panic!("no prefix parser for {:?}", token),
     }
 }
@@ -412,7 +696,10 @@ fn parse_pipeline(
     token: Token,
     first_parameter: usize,
 ) -> ParseResult {
-    let ParseResult(func, token, name) = parse_primitive(func, top_level, token, Kind::Symbol);
+    let ParseResult(func, token, name) = match top_level.kinds[token.0] {
+        tokenizer::Kind::Fn => parse_lambda(func, top_level, token),
+        _ => parse_primitive(func, top_level, token, Kind::Symbol),
+    };
     let token = inc_token(token);
     let parameters = vec![first_parameter];
     let (mut func, token, parameters) = match top_level.kinds.get(token.0) {
@@ -575,6 +862,18 @@ fn parse_function_arguments(
     assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
     func.arguments.push(top_level.indices[token.0]);
     let token = inc_token(token);
+    let token = match top_level.kinds[token.0] {
+        tokenizer::Kind::Colon => {
+            let token = inc_token(token);
+            assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+            func.argument_types.push(Some(top_level.indices[token.0]));
+            inc_token(token)
+        }
+        _ => {
+            func.argument_types.push(None);
+            token
+        }
+    };
     match top_level.kinds[token.0] {
         tokenizer::Kind::Comma => parse_function_arguments(func, top_level, inc_token(token)),
         tokenizer::Kind::RightParen => (func, token),
@@ -585,12 +884,116 @@ fn parse_function_arguments(
     }
 }
 
+fn parse_type_parameters(
+    mut func: Function,
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+) -> (Function, Token) {
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+    func.type_parameters.push(top_level.indices[token.0]);
+    let token = inc_token(token);
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Comma => parse_type_parameters(func, top_level, inc_token(token)),
+        tokenizer::Kind::RightBracket => (func, inc_token(token)),
+        kind => panic!(
+            "Parsing type parameters, expected comma or right bracket, found {:?}",
+            kind
+        ),
+    }
+}
+
+fn parse_return_type(
+    mut func: Function,
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+) -> (Function, Token) {
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Arrow => {
+            let token = inc_token(token);
+            assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+            func.return_type = Some(top_level.indices[token.0]);
+            (func, inc_token(token))
+        }
+        _ => (func, token),
+    }
+}
+
+fn parse_export_alias(
+    mut func: Function,
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+) -> (Function, Token) {
+    match (func.export, top_level.kinds[token.0]) {
+        (Some(_), tokenizer::Kind::As) => {
+            let token = inc_token(token);
+            assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+            func.export = Some(top_level.indices[token.0]);
+            (func, inc_token(token))
+        }
+        (None, tokenizer::Kind::As) => panic!("Only exported functions can be given an alias"),
+        _ => (func, token),
+    }
+}
+
+fn parse_attributes(top_level: &tokenizer::TopLevel, token: Token) -> (Option<bool>, Token) {
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Attribute => {
+            let inline = match top_level.symbols[top_level.indices[token.0]].as_str() {
+                "inline" => true,
+                "noinline" => false,
+                attribute => panic!("Unknown attribute @{}", attribute),
+            };
+            let (other, token) = parse_attributes(top_level, inc_token(token));
+            assert!(
+                other.is_none() || other == Some(inline),
+                "@inline and @noinline cannot be combined"
+            );
+            (Some(inline), token)
+        }
+        _ => (None, token),
+    }
+}
+
 fn parse_function(top_level: &tokenizer::TopLevel, token: Token) -> Function {
+    let (inline, token) = parse_attributes(top_level, token);
+    let (exported, token) = match top_level.kinds[token.0] {
+        tokenizer::Kind::Export => (true, inc_token(token)),
+        _ => (false, token),
+    };
     let token = consume(top_level, token, tokenizer::Kind::Def);
     assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
-    let func = Function {
-        name: top_level.indices[token.0],
+    let name = top_level.indices[token.0];
+    let mut func = empty_function(name);
+    func.export = if exported { Some(name) } else { None };
+    func.inline = inline;
+    let token = inc_token(token);
+    let (func, token) = match top_level.kinds[token.0] {
+        tokenizer::Kind::LeftBracket => parse_type_parameters(func, top_level, inc_token(token)),
+        _ => (func, token),
+    };
+    let token = consume(top_level, token, tokenizer::Kind::LeftParen);
+    let (func, token) = if top_level.kinds[token.0] != tokenizer::Kind::RightParen {
+        parse_function_arguments(func, top_level, token)
+    } else {
+        (func, token)
+    };
+    let token = consume(top_level, token, tokenizer::Kind::RightParen);
+    let (func, token) = parse_return_type(func, top_level, token);
+    let (func, token) = parse_export_alias(func, top_level, token);
+    let token = consume(top_level, token, tokenizer::Kind::Colon);
+    parse_function_body(func, top_level, token)
+}
+
+fn empty_function(name: usize) -> Function {
+    Function {
+        name,
+        hash: 0,
+        export: None,
+        inline: None,
+        type_parameters: vec![],
         arguments: vec![],
+        argument_types: vec![],
+        return_type: None,
         kinds: vec![],
         indices: vec![],
         binary_ops: BinaryOps {
@@ -609,6 +1012,7 @@ fn parse_function(top_level: &tokenizer::TopLevel, token: Token) -> Function {
         expressions: vec![],
         symbols: vec![],
         ints: vec![],
+        floats: vec![],
         ifs: Ifs {
             conditionals: vec![],
             then_branches: vec![],
@@ -618,42 +1022,231 @@ fn parse_function(top_level: &tokenizer::TopLevel, token: Token) -> Function {
             conditionals: vec![],
             bodies: vec![],
         },
+        fors: Fors {
+            variables: vec![],
+            iterables: vec![],
+            bodies: vec![],
+        },
+        matches: Matches {
+            values: vec![],
+            patterns: vec![],
+            guards: vec![],
+            bindings: vec![],
+            bodies: vec![],
+        },
+        arrays: vec![],
+        lambdas: Lambdas {
+            parameters: vec![],
+            bodies: vec![],
+            starts: vec![],
+        },
         groupings: vec![],
-    };
-    let token = consume(top_level, inc_token(token), tokenizer::Kind::LeftParen);
-    let (func, token) = if top_level.kinds[token.0] != tokenizer::Kind::RightParen {
-        parse_function_arguments(func, top_level, token)
-    } else {
-        (func, token)
-    };
-    let token = consume(top_level, token, tokenizer::Kind::RightParen);
+        returns: vec![],
+    }
+}
+
+fn parse_global(top_level: tokenizer::TopLevel, kind: GlobalKind) -> Item {
+    let token = Token(1);
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+    let func = empty_function(top_level.indices[token.0]);
+    let token = consume(&top_level, inc_token(token), tokenizer::Kind::Equal);
+    let ParseResult(mut func, _, value) = parse_expression(func, &top_level, token, LOWEST);
+    func.expressions.push(value);
+    func.symbols = top_level.symbols;
+    func.ints = top_level.ints;
+    func.floats = top_level.floats;
+    Item::Global(kind, Box::new(func))
+}
+
+fn parse_symbol(top_level: &tokenizer::TopLevel, token: Token) -> (String, Token) {
+    assert_eq!(top_level.kinds[token.0], tokenizer::Kind::Symbol);
+    let symbol = top_level.symbols[top_level.indices[token.0]].clone();
+    (symbol, inc_token(token))
+}
+
+fn parse_import_names(
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    mut names: Vec<String>,
+) -> Vec<String> {
+    let (name, token) = parse_symbol(top_level, token);
+    names.push(name);
+    match top_level.kinds.get(token.0) {
+        Some(tokenizer::Kind::Comma) => parse_import_names(top_level, inc_token(token), names),
+        None => names,
+        Some(kind) => panic!("Parsing import names, expected comma, found {:?}", kind),
+    }
+}
+
+fn parse_import(top_level: &tokenizer::TopLevel, token: Token) -> Item {
+    let token = consume(top_level, token, tokenizer::Kind::Import);
+    let (module, token) = parse_symbol(top_level, token);
+    match top_level.kinds.get(token.0) {
+        Some(tokenizer::Kind::As) => {
+            let (alias, _) = parse_symbol(top_level, inc_token(token));
+            Item::Import(module, Some(alias), vec![])
+        }
+        None => Item::Import(module.clone(), Some(module), vec![]),
+        Some(kind) => panic!("Parsing import, expected as, found {:?}", kind),
+    }
+}
+
+fn parse_from_import(top_level: &tokenizer::TopLevel, token: Token) -> Item {
+    let token = consume(top_level, token, tokenizer::Kind::From);
+    let (module, token) = parse_symbol(top_level, token);
+    let token = consume(top_level, token, tokenizer::Kind::Import);
+    let names = parse_import_names(top_level, token, vec![]);
+    Item::Import(module, None, names)
+}
+
+fn parse_fields(
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    mut fields: Vec<String>,
+) -> (Vec<String>, Token) {
+    let (field, token) = parse_symbol(top_level, token);
+    fields.push(field);
+    match top_level.kinds[token.0] {
+        tokenizer::Kind::Comma => parse_fields(top_level, inc_token(token), fields),
+        tokenizer::Kind::RightParen => (fields, inc_token(token)),
+        kind => panic!(
+            "Parsing variant fields, expected comma or right paren, found {:?}",
+            kind
+        ),
+    }
+}
+
+fn parse_variants(
+    top_level: &tokenizer::TopLevel,
+    token: Token,
+    mut variants: Vec<String>,
+    mut fields: Vec<Vec<String>>,
+) -> (Vec<String>, Vec<Vec<String>>) {
+    match top_level.kinds.get(token.0) {
+        None => (variants, fields),
+        Some(tokenizer::Kind::Comma) | Some(tokenizer::Kind::Indent) => {
+            parse_variants(top_level, inc_token(token), variants, fields)
+        }
+        Some(_) => {
+            let (variant, token) = parse_symbol(top_level, token);
+            let (variant_fields, token) = match top_level.kinds.get(token.0) {
+                Some(tokenizer::Kind::LeftParen) => {
+                    parse_fields(top_level, inc_token(token), vec![])
+                }
+                _ => (vec![], token),
+            };
+            variants.push(variant);
+            fields.push(variant_fields);
+            parse_variants(top_level, token, variants, fields)
+        }
+    }
+}
+
+fn parse_enum(top_level: &tokenizer::TopLevel, token: Token) -> Item {
+    let token = consume(top_level, token, tokenizer::Kind::Enum);
+    let (name, token) = parse_symbol(top_level, token);
     let token = consume(top_level, token, tokenizer::Kind::Colon);
-    parse_function_body(func, top_level, token)
+    let (variants, fields) = parse_variants(top_level, token, vec![], vec![]);
+    assert!(!variants.is_empty(), "enum {} must have a variant", name);
+    Item::Enum(name, variants, fields)
 }
 
-pub fn parse(tokens: Tokens) -> Ast {
-    let functions: Vec<Function> = tokens
-        .top_level
-        .into_par_iter()
-        .map(|top_level| {
+fn parse_item(top_level: tokenizer::TopLevel) -> Item {
+    match top_level.kinds[0] {
+        tokenizer::Kind::Import => parse_import(&top_level, Token(0)),
+        tokenizer::Kind::From => parse_from_import(&top_level, Token(0)),
+        tokenizer::Kind::Const => parse_global(top_level, GlobalKind::Const),
+        tokenizer::Kind::Var => parse_global(top_level, GlobalKind::Var),
+        tokenizer::Kind::Enum => parse_enum(&top_level, Token(0)),
+        _ => {
             let mut func = parse_function(&top_level, Token(0));
+            func.hash = tokenizer::hash(&top_level);
             func.symbols = top_level.symbols;
             func.ints = top_level.ints;
-            func
-        })
-        .collect();
-    let top_level =
-        functions
-            .iter()
-            .enumerate()
-            .fold(HashMap::new(), |mut top_level, (i, func)| {
-                top_level
-                    .try_insert(func.symbols[func.name].clone(), i)
-                    .unwrap();
-                top_level
-            });
-    Ast {
-        functions,
-        top_level,
+            func.floats = top_level.floats;
+            Item::Function(Box::new(func))
+        }
     }
 }
+
+pub fn parse(tokens: Tokens) -> Ast {
+    let items: Vec<Item> = tokens.top_level.into_par_iter().map(parse_item).collect();
+    let ast = Ast {
+        functions: vec![],
+        top_level: HashMap::new(),
+        imports: Imports {
+            modules: vec![],
+            aliases: vec![],
+            names: vec![],
+        },
+        globals: Globals {
+            kinds: vec![],
+            values: vec![],
+            name_to_global: HashMap::new(),
+        },
+        enums: Enums {
+            names: vec![],
+            variants: vec![],
+            fields: vec![],
+            name_to_enum: HashMap::new(),
+        },
+        libraries: HashSet::new(),
+    };
+    items.into_iter().fold(ast, |mut ast, item| {
+        match item {
+            Item::Function(func) => {
+                assert!(
+                    !ast.globals
+                        .name_to_global
+                        .contains_key(&func.symbols[func.name]),
+                    "{} is defined as both a function and a global",
+                    func.symbols[func.name]
+                );
+                ast.top_level
+                    .try_insert(func.symbols[func.name].clone(), ast.functions.len())
+                    .unwrap();
+                ast.functions.push(*func);
+            }
+            Item::Import(module, alias, names) => {
+                ast.imports.modules.push(module);
+                ast.imports.aliases.push(alias);
+                ast.imports.names.push(names);
+            }
+            Item::Global(kind, value) => {
+                let name = value.symbols[value.name].clone();
+                assert!(
+                    !ast.top_level.contains_key(&name),
+                    "{} is defined as both a function and a global",
+                    name
+                );
+                ast.globals
+                    .name_to_global
+                    .try_insert(name, ast.globals.values.len())
+                    .unwrap();
+                ast.globals.kinds.push(kind);
+                ast.globals.values.push(*value);
+            }
+            Item::Enum(name, variants, fields) => {
+                for variant in &variants {
+                    assert!(
+                        !ast.enums
+                            .variants
+                            .iter()
+                            .flatten()
+                            .any(|other| other == variant),
+                        "Variant {} is defined in more than one enum",
+                        variant
+                    );
+                }
+                ast.enums
+                    .name_to_enum
+                    .try_insert(name.clone(), ast.enums.names.len())
+                    .unwrap();
+                ast.enums.names.push(name);
+                ast.enums.variants.push(variants);
+                ast.enums.fields.push(fields);
+            }
+        }
+        ast
+    })
+}
@@ -0,0 +1,234 @@
+use crate::codegen::{Function, Instruction, OperandKind};
+
+type Replacement = Vec<(Instruction, Vec<OperandKind>, Vec<usize>)>;
+
+type Rewrite = fn(&Function, usize) -> Option<Replacement>;
+
+const PEEPHOLES: &[(&[Instruction], Rewrite)] = &[
+    (&[Instruction::I64Eq, Instruction::I32Eqz], invert),
+    (&[Instruction::I64Neq, Instruction::I32Eqz], invert),
+    (&[Instruction::I64LtS, Instruction::I32Eqz], invert),
+    (&[Instruction::I64LeS, Instruction::I32Eqz], invert),
+    (&[Instruction::I64GtS, Instruction::I32Eqz], invert),
+    (&[Instruction::I64GeS, Instruction::I32Eqz], invert),
+    (
+        &[
+            Instruction::I64Eqz,
+            Instruction::I32Eqz,
+            Instruction::I32Eqz,
+        ],
+        double_negation,
+    ),
+    (
+        &[
+            Instruction::I32Eqz,
+            Instruction::I32Eqz,
+            Instruction::I32Eqz,
+        ],
+        double_negation,
+    ),
+    (&[Instruction::I64Const, Instruction::I64Eq], equals_zero),
+    (&[Instruction::SetLocal, Instruction::GetLocal], tee),
+    (
+        &[Instruction::GetLocal, Instruction::SetLocal],
+        self_assignment,
+    ),
+    (&[Instruction::GetLocal, Instruction::TeeLocal], self_tee),
+    (&[Instruction::TeeLocal, Instruction::SetLocal], overwrite),
+    (&[Instruction::TeeLocal, Instruction::TeeLocal], overwrite),
+    (&[Instruction::TeeLocal, Instruction::Drop], store),
+    (&[Instruction::I64Const, Instruction::Drop], nothing),
+    (&[Instruction::F64Const, Instruction::Drop], nothing),
+    (&[Instruction::GetLocal, Instruction::Drop], nothing),
+    (&[Instruction::GlobalGet, Instruction::Drop], nothing),
+    (&[Instruction::Br, Instruction::End], fall_through),
+    (&[Instruction::Block, Instruction::End], empty),
+    (&[Instruction::Loop, Instruction::End], empty),
+    (
+        &[Instruction::Loop, Instruction::Br, Instruction::End],
+        single_branch,
+    ),
+    (&[Instruction::Return, Instruction::Return], first),
+];
+
+fn inverse(instruction: Instruction) -> Instruction {
+    match instruction {
+        Instruction::I64Eq => Instruction::I64Neq,
+        Instruction::I64Neq => Instruction::I64Eq,
+        Instruction::I64LtS => Instruction::I64GeS,
+        Instruction::I64LeS => Instruction::I64GtS,
+        Instruction::I64GtS => Instruction::I64LeS,
+        Instruction::I64GeS => Instruction::I64LtS,
+        instruction => panic!("{:?} has no inverse", instruction),
+    }
+}
+
+fn invert(func: &Function, i: usize) -> Option<Replacement> {
+    Some(vec![(inverse(func.instructions[i]), vec![], vec![])])
+}
+
+fn double_negation(func: &Function, i: usize) -> Option<Replacement> {
+    Some(vec![(func.instructions[i], vec![], vec![])])
+}
+
+fn equals_zero(func: &Function, i: usize) -> Option<Replacement> {
+    match (
+        &func.operand_kinds[i][..],
+        func.ints[func.operands[i][0]].as_str(),
+    ) {
+        ([OperandKind::IntLiteral], "0") => Some(vec![(Instruction::I64Eqz, vec![], vec![])]),
+        _ => None,
+    }
+}
+
+fn same_local(func: &Function, i: usize) -> bool {
+    func.operands[i] == func.operands[i + 1]
+}
+
+fn tee(func: &Function, i: usize) -> Option<Replacement> {
+    match same_local(func, i) {
+        true => Some(vec![(
+            Instruction::TeeLocal,
+            vec![OperandKind::Local],
+            func.operands[i].clone(),
+        )]),
+        false => None,
+    }
+}
+
+fn self_assignment(func: &Function, i: usize) -> Option<Replacement> {
+    match same_local(func, i) {
+        true => Some(vec![]),
+        false => None,
+    }
+}
+
+fn self_tee(func: &Function, i: usize) -> Option<Replacement> {
+    match same_local(func, i) {
+        true => first(func, i),
+        false => None,
+    }
+}
+
+fn overwrite(func: &Function, i: usize) -> Option<Replacement> {
+    match same_local(func, i) {
+        true => first(func, i + 1),
+        false => None,
+    }
+}
+
+fn store(func: &Function, i: usize) -> Option<Replacement> {
+    Some(vec![(
+        Instruction::SetLocal,
+        vec![OperandKind::Local],
+        func.operands[i].clone(),
+    )])
+}
+
+fn nothing(_: &Function, _: usize) -> Option<Replacement> {
+    Some(vec![])
+}
+
+fn first(func: &Function, i: usize) -> Option<Replacement> {
+    Some(vec![(
+        func.instructions[i],
+        func.operand_kinds[i].clone(),
+        func.operands[i].clone(),
+    )])
+}
+
+fn opening(func: &Function, label: usize) -> Option<usize> {
+    (0..func.instructions.len()).rev().find(|&i| {
+        matches!(func.instructions[i], Instruction::Block | Instruction::Loop)
+            && func.operand_kinds[i] == [OperandKind::Label]
+            && func.operands[i] == [label]
+    })
+}
+
+fn label(func: &Function, i: usize) -> Option<usize> {
+    match func.operand_kinds[i][..] {
+        [OperandKind::Label] => Some(func.operands[i][0]),
+        _ => None,
+    }
+}
+
+fn fall_through(func: &Function, i: usize) -> Option<Replacement> {
+    let target = label(func, i)?;
+    match label(func, i + 1) == Some(target)
+        && func.instructions[opening(func, target)?] == Instruction::Block
+    {
+        true => first(func, i + 1),
+        false => None,
+    }
+}
+
+fn empty(func: &Function, i: usize) -> Option<Replacement> {
+    match label(func, i).is_some() && label(func, i) == label(func, i + 1) {
+        true => Some(vec![]),
+        false => None,
+    }
+}
+
+fn single_branch(func: &Function, i: usize) -> Option<Replacement> {
+    match label(func, i).is_some()
+        && label(func, i) == label(func, i + 2)
+        && label(func, i) != label(func, i + 1)
+    {
+        true => first(func, i + 1),
+        false => None,
+    }
+}
+
+fn rewrite(mut func: Function) -> Function {
+    let length = func.instructions.len();
+    let rewritten = PEEPHOLES.iter().find_map(|(pattern, rewrite)| {
+        let start = length.checked_sub(pattern.len())?;
+        match func.instructions[start..] == pattern[..] {
+            true => rewrite(&func, start).map(|replacement| (start, replacement)),
+            false => None,
+        }
+    });
+    match rewritten {
+        Some((start, replacement)) => {
+            func.instructions.truncate(start);
+            func.operand_kinds.truncate(start);
+            func.operands.truncate(start);
+            let func = replacement.into_iter().fold(
+                func,
+                |mut func, (instruction, operand_kinds, operands)| {
+                    func.instructions.push(instruction);
+                    func.operand_kinds.push(operand_kinds);
+                    func.operands.push(operands);
+                    func
+                },
+            );
+            rewrite(func)
+        }
+        None => func,
+    }
+}
+
+pub fn peephole(func: Function) -> Function {
+    let instructions = func.instructions;
+    let operand_kinds = func.operand_kinds;
+    let operands = func.operands;
+    let func = Function {
+        instructions: vec![],
+        operand_kinds: vec![],
+        operands: vec![],
+        ..func
+    };
+    instructions
+        .into_iter()
+        .zip(operand_kinds)
+        .zip(operands)
+        .fold(
+            func,
+            |mut func, ((instruction, operand_kinds), operands)| {
+                func.instructions.push(instruction);
+                func.operand_kinds.push(operand_kinds);
+                func.operands.push(operands);
+                rewrite(func)
+            },
+        )
+}
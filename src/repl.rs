@@ -0,0 +1,335 @@
+use std::path::PathBuf;
+
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use crate::{
+    codegen::{codegen, type_name, widen, Type, Wasm},
+    loader::{load_source, top_level_names},
+    optimizer::optimize,
+    parser::{parse, BinaryOp, Function, Kind, Pattern},
+    tokenizer::tokenize,
+    writer::write,
+};
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Session {
+    pub definitions: Vec<String>,
+    pub locals: Vec<String>,
+    pub search_path: Vec<PathBuf>,
+}
+
+const RESULT: &str = "repl_result";
+
+const DEFINITIONS: [&str; 7] = ["def", "enum", "export", "import", "from", "const", "var"];
+
+pub fn session(search_path: Vec<PathBuf>) -> Session {
+    Session {
+        definitions: vec![],
+        locals: vec![],
+        search_path,
+    }
+}
+
+fn is_definition(input: &str) -> bool {
+    input.starts_with('@')
+        || input
+            .split_whitespace()
+            .next()
+            .is_some_and(|word| DEFINITIONS.contains(&word))
+}
+
+fn indent(input: &str) -> String {
+    input
+        .lines()
+        .map(|line| format!("    {}\n", line))
+        .collect()
+}
+
+fn source(definitions: &[String], locals: &[String], result: Type, body: &[&str]) -> String {
+    let start = locals
+        .iter()
+        .map(String::as_str)
+        .chain(body.iter().copied())
+        .map(indent)
+        .collect::<String>();
+    let start = format!("def start() -> {}:\n{}", type_name(result), start);
+    definitions
+        .iter()
+        .map(|definition| format!("{}\n\n", definition))
+        .chain(std::iter::once(start))
+        .collect()
+}
+
+fn result_type(session: &Session, definitions: &[String], body: &[&str]) -> Type {
+    let (last, rest) = body.split_last().unwrap();
+    let result = format!("{} = {}", RESULT, last);
+    let probe = rest
+        .iter()
+        .copied()
+        .chain([result.as_str(), "0"])
+        .collect::<Vec<&str>>();
+    let source = source(definitions, &session.locals, Type::I64, &probe);
+    let wasm = codegen(load_source("repl", &source, &session.search_path));
+    let start = &wasm.functions[wasm.name_to_function["start"]];
+    widen(start.types[start.name_to_local[RESULT]])
+}
+
+fn compile(session: &Session, definitions: &[String], body: &[&str]) -> Wasm {
+    let result = result_type(session, definitions, body);
+    let source = source(definitions, &session.locals, result, body);
+    optimize(codegen(load_source("repl", &source, &session.search_path)))
+}
+
+fn run(wasm: Wasm) -> Value {
+    let store = Store::default();
+    let module = Module::new(&store, write(wasm)).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start.call(&[]).unwrap()[0].clone()
+}
+
+fn operator(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Subtract => "-",
+        BinaryOp::Multiply => "*",
+        BinaryOp::Divide => "/",
+        BinaryOp::Modulo => "%",
+        BinaryOp::Equal => "==",
+        BinaryOp::NotEqual => "!=",
+        BinaryOp::BitwiseAnd => "&",
+        BinaryOp::BitwiseOr => "|",
+        BinaryOp::BitwiseXor => "^",
+        BinaryOp::LessThan => "<",
+        BinaryOp::LessThanEqual => "<=",
+        BinaryOp::GreaterThan => ">",
+        BinaryOp::GreaterThanEqual => ">=",
+        BinaryOp::ShiftLeft => "<<",
+        BinaryOp::ShiftRight => ">>",
+    }
+}
+
+fn render_all(func: &Function, entities: &[usize]) -> String {
+    entities
+        .iter()
+        .map(|&entity| render(func, entity))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+fn render_pattern(func: &Function, pattern: Pattern, bindings: &[usize]) -> String {
+    match pattern {
+        Pattern::Int(int) => func.ints[int].clone(),
+        Pattern::Range(low, high) => format!("{}..{}", func.ints[low], func.ints[high]),
+        Pattern::Wildcard => String::from("_"),
+        Pattern::Variant(name) if bindings.is_empty() => func.symbols[name].clone(),
+        Pattern::Variant(name) => {
+            bindings
+                .iter()
+                .fold(format!("({}", func.symbols[name]), |pattern, &binding| {
+                    format!("{} {}", pattern, func.symbols[binding])
+                })
+                + ")"
+        }
+    }
+}
+
+fn render_arm(func: &Function, index: usize, arm: usize) -> String {
+    let matches = &func.matches;
+    let pattern = render_pattern(
+        func,
+        matches.patterns[index][arm],
+        &matches.bindings[index][arm],
+    );
+    let body = render_all(func, &matches.bodies[index][arm]);
+    match matches.guards[index][arm] {
+        Some(guard) => format!("({} (if {}) {})", pattern, render(func, guard), body),
+        None => format!("({} {})", pattern, body),
+    }
+}
+
+fn render(func: &Function, entity: usize) -> String {
+    let index = func.indices[entity];
+    match func.kinds[entity] {
+        Kind::Symbol => func.symbols[index].clone(),
+        Kind::Int => func.ints[index].clone(),
+        Kind::Float => func.floats[index].clone(),
+        Kind::BinaryOp => format!(
+            "({} {} {})",
+            operator(func.binary_ops.ops[index]),
+            render(func, func.binary_ops.lefts[index]),
+            render(func, func.binary_ops.rights[index])
+        ),
+        Kind::Assign => format!(
+            "(= {} {})",
+            render(func, func.assignments.names[index]),
+            render(func, func.assignments.values[index])
+        ),
+        Kind::FunctionCall => {
+            let name = render(func, func.function_calls.names[index]);
+            match func.function_calls.parameters[index][..] {
+                [] => format!("({})", name),
+                ref parameters => format!("({} {})", name, render_all(func, parameters)),
+            }
+        }
+        Kind::If => {
+            let conditional = render(func, func.ifs.conditionals[index]);
+            let then = render_all(func, &func.ifs.then_branches[index]);
+            match func.ifs.else_branches[index][..] {
+                [] => format!("(if {} ({}))", conditional, then),
+                ref other => format!(
+                    "(if {} ({}) ({}))",
+                    conditional,
+                    then,
+                    render_all(func, other)
+                ),
+            }
+        }
+        Kind::While => format!(
+            "(while {} {})",
+            render(func, func.whiles.conditionals[index]),
+            render_all(func, &func.whiles.bodies[index])
+        ),
+        Kind::For => format!(
+            "(for {} {} {})",
+            render(func, func.fors.variables[index]),
+            render(func, func.fors.iterables[index]),
+            render_all(func, &func.fors.bodies[index])
+        ),
+        Kind::Array => format!("[{}]", render_all(func, &func.arrays[index])),
+        Kind::Match => {
+            (0..func.matches.patterns[index].len()).fold(
+                format!("(match {}", render(func, func.matches.values[index])),
+                |rendered, arm| format!("{} {}", rendered, render_arm(func, index, arm)),
+            ) + ")"
+        }
+        Kind::Lambda => format!(
+            "(fn ({}) {})",
+            func.lambdas.parameters[index]
+                .iter()
+                .map(|&parameter| func.symbols[parameter].as_str())
+                .collect::<Vec<&str>>()
+                .join(" "),
+            render_all(func, &func.lambdas.bodies[index])
+        ),
+        Kind::Grouping => render(func, func.groupings[index]),
+        Kind::Break => String::from("(break)"),
+        Kind::Continue => String::from("(continue)"),
+        Kind::Return => format!("(return {})", render(func, func.returns[index])),
+    }
+}
+
+fn ast(input: &str) -> String {
+    let ast = parse(tokenize(&source(&[], &[], Type::I64, &[input])));
+    let func = &ast.functions[ast.top_level["start"]];
+    func.expressions
+        .iter()
+        .map(|&expression| render(func, expression))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn wat(session: &Session, input: &str) -> String {
+    let name = assigned(input);
+    write(compile(session, &session.definitions, &body(input, &name)))
+}
+
+fn type_of(session: &Session, input: &str) -> String {
+    let name = assigned(input);
+    let result = result_type(session, &session.definitions, &body(input, &name));
+    type_name(result).to_string()
+}
+
+fn define(mut session: Session, input: &str) -> (Session, String) {
+    let defined = parse(tokenize(input));
+    let mut names = top_level_names(&defined).cloned().collect::<Vec<String>>();
+    names.sort();
+    let definitions = session
+        .definitions
+        .iter()
+        .filter(|definition| {
+            let ast = parse(tokenize(definition));
+            !top_level_names(&ast).any(|name| names.contains(name)) && *definition != input
+        })
+        .cloned()
+        .chain(std::iter::once(input.to_string()))
+        .collect::<Vec<String>>();
+    compile(&session, &definitions, &["0"]);
+    session.definitions = definitions;
+    (session, names.join(", "))
+}
+
+fn assigned(input: &str) -> Option<String> {
+    let ast = parse(tokenize(&source(&[], &[], Type::I64, &[input])));
+    let func = &ast.functions[ast.top_level["start"]];
+    let &last = func.expressions.last()?;
+    match func.kinds[last] {
+        Kind::Assign => {
+            let name = func.assignments.names[func.indices[last]];
+            Some(func.symbols[func.indices[name]].clone())
+        }
+        _ => None,
+    }
+}
+
+fn assigns(input: &str) -> bool {
+    let ast = parse(tokenize(&source(&[], &[], Type::I64, &[input])));
+    ast.functions[ast.top_level["start"]]
+        .kinds
+        .contains(&Kind::Assign)
+}
+
+fn valueless(input: &str) -> bool {
+    let ast = parse(tokenize(&source(&[], &[], Type::I64, &[input])));
+    let func = &ast.functions[ast.top_level["start"]];
+    func.expressions
+        .last()
+        .is_some_and(|&last| match func.kinds[last] {
+            Kind::While | Kind::For => true,
+            Kind::If => func.ifs.else_branches[func.indices[last]].is_empty(),
+            _ => false,
+        })
+}
+
+fn body<'a>(input: &'a str, name: &'a Option<String>) -> Vec<&'a str> {
+    std::iter::once(input).chain(name.as_deref()).collect()
+}
+
+// Every entry compiles a fresh module, so locals are rebuilt by replaying each earlier
+// entry that assigns to anything. The module has no imports, so replaying is only
+// observable as time spent recomputing those entries.
+fn evaluate_expression(mut session: Session, input: &str) -> (Session, String) {
+    let valueless = valueless(input);
+    let name = assigned(input);
+    let body = match valueless {
+        true => vec![input, "0"],
+        false => body(input, &name),
+    };
+    let value = run(compile(&session, &session.definitions, &body));
+    if assigns(input) {
+        session.locals.push(input.to_string());
+    }
+    match valueless {
+        true => (session, String::new()),
+        false => (session, format!("{:?}", value)),
+    }
+}
+
+pub fn evaluate(session: Session, input: &str) -> (Session, String) {
+    let input = input.trim_end();
+    match input.split_once(' ') {
+        Some((":ast", rest)) => (session, ast(rest.trim())),
+        Some((":wat", rest)) => {
+            let wat = wat(&session, rest.trim());
+            (session, wat)
+        }
+        Some((":type", rest)) => {
+            let type_of = type_of(&session, rest.trim());
+            (session, type_of)
+        }
+        _ if input.trim().is_empty() => (session, String::new()),
+        _ if input.starts_with(':') => panic!("Unknown command {}", input),
+        _ if is_definition(input) => define(session, input),
+        _ => evaluate_expression(session, input),
+    }
+}
@@ -1,12 +1,18 @@
-#[derive(Debug, PartialEq, Copy, Clone)]
+use std::hash::{Hash, Hasher};
+
+#[derive(Debug, PartialEq, Hash, Copy, Clone)]
 pub enum Kind {
     Def,
+    Fn,
     Symbol,
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
     Colon,
     Plus,
     Minus,
+    Arrow,
     Asterisk,
     Slash,
     Percent,
@@ -24,20 +30,39 @@ pub enum Kind {
     GreaterThanEqual,
     GreaterThanGreaterThan,
     Comma,
+    DotDot,
     Indent,
     Int,
+    Float,
     If,
     Else,
+    Elif,
     While,
+    For,
+    In,
+    Match,
+    Enum,
+    Export,
+    As,
+    Import,
+    From,
+    Const,
+    Var,
+    Break,
+    Continue,
+    Return,
+    Attribute,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct TopLevel {
     pub indices: Vec<usize>,
     pub kinds: Vec<Kind>,
     pub symbols: Vec<String>,
     pub ints: Vec<String>,
+    pub floats: Vec<String>,
     pub indents: Vec<usize>,
+    pub positions: Vec<usize>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -58,16 +83,42 @@ fn insert_symbol(mut top_level: TopLevel, symbol: String) -> TopLevel {
     top_level
 }
 
-fn tokenize_symbol(top_level: TopLevel, source: &str) -> (TopLevel, &str) {
-    let length = 1 + source[1..]
-        .chars()
-        .take_while(|&c| c.is_alphanumeric() || c == '_')
-        .count();
+fn is_identifier(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn symbol_length(source: &str, length: usize) -> usize {
+    let mut chars = source[length..].chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), _) if is_identifier(c) => symbol_length(source, length + c.len_utf8()),
+        (Some('.'), Some(c)) if is_identifier(c) => symbol_length(source, length + 1),
+        _ => length,
+    }
+}
+
+fn tokenize_symbol(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
+    top_level.positions.push(source.len());
+    let length = symbol_length(source, 0);
     let top_level = match &source[..length] {
         "def" => insert_keyword(top_level, Kind::Def),
+        "fn" => insert_keyword(top_level, Kind::Fn),
         "if" => insert_keyword(top_level, Kind::If),
         "else" => insert_keyword(top_level, Kind::Else),
+        "elif" => insert_keyword(top_level, Kind::Elif),
         "while" => insert_keyword(top_level, Kind::While),
+        "for" => insert_keyword(top_level, Kind::For),
+        "in" => insert_keyword(top_level, Kind::In),
+        "match" => insert_keyword(top_level, Kind::Match),
+        "enum" => insert_keyword(top_level, Kind::Enum),
+        "export" => insert_keyword(top_level, Kind::Export),
+        "as" => insert_keyword(top_level, Kind::As),
+        "import" => insert_keyword(top_level, Kind::Import),
+        "from" => insert_keyword(top_level, Kind::From),
+        "const" => insert_keyword(top_level, Kind::Const),
+        "var" => insert_keyword(top_level, Kind::Var),
+        "break" => insert_keyword(top_level, Kind::Break),
+        "continue" => insert_keyword(top_level, Kind::Continue),
+        "return" => insert_keyword(top_level, Kind::Return),
         _ => insert_symbol(top_level, source[..length].to_string()),
     };
     tokenize_top_level(top_level, &source[length..])
@@ -76,6 +127,7 @@ fn tokenize_symbol(top_level: TopLevel, source: &str) -> (TopLevel, &str) {
 fn tokenize_one(mut top_level: TopLevel, source: &str, kind: Kind) -> (TopLevel, &str) {
     top_level.kinds.push(kind);
     top_level.indices.push(0);
+    top_level.positions.push(source.len());
     tokenize_top_level(top_level, &source[1..])
 }
 
@@ -86,6 +138,18 @@ fn tokenize_equal(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
     };
     top_level.kinds.push(kind);
     top_level.indices.push(0);
+    top_level.positions.push(source.len());
+    tokenize_top_level(top_level, &source[length..])
+}
+
+fn tokenize_minus(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
+    let (length, kind) = match source.chars().nth(1) {
+        Some('>') => (2, Kind::Arrow),
+        _ => (1, Kind::Minus),
+    };
+    top_level.kinds.push(kind);
+    top_level.indices.push(0);
+    top_level.positions.push(source.len());
     tokenize_top_level(top_level, &source[length..])
 }
 
@@ -96,13 +160,23 @@ fn tokenize_vertical_bar(mut top_level: TopLevel, source: &str) -> (TopLevel, &s
     };
     top_level.kinds.push(kind);
     top_level.indices.push(0);
+    top_level.positions.push(source.len());
     tokenize_top_level(top_level, &source[length..])
 }
 
+fn tokenize_dot(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
+    assert_eq!(source.chars().nth(1), Some('.'), "expected .. for a range");
+    top_level.kinds.push(Kind::DotDot);
+    top_level.indices.push(0);
+    top_level.positions.push(source.len());
+    tokenize_top_level(top_level, &source[2..])
+}
+
 fn tokenize_exclamation(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
     assert_eq!(source.chars().skip(1).next().unwrap(), '=');
     top_level.kinds.push(Kind::ExclamationEqual);
     top_level.indices.push(0);
+    top_level.positions.push(source.len());
     tokenize_top_level(top_level, &source[2..])
 }
 
@@ -114,6 +188,7 @@ fn tokenize_less_than(mut top_level: TopLevel, source: &str) -> (TopLevel, &str)
     };
     top_level.kinds.push(kind);
     top_level.indices.push(0);
+    top_level.positions.push(source.len());
     tokenize_top_level(top_level, &source[length..])
 }
 
@@ -125,15 +200,34 @@ fn tokenize_greater_than(mut top_level: TopLevel, source: &str) -> (TopLevel, &s
     };
     top_level.kinds.push(kind);
     top_level.indices.push(0);
+    top_level.positions.push(source.len());
     tokenize_top_level(top_level, &source[length..])
 }
 
 fn tokenize_number(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
     let length = 1 + source[1..].chars().take_while(|c| c.is_numeric()).count();
-    top_level.kinds.push(Kind::Int);
-    top_level.indices.push(top_level.ints.len());
-    top_level.ints.push(source[..length].to_string());
-    tokenize_top_level(top_level, &source[length..])
+    let mut rest = source[length..].chars();
+    match (rest.next(), rest.next()) {
+        (Some('.'), Some(c)) if c.is_numeric() => {
+            let fraction = source[length + 1..]
+                .chars()
+                .take_while(|c| c.is_numeric())
+                .count();
+            let length = length + 1 + fraction;
+            top_level.kinds.push(Kind::Float);
+            top_level.indices.push(top_level.floats.len());
+            top_level.positions.push(source.len());
+            top_level.floats.push(source[..length].to_string());
+            tokenize_top_level(top_level, &source[length..])
+        }
+        _ => {
+            top_level.kinds.push(Kind::Int);
+            top_level.indices.push(top_level.ints.len());
+            top_level.positions.push(source.len());
+            top_level.ints.push(source[..length].to_string());
+            tokenize_top_level(top_level, &source[length..])
+        }
+    }
 }
 
 fn tokenize_indent(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
@@ -143,9 +237,13 @@ fn tokenize_indent(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
         .count();
     match source[length + 1..].chars().next() {
         Some('#') => tokenize_comment(top_level, &source[length + 1..]),
+        _ if top_level.kinds.last() == Some(&Kind::Attribute) => {
+            tokenize_top_level(top_level, &source[length + 1..])
+        }
         _ if length > 0 => {
             top_level.kinds.push(Kind::Indent);
             top_level.indices.push(top_level.indents.len());
+            top_level.positions.push(source.len());
             top_level.indents.push(length);
             tokenize_top_level(top_level, &source[length + 1..])
         }
@@ -153,6 +251,18 @@ fn tokenize_indent(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
     }
 }
 
+fn tokenize_attribute(mut top_level: TopLevel, source: &str) -> (TopLevel, &str) {
+    let length = source[1..]
+        .chars()
+        .take_while(|&c| c.is_alphanumeric() || c == '_')
+        .count();
+    top_level.kinds.push(Kind::Attribute);
+    top_level.indices.push(top_level.symbols.len());
+    top_level.positions.push(source.len());
+    top_level.symbols.push(source[1..length + 1].to_string());
+    tokenize_top_level(top_level, &source[length + 1..])
+}
+
 fn tokenize_comment(top_level: TopLevel, source: &str) -> (TopLevel, &str) {
     let length = source[1..].chars().take_while(|&c| c != '\n').count() + 1;
     tokenize_top_level(top_level, &source[length..])
@@ -169,12 +279,15 @@ fn tokenize_top_level(top_level: TopLevel, source: &str) -> (TopLevel, &str) {
         Some(c) if c.is_alphabetic() || c == '_' => tokenize_symbol(top_level, source),
         Some('(') => tokenize_one(top_level, source, Kind::LeftParen),
         Some(')') => tokenize_one(top_level, source, Kind::RightParen),
+        Some('[') => tokenize_one(top_level, source, Kind::LeftBracket),
+        Some(']') => tokenize_one(top_level, source, Kind::RightBracket),
         Some('+') => tokenize_one(top_level, source, Kind::Plus),
-        Some('-') => tokenize_one(top_level, source, Kind::Minus),
+        Some('-') => tokenize_minus(top_level, source),
         Some('*') => tokenize_one(top_level, source, Kind::Asterisk),
         Some('/') => tokenize_one(top_level, source, Kind::Slash),
         Some('%') => tokenize_one(top_level, source, Kind::Percent),
         Some(',') => tokenize_one(top_level, source, Kind::Comma),
+        Some('.') => tokenize_dot(top_level, source),
         Some(':') => tokenize_one(top_level, source, Kind::Colon),
         Some('=') => tokenize_equal(top_level, source),
         Some('&') => tokenize_one(top_level, source, Kind::Ampersand),
@@ -186,12 +299,13 @@ fn tokenize_top_level(top_level: TopLevel, source: &str) -> (TopLevel, &str) {
         Some('0'..='9') => tokenize_number(top_level, source),
         Some('\n') => tokenize_indent(top_level, source),
         Some('#') => tokenize_comment(top_level, source),
+        Some('@') => tokenize_attribute(top_level, source),
         Some(c) => panic!("not implemented for char \"{}\"", c),
         None => (top_level, source),
     }
 }
 
-fn tokenize_impl(mut tokens: Tokens, source: &str) -> Tokens {
+fn tokenize_impl(mut tokens: Tokens, source: &str, length: usize) -> Tokens {
     let source = trim(source, |c| c.is_whitespace());
     if source.len() == 0 {
         tokens
@@ -201,17 +315,82 @@ fn tokenize_impl(mut tokens: Tokens, source: &str) -> Tokens {
             kinds: vec![],
             symbols: vec![],
             ints: vec![],
+            floats: vec![],
             indents: vec![],
+            positions: vec![],
         };
-        let (top_level, source) = tokenize_top_level(top_level, source);
+        let (mut top_level, source) = tokenize_top_level(top_level, source);
+        top_level.positions = top_level
+            .positions
+            .iter()
+            .map(|remaining| length - remaining)
+            .collect();
         if top_level.indices.len() > 0 {
             tokens.top_level.push(top_level);
         }
-        tokenize_impl(tokens, source)
+        tokenize_impl(tokens, source, length)
+    }
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+pub(crate) struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Fnv {
+        Fnv(FNV_OFFSET)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
     }
+
+    fn write_u16(&mut self, value: u16) {
+        self.write(&value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes())
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write(&value.to_le_bytes())
+    }
+
+    fn write_usize(&mut self, value: usize) {
+        self.write_u64(value as u64)
+    }
+
+    fn write_i64(&mut self, value: i64) {
+        self.write_u64(value as u64)
+    }
+
+    fn write_isize(&mut self, value: isize) {
+        self.write_u64(value as u64)
+    }
+}
+
+pub fn hash(top_level: &TopLevel) -> u64 {
+    let mut hasher = Fnv::default();
+    top_level.indices.hash(&mut hasher);
+    top_level.kinds.hash(&mut hasher);
+    top_level.symbols.hash(&mut hasher);
+    top_level.ints.hash(&mut hasher);
+    top_level.floats.hash(&mut hasher);
+    top_level.indents.hash(&mut hasher);
+    hasher.finish()
 }
 
 pub fn tokenize(source: &str) -> Tokens {
     let tokens = Tokens { top_level: vec![] };
-    tokenize_impl(tokens, source)
+    tokenize_impl(tokens, source, source.len())
 }
@@ -2,7 +2,9 @@ use std::fmt::{Error, Write};
 
 use rayon::prelude::*;
 
-use crate::codegen::{Function, Instruction, OperandKind, Wasm};
+use crate::codegen::{
+    type_name, Exports, Function, Globals, Instruction, OperandKind, Wasm, TYPES,
+};
 
 pub fn write_i64_const(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
     assert_eq!(func.operand_kinds[i], vec![OperandKind::IntLiteral]);
@@ -13,6 +15,15 @@ pub fn write_i64_const(mut code: String, func: &Function, i: usize) -> Result<St
     Ok(code)
 }
 
+pub fn write_f64_const(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    assert_eq!(func.operand_kinds[i], vec![OperandKind::FloatLiteral]);
+    let operands = &func.operands[i];
+    assert_eq!(operands.len(), 1);
+    let literal = &func.floats[operands[0]];
+    write!(code, "\n    (f64.const {})", literal)?;
+    Ok(code)
+}
+
 pub fn write_set_local(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
     assert_eq!(func.operand_kinds[i], vec![OperandKind::Local]);
     let operands = &func.operands[i];
@@ -31,6 +42,15 @@ pub fn write_get_local(mut code: String, func: &Function, i: usize) -> Result<St
     Ok(code)
 }
 
+pub fn write_tee_local(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    assert_eq!(func.operand_kinds[i], vec![OperandKind::Local]);
+    let operands = &func.operands[i];
+    assert_eq!(operands.len(), 1);
+    let local = &func.locals[operands[0]];
+    write!(code, "\n    (tee_local {})", local)?;
+    Ok(code)
+}
+
 pub fn write_call(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
     assert_eq!(func.operand_kinds[i], vec![OperandKind::Symbol]);
     let operands = &func.operands[i];
@@ -40,6 +60,36 @@ pub fn write_call(mut code: String, func: &Function, i: usize) -> Result<String,
     Ok(code)
 }
 
+pub fn write_call_indirect(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    assert_eq!(func.operand_kinds[i], vec![OperandKind::Type]);
+    let operands = &func.operands[i];
+    assert_eq!(operands.len(), 1);
+    write!(
+        code,
+        "\n    (call_indirect (type $.closure.{}))",
+        operands[0]
+    )?;
+    Ok(code)
+}
+
+pub fn write_global_get(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    assert_eq!(func.operand_kinds[i], vec![OperandKind::Symbol]);
+    let operands = &func.operands[i];
+    assert_eq!(operands.len(), 1);
+    let symbol = &func.symbols[operands[0]];
+    write!(code, "\n    (global.get ${})", symbol)?;
+    Ok(code)
+}
+
+pub fn write_global_set(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    assert_eq!(func.operand_kinds[i], vec![OperandKind::Symbol]);
+    let operands = &func.operands[i];
+    assert_eq!(operands.len(), 1);
+    let symbol = &func.symbols[operands[0]];
+    write!(code, "\n    (global.set ${})", symbol)?;
+    Ok(code)
+}
+
 pub fn write_str(mut code: String, text: &str) -> Result<String, Error> {
     write!(code, "\n    {}", text)?;
     Ok(code)
@@ -48,8 +98,9 @@ pub fn write_str(mut code: String, text: &str) -> Result<String, Error> {
 fn write_arguments(code: String, func: &Function) -> Result<String, Error> {
     func.locals[..func.arguments]
         .iter()
-        .try_fold(code, |mut code, local| {
-            write!(code, " (param {} i64)", local)?;
+        .zip(func.types.iter())
+        .try_fold(code, |mut code, (local, &value_type)| {
+            write!(code, " (param {} {})", local, type_name(value_type))?;
             Ok(code)
         })
 }
@@ -57,25 +108,69 @@ fn write_arguments(code: String, func: &Function) -> Result<String, Error> {
 fn write_locals(code: String, func: &Function) -> Result<String, Error> {
     func.locals[func.arguments..]
         .iter()
-        .try_fold(code, |mut code, local| {
-            write!(code, "\n    (local {} i64)", local)?;
+        .zip(func.types[func.arguments..].iter())
+        .try_fold(code, |mut code, (local, &value_type)| {
+            write!(code, "\n    (local {} {})", local, type_name(value_type))?;
             Ok(code)
         })
 }
 
 pub fn write_block(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
-    assert_eq!(func.operand_kinds[i], vec![OperandKind::Label]);
     let operands = &func.operands[i];
-    assert_eq!(operands.len(), 1);
-    write!(code, "\n    block $.label.{}", operands[0])?;
+    match &func.operand_kinds[i][..] {
+        [OperandKind::Label] => write!(code, "\n    block $.label.{}", operands[0])?,
+        [OperandKind::Label, OperandKind::Result] => write!(
+            code,
+            "\n    block $.label.{} (result {})",
+            operands[0],
+            type_name(TYPES[operands[1]])
+        )?,
+        operand_kinds => panic!("Invalid block operands {:?}", operand_kinds),
+    }
     Ok(code)
 }
 
 pub fn write_loop(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
-    assert_eq!(func.operand_kinds[i], vec![OperandKind::Label]);
     let operands = &func.operands[i];
-    assert_eq!(operands.len(), 1);
-    write!(code, "\n    loop $.label.{}", operands[0])?;
+    match &func.operand_kinds[i][..] {
+        [OperandKind::Label] => write!(code, "\n    loop $.label.{}", operands[0])?,
+        [OperandKind::Label, OperandKind::Result] => write!(
+            code,
+            "\n    loop $.label.{} (result {})",
+            operands[0],
+            type_name(TYPES[operands[1]])
+        )?,
+        operand_kinds => panic!("Invalid loop operands {:?}", operand_kinds),
+    }
+    Ok(code)
+}
+
+pub fn write_memory_access(
+    mut code: String,
+    func: &Function,
+    i: usize,
+    text: &str,
+) -> Result<String, Error> {
+    write!(code, "\n    {}", text)?;
+    let operand_kinds = &func.operand_kinds[i];
+    if !operand_kinds.is_empty() {
+        assert_eq!(operand_kinds, &[OperandKind::Offset]);
+        let operands = &func.operands[i];
+        assert_eq!(operands.len(), 1);
+        write!(code, " offset={}", operands[0])?;
+    }
+    Ok(code)
+}
+
+pub fn write_if(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    code.push_str("\n    if");
+    let operand_kinds = &func.operand_kinds[i];
+    if !operand_kinds.is_empty() {
+        assert_eq!(operand_kinds, &[OperandKind::Result]);
+        let operands = &func.operands[i];
+        assert_eq!(operands.len(), 1);
+        write!(code, " (result {})", type_name(TYPES[operands[0]]))?;
+    }
     Ok(code)
 }
 
@@ -99,6 +194,17 @@ pub fn write_br_if(mut code: String, func: &Function, i: usize) -> Result<String
     Ok(code)
 }
 
+pub fn write_br_table(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
+    assert!(func.operand_kinds[i]
+        .iter()
+        .all(|kind| *kind == OperandKind::Label));
+    code.push_str("\n    br_table");
+    func.operands[i].iter().try_fold(code, |mut code, label| {
+        write!(code, " $.label.{}", label)?;
+        Ok(code)
+    })
+}
+
 pub fn write_br(mut code: String, func: &Function, i: usize) -> Result<String, Error> {
     assert_eq!(func.operand_kinds[i], vec![OperandKind::Label]);
     let operands = &func.operands[i];
@@ -110,7 +216,7 @@ pub fn write_br(mut code: String, func: &Function, i: usize) -> Result<String, E
 fn write_function(mut code: String, func: &Function) -> Result<String, Error> {
     write!(code, "\n\n  (func ${}", func.symbols[func.name])?;
     let mut code = write_arguments(code, &func)?;
-    code.push_str(" (result i64)");
+    write!(code, " (result {})", type_name(func.result))?;
     let code = write_locals(code, &func)?;
     let mut code =
         func.instructions
@@ -118,6 +224,7 @@ fn write_function(mut code: String, func: &Function) -> Result<String, Error> {
             .enumerate()
             .try_fold(code, |code, (i, instruction)| match instruction {
                 Instruction::I64Const => write_i64_const(code, &func, i),
+                Instruction::F64Const => write_f64_const(code, func, i),
                 Instruction::I64Add => write_str(code, "i64.add"),
                 Instruction::I64Sub => write_str(code, "i64.sub"),
                 Instruction::I64Mul => write_str(code, "i64.mul"),
@@ -134,26 +241,108 @@ fn write_function(mut code: String, func: &Function) -> Result<String, Error> {
                 Instruction::I64GtS => write_str(code, "i64.gt_s"),
                 Instruction::I64LeS => write_str(code, "i64.le_s"),
                 Instruction::I64GeS => write_str(code, "i64.ge_s"),
+                Instruction::F64Add => write_str(code, "f64.add"),
+                Instruction::F64Sub => write_str(code, "f64.sub"),
+                Instruction::F64Mul => write_str(code, "f64.mul"),
+                Instruction::F64Div => write_str(code, "f64.div"),
+                Instruction::F64Eq => write_str(code, "f64.eq"),
+                Instruction::F64Ne => write_str(code, "f64.ne"),
+                Instruction::F64Lt => write_str(code, "f64.lt"),
+                Instruction::F64Le => write_str(code, "f64.le"),
+                Instruction::F64Gt => write_str(code, "f64.gt"),
+                Instruction::F64Ge => write_str(code, "f64.ge"),
+                Instruction::I64Eqz => write_str(code, "i64.eqz"),
                 Instruction::I32Eqz => write_str(code, "i32.eqz"),
+                Instruction::I32And => write_str(code, "i32.and"),
+                Instruction::I64GeU => write_str(code, "i64.ge_u"),
                 Instruction::SetLocal => write_set_local(code, &func, i),
                 Instruction::GetLocal => write_get_local(code, &func, i),
+                Instruction::TeeLocal => write_tee_local(code, func, i),
+                Instruction::GlobalGet => write_global_get(code, func, i),
+                Instruction::GlobalSet => write_global_set(code, func, i),
                 Instruction::Call => write_call(code, &func, i),
-                Instruction::If => write_str(code, "if (result i64)"),
+                Instruction::CallIndirect => write_call_indirect(code, func, i),
+                Instruction::If => write_if(code, func, i),
                 Instruction::Block => write_block(code, &func, i),
                 Instruction::Loop => write_loop(code, &func, i),
                 Instruction::Else => write_str(code, "else"),
                 Instruction::End => write_end(code, &func, i),
                 Instruction::BrIf => write_br_if(code, &func, i),
                 Instruction::Br => write_br(code, &func, i),
+                Instruction::BrTable => write_br_table(code, func, i),
+                Instruction::Return => write_str(code, "return"),
+                Instruction::Drop => write_str(code, "drop"),
+                Instruction::Unreachable => write_str(code, "unreachable"),
+                Instruction::I32WrapI64 => write_str(code, "i32.wrap_i64"),
+                Instruction::I64ExtendI32U => write_str(code, "i64.extend_i32_u"),
+                Instruction::I64Load => write_memory_access(code, func, i, "i64.load"),
+                Instruction::I64Store => write_memory_access(code, func, i, "i64.store"),
+                Instruction::MemorySize => write_str(code, "memory.size"),
+                Instruction::MemoryGrow => write_str(code, "memory.grow"),
             })?;
     code.push(')');
     Ok(code)
 }
 
+fn write_globals(code: String, globals: &Globals) -> Result<String, Error> {
+    globals
+        .names
+        .iter()
+        .zip(globals.values.iter())
+        .try_fold(code, |mut code, (name, value)| {
+            write!(
+                code,
+                "\n\n  (global ${} (mut i64) (i64.const {}))",
+                name, value
+            )?;
+            Ok(code)
+        })
+}
+
+fn write_types(code: String, types: &[usize]) -> Result<String, Error> {
+    types.iter().try_fold(code, |mut code, &arity| {
+        write!(code, "\n\n  (type $.closure.{} (func (param", arity)?;
+        (0..arity).for_each(|_| code.push_str(" i64"));
+        code.push_str(") (result i64)))");
+        Ok(code)
+    })
+}
+
+fn write_table(mut code: String, table: &[String], types: &[usize]) -> Result<String, Error> {
+    if table.is_empty() && types.is_empty() {
+        return Ok(code);
+    }
+    write!(code, "\n\n  (table {} funcref)", table.len())?;
+    if !table.is_empty() {
+        code.push_str("\n\n  (elem (i32.const 0)");
+        table
+            .iter()
+            .try_for_each(|function| write!(code, " ${}", function))?;
+        code.push(')');
+    }
+    Ok(code)
+}
+
+fn write_exports(code: String, exports: &Exports) -> Result<String, Error> {
+    exports.names.iter().zip(exports.functions.iter()).try_fold(
+        code,
+        |mut code, (name, function)| {
+            write!(code, "\n\n  (export \"{}\" (func ${}))", name, function)?;
+            Ok(code)
+        },
+    )
+}
+
 pub fn write(wasm: Wasm) -> String {
     let mut code = String::new();
     code.push_str("\n(module");
-    let mut code = wasm
+    let mut code = write_types(code, &wasm.types).unwrap();
+    if wasm.memory {
+        code.push_str("\n\n  (memory 1)");
+    }
+    let code = write_table(code, &wasm.table, &wasm.types).unwrap();
+    let code = write_globals(code, &wasm.globals).unwrap();
+    let code = wasm
         .functions
         .par_iter()
         .map(|function| write_function(String::new(), function).unwrap())
@@ -163,6 +352,7 @@ pub fn write(wasm: Wasm) -> String {
             code.push_str(&fragment);
             code
         });
-    code.push_str("\n\n  (export \"_start\" (func $start)))\n");
+    let mut code = write_exports(code, &wasm.exports).unwrap();
+    code.push_str(")\n");
     code
 }
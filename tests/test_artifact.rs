@@ -0,0 +1,167 @@
+use std::{
+    env,
+    fs::{self, File},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use mongoose::{
+    artifact::{artifact_path, fresh, load_module, restore, save, source_files, stamps},
+    codegen::codegen,
+    loader::{load_packages, load_project},
+    manifest::read_manifest,
+    writer::write,
+};
+
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = env::temp_dir().join(format!("mongoose_{}", name));
+    let _ = fs::remove_dir_all(&directory);
+    for (path, source) in files {
+        let path = directory.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    directory
+}
+
+fn call(module: &Module) -> Value {
+    let instance = Instance::new(module, &imports! {}).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start.call(&[]).unwrap()[0].clone()
+}
+
+fn touch(path: &PathBuf, time: SystemTime) {
+    File::options()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_modified(time)
+        .unwrap();
+}
+
+#[test]
+fn test_artifact_round_trip() {
+    let directory = write_files(
+        "test_artifact_round_trip",
+        &[
+            (
+                "app/mongoose.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nutils = { path = \"../utils\" }\n",
+            ),
+            (
+                "app/src/main.mon",
+                "from utils.math import square\n\ndef start(): square(6)\n",
+            ),
+            (
+                "utils/mongoose.toml",
+                "[package]\nname = \"utils\"\nversion = \"0.1.0\"\n",
+            ),
+            ("utils/src/math.mon", "def square(x): x * x\n"),
+        ],
+    );
+    let directory = directory.join("app");
+    let sources = source_files(&load_packages(&directory, read_manifest(&directory)));
+    let mut names = sources
+        .iter()
+        .map(|source| source.file_name().unwrap().to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    names.sort();
+    assert_eq!(
+        names,
+        vec!["main.mon", "math.mon", "mongoose.toml", "mongoose.toml"]
+    );
+    let artifact = artifact_path(&directory, "app", true);
+    assert!(!fresh(&artifact, &sources));
+    let code = write(codegen(load_project(&directory, read_manifest(&directory))));
+    let store = Store::default();
+    save(&Module::new(&store, &code).unwrap(), &artifact);
+    let past = SystemTime::now() - Duration::from_secs(60);
+    for source in sources.iter() {
+        touch(source, past);
+    }
+    assert!(fresh(&artifact, &sources));
+    let module = restore(&store, &artifact).unwrap();
+    assert_eq!(call(&module), Value::I64(36));
+    let dependency = sources
+        .iter()
+        .find(|source| source.ends_with("math.mon"))
+        .unwrap();
+    touch(dependency, SystemTime::now() + Duration::from_secs(60));
+    assert!(!fresh(&artifact, &sources));
+}
+
+#[test]
+fn test_artifact_restore_invalid() {
+    let directory = write_files(
+        "test_artifact_restore_invalid",
+        &[("build/app.wasmu", "garbage")],
+    );
+    let store = Store::default();
+    assert!(restore(&store, &artifact_path(&directory, "app", true)).is_none());
+}
+
+#[test]
+fn test_artifact_stamps() {
+    let directory = write_files(
+        "test_artifact_stamps",
+        &[
+            ("main.mon", "def start(): 1\n"),
+            ("util.mon", "def f(): 2\n"),
+        ],
+    );
+    let sources = vec![
+        directory.join("main.mon"),
+        directory.join("util.mon"),
+        directory.join("missing.mon"),
+    ];
+    let past = SystemTime::now() - Duration::from_secs(60);
+    touch(&sources[0], past);
+    touch(&sources[1], past);
+    let before = stamps(&sources);
+    assert_eq!(before[2], None);
+    assert_eq!(stamps(&sources), before);
+    touch(&sources[1], SystemTime::now());
+    let after = stamps(&sources);
+    assert_eq!(after[0], before[0]);
+    assert_ne!(after[1], before[1]);
+}
+
+#[test]
+fn test_artifact_run_refreshes_stale() {
+    let directory = write_files(
+        "test_artifact_run_refreshes_stale",
+        &[
+            (
+                "mongoose.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\n",
+            ),
+            ("src/main.mon", "def start(): 6 * 6\n"),
+        ],
+    );
+    let sources = source_files(&load_packages(&directory, read_manifest(&directory)));
+    let past = SystemTime::now() - Duration::from_secs(60);
+    for source in sources.iter() {
+        touch(source, past);
+    }
+    let compile = || write(codegen(load_project(&directory, read_manifest(&directory))));
+    let artifact = artifact_path(&directory, "app", true);
+    assert_ne!(artifact, artifact_path(&directory, "app", false));
+    let store = Store::default();
+    let module = load_module(&store, &artifact, &sources, false, compile);
+    assert_eq!(call(&module), Value::I64(36));
+    assert!(!artifact.exists());
+    let module = load_module(&store, &artifact, &sources, true, compile);
+    assert_eq!(call(&module), Value::I64(36));
+    assert!(fresh(&artifact, &sources));
+    let main = directory.join("src").join("main.mon");
+    fs::write(&main, "def start(): 7 * 7\n").unwrap();
+    touch(&main, SystemTime::now() + Duration::from_secs(60));
+    assert!(!fresh(&artifact, &sources));
+    let module = load_module(&store, &artifact, &sources, false, compile);
+    assert_eq!(call(&module), Value::I64(49));
+    touch(&main, past);
+    assert!(fresh(&artifact, &sources));
+    assert_eq!(call(&restore(&store, &artifact).unwrap()), Value::I64(49));
+}
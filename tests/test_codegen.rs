@@ -1,9 +1,14 @@
-use std::str;
+use std::{env, fs, path::Path, str};
 
 use pretty_assertions::assert_eq;
 use wasmer::{imports, Instance, Module, Store, Value};
 
-use mongoose::{codegen::codegen, parser::parse, tokenizer::tokenize, writer::write};
+use mongoose::{
+    codegen::{codegen, codegen_cached},
+    parser::parse,
+    tokenizer::tokenize,
+    writer::write,
+};
 
 fn run(code: &str) -> Value {
     let store = Store::default();
@@ -659,3 +664,1249 @@ def start():
     );
     assert_eq!(run(&code), Value::I64(10));
 }
+
+#[test]
+#[should_panic(expected = "Export name _start is used by both start and main")]
+fn test_codegen_duplicate_start_export() {
+    let source = r#"
+def start(): 1
+
+export def main() as _start: 2"#;
+    codegen(parse(tokenize(source)));
+}
+
+#[test]
+#[should_panic(expected = "Export name x is used by both f and g")]
+fn test_codegen_duplicate_export_alias() {
+    let source = r#"
+export def f() as x: 1
+
+export def g() as x: 2"#;
+    codegen(parse(tokenize(source)));
+}
+
+#[test]
+fn test_codegen_export_without_start() {
+    let source = r#"
+def square(x): x * x
+
+export def area(w, h) as rect_area: w * h
+
+export def cube(x): square(x) * x"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $area (param $w i64) (param $h i64) (result i64)
+    (get_local $w)
+    (get_local $h)
+    i64.mul)
+
+  (func $cube (param $x i64) (result i64)
+    (get_local $x)
+    (call $square)
+    (get_local $x)
+    i64.mul)
+
+  (func $square (param $x i64) (result i64)
+    (get_local $x)
+    (get_local $x)
+    i64.mul)
+
+  (export "rect_area" (func $area))
+
+  (export "cube" (func $cube)))
+"#
+    );
+    let store = Store::default();
+    let module = Module::new(&store, &code).unwrap();
+    let instance = Instance::new(&module, &imports! {}).unwrap();
+    let area = instance.exports.get_function("rect_area").unwrap();
+    let result = area.call(&[Value::I64(3), Value::I64(4)]).unwrap();
+    assert_eq!(result[0], Value::I64(12));
+    let cube = instance.exports.get_function("cube").unwrap();
+    let result = cube.call(&[Value::I64(3)]).unwrap();
+    assert_eq!(result[0], Value::I64(27));
+}
+
+#[test]
+fn test_codegen_globals() {
+    let source = r#"
+const STEP = 2
+const LIMIT = STEP * 5
+var counter = LIMIT - 10
+
+def tick():
+    counter = counter + STEP
+    counter
+
+def start():
+    while counter < LIMIT:
+        tick()
+    counter"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (global $counter (mut i64) (i64.const 0))
+
+  (func $start (result i64)
+    block $.label.0
+    loop $.label.1
+    (global.get $counter)
+    (i64.const 10)
+    i64.lt_s
+    i32.eqz
+    br_if $.label.0
+    (call $tick)
+    drop
+    br $.label.1
+    end $.label.1
+    end $.label.0
+    (global.get $counter))
+
+  (func $tick (result i64)
+    (global.get $counter)
+    (i64.const 2)
+    i64.add
+    (global.set $counter)
+    (global.get $counter))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(10));
+}
+
+#[test]
+fn test_codegen_break_continue_return() {
+    let source = r#"
+def start():
+    i = 0
+    total = 0
+    while i < 100:
+        i = i + 1
+        if i > 10: break
+        if i % 2 == 0: continue
+        total = total + i
+    return total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (local $i i64)
+    (local $total i64)
+    (i64.const 0)
+    (set_local $i)
+    (i64.const 0)
+    (set_local $total)
+    block $.label.0
+    loop $.label.1
+    (get_local $i)
+    (i64.const 100)
+    i64.lt_s
+    i32.eqz
+    br_if $.label.0
+    (get_local $i)
+    (i64.const 1)
+    i64.add
+    (set_local $i)
+    (get_local $i)
+    (i64.const 10)
+    i64.gt_s
+    if
+    br $.label.0
+    end
+    (get_local $i)
+    (i64.const 2)
+    i64.rem_s
+    (i64.const 0)
+    i64.eq
+    if
+    br $.label.1
+    end
+    (get_local $total)
+    (get_local $i)
+    i64.add
+    (set_local $total)
+    br $.label.1
+    end $.label.1
+    end $.label.0
+    (get_local $total)
+    return)
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(25));
+}
+
+#[test]
+#[should_panic(expected = "break can only be used inside a loop")]
+fn test_codegen_break_outside_loop() {
+    let tokens = tokenize("def start(): break");
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+fn test_codegen_elif() {
+    let source = r#"
+def sign(x):
+    if x < 0: 0 - 1 elif x > 0: 1 else: 0
+
+def start(): sign(0 - 7) * 100 + sign(7) * 10 + sign(0)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (i64.const 0)
+    (i64.const 7)
+    i64.sub
+    (call $sign)
+    (i64.const 100)
+    i64.mul
+    (i64.const 7)
+    (call $sign)
+    (i64.const 10)
+    i64.mul
+    (i64.const 0)
+    (call $sign)
+    i64.add
+    i64.add)
+
+  (func $sign (param $x i64) (result i64)
+    (get_local $x)
+    (i64.const 0)
+    i64.lt_s
+    if (result i64)
+    (i64.const 0)
+    (i64.const 1)
+    i64.sub
+    else
+    (get_local $x)
+    (i64.const 0)
+    i64.gt_s
+    if (result i64)
+    (i64.const 1)
+    else
+    (i64.const 0)
+    end
+    end)
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(-90));
+}
+
+#[test]
+fn test_codegen_if_statement() {
+    let source = r#"
+def start():
+    x = 5
+    if x > 3:
+        x = x * 2
+        x
+    x"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (local $x i64)
+    (i64.const 5)
+    (set_local $x)
+    (get_local $x)
+    (i64.const 3)
+    i64.gt_s
+    if
+    (get_local $x)
+    (i64.const 2)
+    i64.mul
+    (set_local $x)
+    (get_local $x)
+    drop
+    end
+    (get_local $x))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(10));
+}
+
+#[test]
+#[should_panic(expected = "if without else used as a value in start")]
+fn test_codegen_if_without_else_as_value() {
+    let tokens = tokenize("def start(): if 1 < 2: 5");
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+#[should_panic(expected = "if without else used as a value in f")]
+fn test_codegen_if_without_else_assigned() {
+    let source = r#"
+def f(x):
+    y = if x > 0: 3
+    y
+
+def start(): f(1)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+fn test_codegen_for_range() {
+    let source = r#"
+def start():
+    total = 0
+    for i in range(1, 5):
+        total = total + i
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (local $total i64)
+    (local $i i64)
+    (local $.end.0 i64)
+    (i64.const 0)
+    (set_local $total)
+    (i64.const 1)
+    (set_local $i)
+    (i64.const 5)
+    (set_local $.end.0)
+    block $.label.0
+    loop $.label.2
+    (get_local $i)
+    (get_local $.end.0)
+    i64.lt_s
+    i32.eqz
+    br_if $.label.0
+    block $.label.1
+    (get_local $total)
+    (get_local $i)
+    i64.add
+    (set_local $total)
+    end $.label.1
+    (get_local $i)
+    (i64.const 1)
+    i64.add
+    (set_local $i)
+    br $.label.2
+    end $.label.2
+    end $.label.0
+    (get_local $total))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(10));
+}
+
+#[test]
+fn test_codegen_for_continue() {
+    let source = r#"
+def start():
+    total = 0
+    for i in range(10):
+        if i % 2 == 0: continue
+        if i > 7: break
+        total = total + i
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(run(&code), Value::I64(16));
+}
+
+#[test]
+fn test_codegen_for_array() {
+    let source = r#"
+def start():
+    total = 0
+    for x in [3, 4, 5]:
+        total = total + x
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (memory 1)
+
+  (global $.heap (mut i64) (i64.const 0))
+
+  (func $start (result i64)
+    (local $total i64)
+    (local $x i64)
+    (local $.array.0 i64)
+    (local $.array.3 i64)
+    (local $.index.0 i64)
+    (i64.const 0)
+    (set_local $total)
+    (i64.const 32)
+    (call $.allocate)
+    (set_local $.array.3)
+    (get_local $.array.3)
+    i32.wrap_i64
+    (i64.const 3)
+    i64.store
+    (get_local $.array.3)
+    i32.wrap_i64
+    (i64.const 3)
+    i64.store offset=8
+    (get_local $.array.3)
+    i32.wrap_i64
+    (i64.const 4)
+    i64.store offset=16
+    (get_local $.array.3)
+    i32.wrap_i64
+    (i64.const 5)
+    i64.store offset=24
+    (get_local $.array.3)
+    (set_local $.array.0)
+    (i64.const 0)
+    (set_local $.index.0)
+    block $.label.0
+    loop $.label.2
+    (get_local $.index.0)
+    (get_local $.array.0)
+    i32.wrap_i64
+    i64.load
+    i64.lt_s
+    i32.eqz
+    br_if $.label.0
+    (get_local $.array.0)
+    (get_local $.index.0)
+    (i64.const 8)
+    i64.mul
+    i64.add
+    i32.wrap_i64
+    i64.load offset=8
+    (set_local $x)
+    block $.label.1
+    (get_local $total)
+    (get_local $x)
+    i64.add
+    (set_local $total)
+    end $.label.1
+    (get_local $.index.0)
+    (i64.const 1)
+    i64.add
+    (set_local $.index.0)
+    br $.label.2
+    end $.label.2
+    end $.label.0
+    (get_local $total))
+
+  (func $.allocate (param $.size i64) (result i64)
+    (local $.object i64)
+    (global.get $.heap)
+    (set_local $.object)
+    (global.get $.heap)
+    (get_local $.size)
+    i64.add
+    (global.set $.heap)
+    (global.get $.heap)
+    memory.size
+    i64.extend_i32_u
+    (i64.const 16)
+    i64.shl
+    i64.gt_s
+    if
+    (global.get $.heap)
+    memory.size
+    i64.extend_i32_u
+    (i64.const 16)
+    i64.shl
+    i64.sub
+    (i64.const 65535)
+    i64.add
+    (i64.const 16)
+    i64.shr_s
+    i32.wrap_i64
+    memory.grow
+    i64.extend_i32_u
+    (i64.const 4294967295)
+    i64.eq
+    if
+    unreachable
+    end
+    end
+    (get_local $.object))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(12));
+}
+
+#[test]
+fn test_codegen_match_table() {
+    let source = r#"
+def score(x):
+    match x:
+        1: 10
+        2: 20
+        3..5: 30
+        _: 0
+
+def start(): score(1) + score(2) + score(4) + score(9) + score(0 - 4294967295)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $score (param $x i64) (result i64)
+    (local $.match.1 i64)
+    (get_local $x)
+    (set_local $.match.1)
+    block $.label.0 (result i64)
+    block $.label.4
+    block $.label.3
+    block $.label.2
+    block $.label.1
+    (get_local $.match.1)
+    (i64.const 1)
+    i64.sub
+    (set_local $.match.1)
+    (get_local $.match.1)
+    (i64.const 4)
+    i64.ge_u
+    br_if $.label.4
+    (get_local $.match.1)
+    i32.wrap_i64
+    br_table $.label.1 $.label.2 $.label.3 $.label.3 $.label.4
+    end $.label.1
+    (i64.const 10)
+    br $.label.0
+    end $.label.2
+    (i64.const 20)
+    br $.label.0
+    end $.label.3
+    (i64.const 30)
+    br $.label.0
+    end $.label.4
+    (i64.const 0)
+    end $.label.0)"#
+    ));
+    assert_eq!(run(&code), Value::I64(60));
+}
+
+#[test]
+fn test_codegen_match_guards() {
+    let source = r#"
+def classify(x):
+    match x:
+        0: 1
+        1..10: 2
+        _ if x > 100: 3
+        _: 4
+
+def start():
+    total = 0
+    for x in [0, 5, 500, 50]:
+        match x:
+            0: total = total + classify(x)
+            _: total = total * 10 + classify(x)
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $classify (param $x i64) (result i64)
+    (local $.match.1 i64)
+    (get_local $x)
+    (set_local $.match.1)
+    (get_local $.match.1)
+    (i64.const 0)
+    i64.eq
+    if (result i64)
+    (i64.const 1)
+    else
+    (get_local $.match.1)
+    (i64.const 1)
+    i64.ge_s
+    (get_local $.match.1)
+    (i64.const 10)
+    i64.lt_s
+    i32.and
+    if (result i64)
+    (i64.const 2)
+    else
+    (get_local $x)
+    (i64.const 100)
+    i64.gt_s
+    if (result i64)
+    (i64.const 3)
+    else
+    (i64.const 4)
+    end
+    end
+    end)"#
+    ));
+    assert_eq!(run(&code), Value::I64(1234));
+}
+
+#[test]
+#[should_panic(expected = "match in start is not exhaustive, add a _ arm")]
+fn test_codegen_match_not_exhaustive() {
+    let source = r#"
+def start():
+    match 1:
+        0: 1
+        1: 2"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+fn test_codegen_enum() {
+    let source = r#"
+enum Shape: Circle(r), Rect(w, h), Empty
+
+def area(shape):
+    match shape:
+        Circle(r): 3 * r * r
+        Rect(w, h) if w == h: 1000
+        Rect(w, h): w * h
+        Empty: 0
+
+def start(): area(Circle(2)) + area(Rect(3, 4)) + area(Rect(5, 5)) + area(Empty)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $area (param $shape i64) (result i64)
+    (local $.match.1 i64)
+    (local $r i64)
+    (local $w i64)
+    (local $h i64)
+    (get_local $shape)
+    (set_local $.match.1)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load
+    (i64.const 0)
+    i64.eq
+    if (result i64)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load offset=8
+    (set_local $r)
+    (i64.const 3)
+    (get_local $r)
+    (get_local $r)
+    i64.mul
+    i64.mul
+    else
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load
+    (i64.const 1)
+    i64.eq
+    if (result i64)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load offset=8
+    else
+    (i64.const 0)
+    end
+    (set_local $w)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load
+    (i64.const 1)
+    i64.eq
+    if (result i64)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load offset=16
+    else
+    (i64.const 0)
+    end
+    (set_local $h)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load
+    (i64.const 1)
+    i64.eq
+    (get_local $w)
+    (get_local $h)
+    i64.eq
+    i32.and
+    if (result i64)
+    (i64.const 1000)
+    else
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load
+    (i64.const 1)
+    i64.eq
+    if (result i64)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load offset=8
+    (set_local $w)
+    (get_local $.match.1)
+    i32.wrap_i64
+    i64.load offset=16
+    (set_local $h)
+    (get_local $w)
+    (get_local $h)
+    i64.mul
+    else
+    (i64.const 0)
+    end
+    end
+    end)"#
+    ));
+    assert_eq!(run(&code), Value::I64(1024));
+}
+
+#[test]
+#[should_panic(expected = "match in area is not exhaustive, missing Rect, Empty")]
+fn test_codegen_enum_not_exhaustive() {
+    let source = r#"
+enum Shape: Circle(r), Rect(w, h), Empty
+
+def area(shape):
+    match shape:
+        Circle(r): r
+
+def start(): area(Circle(1))"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+#[should_panic(expected = "Rect expects 2 fields, found 1")]
+fn test_codegen_enum_wrong_arity() {
+    let source = r#"
+enum Shape: Circle(r), Rect(w, h)
+
+def start():
+    match Rect(1):
+        _: 0"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+fn test_codegen_lambda() {
+    let source = r#"
+def start():
+    offset = 10
+    add = fn(x): x + offset
+    offset = 100
+    add(5)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (type $.closure.2 (func (param i64 i64) (result i64)))
+
+  (memory 1)
+
+  (table 1 funcref)
+
+  (elem (i32.const 0) $start.lambda.0)
+
+  (global $.heap (mut i64) (i64.const 0))
+
+  (func $start (result i64)
+    (local $offset i64)
+    (local $.closure.1 i64)
+    (local $add i64)
+    (i64.const 10)
+    (set_local $offset)
+    (i64.const 16)
+    (call $.allocate)
+    (set_local $.closure.1)
+    (get_local $.closure.1)
+    i32.wrap_i64
+    (i64.const 0)
+    i64.store
+    (get_local $.closure.1)
+    i32.wrap_i64
+    (get_local $offset)
+    i64.store offset=8
+    (get_local $.closure.1)
+    (set_local $add)
+    (i64.const 100)
+    (set_local $offset)
+    (get_local $add)
+    (i64.const 5)
+    (get_local $add)
+    i32.wrap_i64
+    i64.load
+    i32.wrap_i64
+    (call_indirect (type $.closure.2)))
+
+  (func $start.lambda.0 (param $.env i64) (param $x i64) (result i64)
+    (local $offset i64)
+    (get_local $.env)
+    i32.wrap_i64
+    i64.load offset=8
+    (set_local $offset)
+    (get_local $x)
+    (get_local $offset)
+    i64.add)
+
+  (func $.allocate (param $.size i64) (result i64)
+    (local $.object i64)
+    (global.get $.heap)
+    (set_local $.object)
+    (global.get $.heap)
+    (get_local $.size)
+    i64.add
+    (global.set $.heap)
+    (global.get $.heap)
+    memory.size
+    i64.extend_i32_u
+    (i64.const 16)
+    i64.shl
+    i64.gt_s
+    if
+    (global.get $.heap)
+    memory.size
+    i64.extend_i32_u
+    (i64.const 16)
+    i64.shl
+    i64.sub
+    (i64.const 65535)
+    i64.add
+    (i64.const 16)
+    i64.shr_s
+    i32.wrap_i64
+    memory.grow
+    i64.extend_i32_u
+    (i64.const 4294967295)
+    i64.eq
+    if
+    unreachable
+    end
+    end
+    (get_local $.object))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(15));
+}
+
+#[test]
+fn test_codegen_function_values() {
+    let source = r#"
+enum List: Cons(head, tail), Nil
+
+def double(x): x * 2
+
+def map(list, f):
+    match list:
+        Cons(head, tail): Cons(f(head), map(tail, f))
+        Nil: Nil
+
+def filter(list, f):
+    match list:
+        Cons(head, tail): if f(head) == 1: Cons(head, filter(tail, f)) else: filter(tail, f)
+        Nil: Nil
+
+def sum(list):
+    match list:
+        Cons(head, tail): head + sum(tail)
+        Nil: 0
+
+def compose(f, g): fn(x): g(f(x))
+
+def start():
+    limit = 8
+    add_one = fn(x): x + 1
+    Cons(1, Cons(2, Cons(3, Cons(4, Nil))))
+    |> map(compose(double, add_one))
+    |> filter(fn(x): if x < limit: 1 else: 0)
+    |> sum()"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $double.closure (param $.env i64) (param $.argument.0 i64) (result i64)
+    (get_local $.argument.0)
+    (call $double))"#
+    ));
+    assert!(code.contains(
+        r#"
+  (func $compose.lambda.0 (param $.env i64) (param $x i64) (result i64)
+    (local $g i64)
+    (local $f i64)
+    (get_local $.env)
+    i32.wrap_i64
+    i64.load offset=8
+    (set_local $g)
+    (get_local $.env)
+    i32.wrap_i64
+    i64.load offset=16
+    (set_local $f)
+    (get_local $g)
+    (get_local $f)
+    (get_local $x)
+    (get_local $f)
+    i32.wrap_i64
+    i64.load
+    i32.wrap_i64
+    (call_indirect (type $.closure.2))
+    (get_local $g)
+    i32.wrap_i64
+    i64.load
+    i32.wrap_i64
+    (call_indirect (type $.closure.2)))"#
+    ));
+    assert_eq!(run(&code), Value::I64(15));
+}
+
+#[test]
+fn test_codegen_generic_function() {
+    let source = r#"
+def max[T](a: T, b: T) -> T:
+    if a > b: a else: b
+
+def area(width: f64, height: f64) -> f64: width * height
+
+def start() -> f64:
+    largest = max(3, 7)
+    max(area(1.5, 2.0), 2.5) + max(0.5, 0.25)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $max<i64> (param $a i64) (param $b i64) (result i64)
+    (get_local $a)
+    (get_local $b)
+    i64.gt_s
+    if (result i64)
+    (get_local $a)
+    else
+    (get_local $b)
+    end)"#
+    ));
+    assert!(code.contains(
+        r#"
+  (func $max<f64> (param $a f64) (param $b f64) (result f64)
+    (get_local $a)
+    (get_local $b)
+    f64.gt
+    if (result f64)
+    (get_local $a)
+    else
+    (get_local $b)
+    end)"#
+    ));
+    assert!(code.contains(
+        r#"
+  (func $start (result f64)
+    (local $largest i64)
+    (i64.const 3)
+    (i64.const 7)
+    (call $max<i64>)
+    (set_local $largest)
+    (f64.const 1.5)
+    (f64.const 2.0)
+    (call $area)
+    (f64.const 2.5)
+    (call $max<f64>)
+    (f64.const 0.5)
+    (f64.const 0.25)
+    (call $max<f64>)
+    f64.add)"#
+    ));
+    assert_eq!(code.matches("(func $max<f64>").count(), 1);
+    assert_eq!(run(&code), Value::F64(3.5));
+}
+
+#[test]
+#[should_panic(expected = "max called with T = i64 and T = f64")]
+fn test_codegen_generic_conflicting_types() {
+    let source = r#"
+def max[T](a: T, b: T) -> T:
+    if a > b: a else: b
+
+def start(): max(1, 2.0)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+#[should_panic(expected = "Cannot apply Add to i64 and f64 in start")]
+fn test_codegen_mixed_types() {
+    let source = r#"
+def start(): 1 + 2.5"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+fn test_codegen_tail_call() {
+    let source = r#"
+def fib_impl(n, prev, curr):
+    if n == 0:
+        curr
+    else:
+        fib_impl(n - 1, curr, prev + curr)
+
+def start(): fib_impl(10, 0, 1)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (i64.const 10)
+    (i64.const 0)
+    (i64.const 1)
+    (call $fib_impl))
+
+  (func $fib_impl (param $n i64) (param $prev i64) (param $curr i64) (result i64)
+    loop $.label.0 (result i64)
+    (get_local $n)
+    (i64.const 0)
+    i64.eq
+    if (result i64)
+    (get_local $curr)
+    else
+    (get_local $n)
+    (i64.const 1)
+    i64.sub
+    (get_local $curr)
+    (get_local $prev)
+    (get_local $curr)
+    i64.add
+    (set_local $curr)
+    (set_local $prev)
+    (set_local $n)
+    br $.label.0
+    end
+    end $.label.0)
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(89));
+}
+
+#[test]
+fn test_codegen_tail_call_constant_stack() {
+    let source = r#"
+def count(n, total):
+    if n == 0: return total
+    match n % 2:
+        0: count(n - 1, total + 2)
+        _: count(n - 1, total + 1)
+
+def start(): count(1000000, 0)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(!code.contains("call $count\n"));
+    assert_eq!(run(&code), Value::I64(1500000));
+}
+
+#[test]
+fn test_codegen_non_tail_call() {
+    let source = r#"
+def factorial(n):
+    if n == 0: 1 else: n * factorial(n - 1)
+
+def start(): factorial(10)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains("(call $factorial)"));
+    assert!(!code.contains("loop"));
+    assert_eq!(run(&code), Value::I64(3628800));
+}
+
+#[test]
+fn test_codegen_warnings() {
+    let source = r#"
+def helper(x): x * 2
+
+def unused(y): y
+
+def scale(value, factor, _ignored):
+    previous = value
+    value * factor
+
+def start(): scale(helper(2), 3, 0)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    assert_eq!(
+        wasm.warnings,
+        vec![
+            "function unused is never used",
+            "local previous in scale is never read",
+        ]
+    );
+}
+
+#[test]
+fn test_codegen_unused_parameter_warning() {
+    let source = r#"
+def first(a, b): a
+def start(): first(1, 2)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    assert_eq!(wasm.warnings, vec!["parameter b of first is never used"]);
+}
+
+fn cached(source: &str, cache: &Path) -> (String, usize) {
+    let code = write(codegen_cached(parse(tokenize(source)), cache));
+    assert_eq!(code, write(codegen(parse(tokenize(source)))));
+    (code, fs::read_dir(cache).unwrap().count())
+}
+
+#[test]
+fn test_codegen_cached() {
+    let cache = env::temp_dir().join("mongoose_test_codegen_cached");
+    let _ = fs::remove_dir_all(&cache);
+    let source = r#"
+def square(x): x * x
+
+def max[T](a: T, b: T) -> T:
+    if a > b: a else: b
+
+def start():
+    offset = 3
+    add = fn(x): x + offset
+    largest = max(1.5, 2.5)
+    max(square(add(1)), 10)"#;
+    let (code, entries) = cached(source, &cache);
+    assert_eq!(entries, 4);
+    assert_eq!(run(&code), Value::I64(16));
+    assert_eq!(cached(source, &cache), (code, entries));
+    let edited = source.replace("x * x", "x * x * x");
+    let (code, entries) = cached(&edited, &cache);
+    assert_eq!(entries, 5);
+    assert_eq!(run(&code), Value::I64(64));
+    let annotated = edited.replace("def square(x):", "def square(x) -> i64:");
+    let (code, entries) = cached(&annotated, &cache);
+    assert_eq!(entries, 7);
+    assert_eq!(run(&code), Value::I64(64));
+    let mut paths = fs::read_dir(&cache)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect::<Vec<_>>();
+    paths.sort();
+    let first = fs::read_to_string(&paths[0]).unwrap();
+    for path in paths.iter() {
+        fs::write(path, &first).unwrap();
+    }
+    assert_eq!(cached(&annotated, &cache), (code, entries));
+}
+
+#[test]
+fn test_codegen_pipeline_lambda() {
+    let source = r#"
+def start():
+    offset = 10
+    5 |> fn(x): x + offset"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert!(code.contains("(call_indirect (type $.closure.2))"));
+    assert_eq!(run(&code), Value::I64(15));
+}
+
+#[test]
+#[should_panic(expected = "Closures can only take i64 arguments, found f64 in start")]
+fn test_codegen_closure_float_argument() {
+    let tokens = tokenize("def start(): 4 + 2.5 |> fn(x): 0");
+    let ast = parse(tokens);
+    codegen(ast);
+}
+
+#[test]
+fn test_codegen_pipeline_local_function() {
+    let source = r#"
+def start():
+    double = fn(x): x * 2
+    add = fn(x, y): x + y
+    5
+    |> double
+    |> add(1)
+    |> fn(x): x - 3"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(run(&code), Value::I64(8));
+}
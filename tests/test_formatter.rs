@@ -0,0 +1,132 @@
+use std::{fs, path::Path};
+
+use pretty_assertions::assert_eq;
+
+use mongoose::formatter::{format, sources};
+
+#[test]
+fn test_format_layout() {
+    let source = r#"
+import geometry.shapes   as shapes
+from pipeline import square,min
+
+
+const LIMIT=10
+var counter =0
+enum Shape:
+  Circle( radius )
+  Square(side)
+@inline
+def area(s) -> f64:
+  match s:
+      Circle(r):r*r*3.0
+      Square(x) if x>1.0 :x*x
+      _: 0.0
+def start():
+  total=0
+  for i in range(0,LIMIT):
+          total=total+i
+          if total>20:
+             break
+          else:
+             continue
+  f=fn(a,b):a+b
+  while total<100 :total = f(total , 1)
+  total
+"#;
+    assert_eq!(
+        format(source),
+        r#"import geometry.shapes as shapes
+from pipeline import square, min
+
+const LIMIT = 10
+var counter = 0
+
+enum Shape:
+    Circle(radius)
+    Square(side)
+
+@inline
+def area(s) -> f64:
+    match s:
+        Circle(r): r * r * 3.0
+        Square(x) if x > 1.0: x * x
+        _: 0.0
+
+def start():
+    total = 0
+    for i in range(0, LIMIT):
+        total = total + i
+        if total > 20:
+            break
+        else:
+            continue
+    f = fn(a, b): a + b
+    while total < 100: total = f(total, 1)
+    total
+"#
+    );
+}
+
+#[test]
+fn test_format_comments() {
+    let source = r#"# header
+def square(x):  # trailing
+  # leading body comment
+  x * x
+      # end of body
+# between functions
+
+
+def start(): square(3)   # last
+# footer
+"#;
+    assert_eq!(
+        format(source),
+        r#"# header
+def square(x): # trailing
+    # leading body comment
+    x * x
+    # end of body
+
+# between functions
+def start(): square(3) # last
+
+# footer
+"#
+    );
+}
+
+#[test]
+fn test_format_pipelines() {
+    let source = r#"
+def start():
+  x = 5 |> square |> min(20)
+  y = 5 |> square
+          |> min(20)
+  x + y
+"#;
+    assert_eq!(
+        format(source),
+        r#"def start():
+    x = 5 |> square |> min(20)
+    y = 5
+    |> square
+    |> min(20)
+    x + y
+"#
+    );
+}
+
+#[test]
+fn test_format_examples() {
+    for path in sources(Path::new("examples")) {
+        let formatted = format(&fs::read_to_string(&path).unwrap());
+        assert_eq!(
+            format(&formatted),
+            formatted,
+            "{} does not format stably",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use pretty_assertions::assert_eq;
+use std::{fs, panic};
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use mongoose::{
+    codegen::codegen,
+    interpreter::{self, interpret},
+    loader::load_source,
+    optimizer::optimize,
+    writer::write,
+};
+
+fn run(code: &str) -> Result<Value, String> {
+    let store = Store::default();
+    let module = Module::new(&store, code).unwrap();
+    let import_object = imports! {};
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start
+        .call(&[])
+        .map(|results| results[0].clone())
+        .map_err(|error| error.message())
+}
+
+fn convert(value: interpreter::Value) -> Value {
+    match value {
+        interpreter::Value::I64(value) => Value::I64(value),
+        interpreter::Value::F64(value) => Value::F64(value),
+    }
+}
+
+fn compare(source: &str) -> Value {
+    let interpreted = convert(interpret(&load_source("test", source, &[])));
+    let compiled = run(&write(codegen(load_source("test", source, &[])))).unwrap();
+    let optimized = run(&write(optimize(codegen(load_source("test", source, &[]))))).unwrap();
+    assert_eq!(interpreted, compiled, "{}", source);
+    assert_eq!(interpreted, optimized, "{}", source);
+    interpreted
+}
+
+#[test]
+fn test_interpreter_integer_semantics() {
+    let cases = [
+        ("9223372036854775807 + 1", i64::MIN),
+        ("((0 - 9223372036854775807) - 1) % (0 - 1)", 0),
+        ("(0 - 7) / 2", -3),
+        ("(0 - 7) % 2", -1),
+        ("7 % (0 - 2)", 1),
+        ("1 << 65", 2),
+        ("(0 - 8) >> 1", -4),
+        ("(0 - 1) >> 70", -1),
+        ("3037000500 * 3037000500", -9223372036709301616),
+        ("(6 & 3) + (6 | 3) * 10 + (6 ^ 3) * 100", 572),
+        ("0 - 2 - 3", 1),
+    ];
+    for (expression, expected) in cases {
+        let source = format!("def start(): {}", expression);
+        assert_eq!(compare(&source), Value::I64(expected), "{}", expression);
+    }
+}
+
+#[test]
+fn test_interpreter_traps() {
+    let sources = [
+        "def divide(a, b): a / b\n\ndef start(): divide(1, 0)",
+        "def divide(a, b): a / b\n\ndef start(): divide((0 - 9223372036854775807) - 1, 0 - 1)",
+        "def remainder(a, b): a % b\n\ndef start(): remainder(1, 0)",
+    ];
+    for source in sources {
+        assert!(run(&write(codegen(load_source("test", source, &[])))).is_err());
+        let interpreted = panic::catch_unwind(|| interpret(&load_source("test", source, &[])));
+        assert!(interpreted.is_err(), "{}", source);
+    }
+}
+
+#[test]
+fn test_interpreter_floats() {
+    let source = r#"
+def half(x: f64) -> f64: x / 2.0
+
+def max[T](a: T, b: T) -> T: if a > b: a else: b
+
+def start() -> f64:
+    y = half(3.0) + 0.25
+    if max(y, 1.0) == y: y * 4.0 else: 0.0 - 1.0"#;
+    assert_eq!(compare(source), Value::F64(7.0));
+}
+
+#[test]
+fn test_interpreter_language() {
+    let source = r#"
+enum Shape: Circle(r), Rect(w, h), Empty
+
+var counter = 0
+
+const LIMIT = 10
+
+def area(shape):
+    match shape:
+        Circle(r): 3 * r * r
+        Rect(w, h) if w == h: w * w + 1000
+        Rect(w, h): w * h
+        Empty: 0
+
+def bump(n):
+    counter = counter + n
+    counter
+
+def sum_to(n, acc): if n == 0: acc else: sum_to(n - 1, acc + n)
+
+def classify(j):
+    match j:
+        3..5: j
+        5: 100
+        6: 50
+        7: 25
+        _: 1
+
+def start():
+    total = 0
+    for shape in [Circle(2), Rect(3, 4), Rect(5, 5), Empty]:
+        total = total + area(shape)
+    i = 0
+    while i < 100:
+        i = i + 1
+        if i % 2 == 0:
+            continue
+        if i > 15:
+            break
+        total = total + i
+    for j in range(3, LIMIT):
+        total = total + classify(j)
+    offset = 7
+    add = fn(x): x + offset
+    twice = fn(f, x): f(f(x))
+    total = total + twice(add, 1) + fold([1, 2, 3], 0, fn(a, b): a + b)
+    bump(5)
+    bump(6)
+    total + counter * 1000 + sum_to(100000, 0) + unwrap_or(Some(4), 0) + unwrap_or(None, 9) * 10"#;
+    assert_eq!(compare(source), Value::I64(5000062412));
+}
+
+#[test]
+fn test_interpreter_examples() {
+    let mut paths = fs::read_dir("examples")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "mon"))
+        .filter(|path| !path.ends_with("fib_naive.mon"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty());
+    for path in paths {
+        let ast = mongoose::loader::load(&path, &[]);
+        let interpreted = convert(interpret(&ast));
+        let compiled = run(&write(codegen(mongoose::loader::load(&path, &[])))).unwrap();
+        assert_eq!(interpreted, compiled, "{}", path.display());
+    }
+}
+
+fn message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast::<&str>().unwrap().to_string(),
+    }
+}
+
+#[test]
+fn test_interpreter_rejects_invalid_programs() {
+    let sources = [
+        "def f(x):\n    match x:\n        1: 0\n        _ if x > 100: 2\n\ndef start(): f(5)",
+        "def f(x):\n    match x:\n        1: 0\n        2: 1\n\ndef start(): f(5)",
+        "enum Shape: Circle(r), Rect(w, h)\n\ndef f(s):\n    match s:\n        Circle(r): r\n\ndef start(): f(Circle(2))",
+        "def f(x): if x > 1: 2\n\ndef start(): f(5)",
+    ];
+    for source in sources {
+        let compiled = panic::catch_unwind(|| codegen(load_source("test", source, &[])));
+        let interpreted = panic::catch_unwind(|| interpret(&load_source("test", source, &[])));
+        match (compiled, interpreted) {
+            (Err(compiled), Err(interpreted)) => {
+                assert_eq!(message(compiled), message(interpreted), "{}", source)
+            }
+            _ => panic!("expected both backends to reject {}", source),
+        }
+    }
+}
+
+#[test]
+fn test_interpreter_memory_growth() {
+    let source = r#"
+def start():
+    total = 0
+    for i in range(3000):
+        for x in [i, 1, 2]:
+            total = total + x
+    total"#;
+    assert_eq!(compare(source), Value::I64(4507500));
+}
+
+#[test]
+fn test_interpreter_variant_memory_growth() {
+    let source = r#"
+def check(i): if i % 3 == 0: None else: Some(i)
+
+def start():
+    total = 0
+    for i in range(10000):
+        total = total + unwrap_or(check(i), 0)
+    total"#;
+    assert_eq!(compare(source), Value::I64(33326667));
+}
+
+#[test]
+fn test_interpreter_pipeline_closures() {
+    let source = r#"
+def start():
+    offset = 10
+    double = fn(x): x * 2
+    5
+    |> double
+    |> double
+    |> fn(x): x + offset"#;
+    assert_eq!(compare(source), Value::I64(30));
+}
+
+#[test]
+fn test_interpreter_deep_recursion() {
+    let source = "def down(n): if n == 0: 0 else: 1 + down(n - 1)\n\ndef start(): down(50000)";
+    assert_eq!(compare(source), Value::I64(50000));
+    let source = "def down(n): if n == 0: 0 else: 1 + down(n - 1)\n\ndef start(): down(10000000)";
+    assert_eq!(
+        run(&write(codegen(load_source("test", source, &[])))),
+        Err(String::from("call stack exhausted"))
+    );
+    let interpreted = panic::catch_unwind(|| interpret(&load_source("test", source, &[])));
+    assert_eq!(message(interpreted.unwrap_err()), "call stack exhausted");
+}
@@ -0,0 +1,210 @@
+use pretty_assertions::assert_eq;
+use std::collections::HashSet;
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use mongoose::{
+    codegen::codegen,
+    ir::{build, build_functions, default_passes, lower_functions, run_passes, Kind, Terminator},
+    optimizer::optimize,
+    parser::parse,
+    tokenizer::tokenize,
+    writer::write,
+};
+
+fn run(code: &str) -> Value {
+    let store = Store::default();
+    let module = Module::new(&store, code).unwrap();
+    let import_object = imports! {};
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start.call(&[]).unwrap()[0].clone()
+}
+
+fn compile(source: &str) -> (String, String) {
+    let original = write(codegen(parse(tokenize(source))));
+    (
+        original,
+        write(lower_functions(codegen(parse(tokenize(source))))),
+    )
+}
+
+#[test]
+fn test_ir_loop_phis() {
+    let source = r#"
+def fib(n):
+    a = 0
+    b = 1
+    i = 0
+    while i < n:
+        t = a + b
+        a = b
+        b = t
+        i = i + 1
+    a"#;
+    let ast = parse(tokenize(source));
+    let functions = ["fib"].iter().map(|name| name.to_string()).collect();
+    let func = build(&ast.functions[0], &functions, &HashSet::new()).unwrap();
+    let func = run_passes(&default_passes(), func);
+    assert_eq!(func.blocks.terminators[0], Terminator::Jump(1));
+    let header = func.blocks.values[1]
+        .iter()
+        .filter(|&&value| func.values.kinds[value] == Kind::Phi)
+        .count();
+    assert_eq!(header, 3);
+    assert_eq!(func.blocks.predecessors[1], vec![0, 2]);
+}
+
+#[test]
+fn test_ir_passes_fold_branches() {
+    let source = r#"
+def f(x):
+    y = 3 * 4
+    if 2 > 1: x + y else: x"#;
+    let ast = parse(tokenize(source));
+    let functions = ["f"].iter().map(|name| name.to_string()).collect();
+    let func = build(&ast.functions[0], &functions, &HashSet::new()).unwrap();
+    let func = run_passes(&default_passes(), func);
+    assert!(func
+        .blocks
+        .terminators
+        .iter()
+        .all(|terminator| !matches!(terminator, Terminator::Branch(..))));
+    let live = func.blocks.values.iter().flatten().copied();
+    assert!(live
+        .clone()
+        .all(|value| func.values.kinds[value] != Kind::Phi));
+    assert!(live
+        .into_iter()
+        .any(|value| func.values.kinds[value] == Kind::Int
+            && func.values.ints[func.values.indices[value]] == 12));
+}
+
+#[test]
+fn test_ir_unsupported_function() {
+    let ast = parse(tokenize("def f(x: f64) -> f64: x"));
+    let functions = ["f"].iter().map(|name| name.to_string()).collect();
+    assert_eq!(build(&ast.functions[0], &functions, &HashSet::new()), None);
+}
+
+#[test]
+fn test_ir_if_without_else_value() {
+    let ast = parse(tokenize("def f(x):\n    y = if x > 0: 3\n    y"));
+    let functions = ["f"].iter().map(|name| name.to_string()).collect();
+    assert_eq!(build(&ast.functions[0], &functions, &HashSet::new()), None);
+}
+
+#[test]
+fn test_ir_lower_while() {
+    let source = r#"
+def fib(n):
+    a = 0
+    b = 1
+    i = 0
+    while i < n:
+        t = a + b
+        a = b
+        b = t
+        i = i + 1
+    a
+
+def start(): fib(10)"#;
+    let (original, lowered) = compile(source);
+    assert!(lowered.contains("loop $.label."));
+    assert_eq!(run(&original), Value::I64(55));
+    assert_eq!(run(&lowered), Value::I64(55));
+}
+
+#[test]
+fn test_ir_lower_if_values() {
+    let source = r#"
+def sign(x): if x < 0: 0 - 1 elif x > 0: 1 else: 0
+
+def start(): sign(0 - 5) * 100 + sign(7) * 10 + sign(0)"#;
+    let (original, lowered) = compile(source);
+    assert_eq!(run(&original), Value::I64(-90));
+    assert_eq!(run(&lowered), Value::I64(-90));
+}
+
+#[test]
+fn test_ir_lower_parallel_copies() {
+    let source = r#"
+def swap(n):
+    a = 1
+    b = 2
+    i = 0
+    while i < n:
+        t = a
+        a = b
+        b = t
+        i = i + 1
+    a * 10 + b
+
+def start(): swap(3) * 100 + swap(4)"#;
+    let (original, lowered) = compile(source);
+    assert_eq!(run(&original), Value::I64(2112));
+    assert_eq!(run(&lowered), Value::I64(2112));
+}
+
+#[test]
+fn test_ir_lower_nested_loops() {
+    let source = r#"
+def sum(n):
+    total = 0
+    i = 0
+    while i < n:
+        j = 0
+        while j < i:
+            if (i + j) % 3 == 0:
+                total = total + i * j
+            j = j + 1
+        i = i + 1
+    total
+
+def start(): sum(12)"#;
+    let (original, lowered) = compile(source);
+    assert_eq!(run(&lowered), run(&original));
+}
+
+#[test]
+fn test_ir_optimize_lowers_functions() {
+    let source = r#"
+@noinline
+def fib(n):
+    a = 0
+    b = 1
+    i = 0
+    while i < n:
+        t = a + b
+        a = b
+        b = t
+        i = i + 1
+    a
+
+def start(): fib(10)"#;
+    let wasm = codegen(parse(tokenize(source)));
+    assert!(build_functions(wasm.ast.as_ref().unwrap()).contains_key("fib"));
+    let code = write(optimize(wasm));
+    assert!(code.contains("(local $.value."));
+    assert_eq!(run(&code), Value::I64(55));
+}
+
+#[test]
+fn test_ir_optimize_fallback() {
+    let source = r#"
+@noinline
+def count(n, total): if n == 0: total else: count(n - 1, total + n)
+
+@noinline
+def shift(x):
+    add = fn(y): y + x
+    add(1)
+
+def start(): count(100000, 0) + shift(2)"#;
+    let wasm = codegen(parse(tokenize(source)));
+    let ir = build_functions(wasm.ast.as_ref().unwrap());
+    assert!(ir.contains_key("count"));
+    assert!(!ir.contains_key("shift"));
+    let code = write(optimize(wasm));
+    assert!(code.contains("call_indirect"));
+    assert_eq!(run(&code), Value::I64(5000050003));
+}
@@ -0,0 +1,246 @@
+use std::{env, fs, path::PathBuf};
+
+use pretty_assertions::assert_eq;
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use mongoose::{
+    codegen::codegen,
+    loader::{load, load_project},
+    manifest::read_manifest,
+    writer::write,
+};
+
+fn run(code: &str) -> Value {
+    let store = Store::default();
+    let module = Module::new(&store, code).unwrap();
+    let import_object = imports! {};
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start.call(&[]).unwrap()[0].clone()
+}
+
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = env::temp_dir().join(format!("mongoose_{}", name));
+    let _ = fs::remove_dir_all(&directory);
+    for (path, source) in files {
+        let path = directory.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    directory
+}
+
+#[test]
+fn test_load_import() {
+    let directory = write_files(
+        "test_load_import",
+        &[
+            ("main.mon", "import math\n\ndef start(): math.square(5)\n"),
+            ("math.mon", "def square(x): x * x\n"),
+        ],
+    );
+    let ast = load(&directory.join("main.mon"), &[]);
+    let wasm = codegen(ast);
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (i64.const 5)
+    (call $math.square))
+
+  (func $math.square (param $x i64) (result i64)
+    (get_local $x)
+    (get_local $x)
+    i64.mul)
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(25));
+}
+
+#[test]
+fn test_load_from_import_on_search_path() {
+    let directory = write_files(
+        "test_load_from_import_on_search_path",
+        &[
+            (
+                "src/main.mon",
+                "from geometry.shapes import area\n\ndef start(): area(2, 3)\n",
+            ),
+            (
+                "lib/geometry/shapes.mon",
+                "import math as m\n\ndef area(w, h): m.square(w) * h\n",
+            ),
+            ("lib/math.mon", "def square(x): x * x\n"),
+        ],
+    );
+    let ast = load(&directory.join("src/main.mon"), &[directory.join("lib")]);
+    assert!(ast.top_level.contains_key("geometry.shapes.area"));
+    assert!(ast.top_level.contains_key("math.square"));
+    let code = write(codegen(ast));
+    assert_eq!(run(&code), Value::I64(12));
+}
+
+#[test]
+#[should_panic(expected = "Import cycle detected: a -> b -> a")]
+fn test_load_import_cycle() {
+    let directory = write_files(
+        "test_load_import_cycle",
+        &[
+            ("main.mon", "import a\n\ndef start(): a.f()\n"),
+            ("a.mon", "import b\n\ndef f(): b.g()\n"),
+            ("b.mon", "import a\n\ndef g(): a.f()\n"),
+        ],
+    );
+    load(&directory.join("main.mon"), &[]);
+}
+
+#[test]
+fn test_load_project_with_path_dependency() {
+    let directory = write_files(
+        "test_load_project_with_path_dependency",
+        &[
+            (
+                "app/mongoose.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n[dependencies]\nutils = { path = \"../utils\" }\n",
+            ),
+            (
+                "app/src/main.mon",
+                "from utils.math import square\n\ndef start(): square(4)\n",
+            ),
+            (
+                "utils/mongoose.toml",
+                "[package]\nname = \"utils\"\nversion = \"0.1.0\"\nsources = [\"lib\"]\n",
+            ),
+            (
+                "utils/lib/math.mon",
+                "import helpers\n\ndef square(x): helpers.times(x, x)\n",
+            ),
+            ("utils/lib/helpers.mon", "def times(x, y): x * y\n"),
+        ],
+    );
+    let directory = directory.join("app");
+    let manifest = read_manifest(&directory);
+    let ast = load_project(&directory, manifest);
+    assert!(ast.top_level.contains_key("utils.math.square"));
+    assert!(ast.top_level.contains_key("utils.helpers.times"));
+    let code = write(codegen(ast));
+    assert_eq!(run(&code), Value::I64(16));
+}
+
+#[test]
+#[should_panic(expected = "Dependency cycle detected: app -> utils -> app")]
+fn test_load_project_dependency_cycle() {
+    let directory = write_files(
+        "test_load_project_dependency_cycle",
+        &[
+            (
+                "app/mongoose.toml",
+                "[package]\nname = \"app\"\n\n[dependencies]\nutils = { path = \"../utils\" }\n",
+            ),
+            ("app/src/main.mon", "def start(): 0\n"),
+            (
+                "utils/mongoose.toml",
+                "[package]\nname = \"utils\"\n\n[dependencies]\napp = { path = \"../app\" }\n",
+            ),
+        ],
+    );
+    let directory = directory.join("app");
+    let manifest = read_manifest(&directory);
+    load_project(&directory, manifest);
+}
+
+#[test]
+#[should_panic(expected = "helper is defined in both main and tools")]
+fn test_load_project_duplicate_entry_definitions() {
+    let directory = write_files(
+        "test_load_project_duplicate_entry_definitions",
+        &[
+            (
+                "mongoose.toml",
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\nentries = [\"main\", \"tools\"]\n",
+            ),
+            ("src/main.mon", "def helper(): 1\n\ndef start(): helper()\n"),
+            (
+                "src/tools.mon",
+                "def helper(): 2\n\nexport def tool(): helper()\n",
+            ),
+        ],
+    );
+    load_project(&directory, read_manifest(&directory));
+}
+
+#[test]
+fn test_load_imported_globals() {
+    let directory = write_files(
+        "test_load_imported_globals",
+        &[
+            (
+                "main.mon",
+                "import config\nfrom config import SCALE\n\ndef start(): config.scaled(config.BASE) + SCALE\n",
+            ),
+            (
+                "config.mon",
+                "const BASE = 4\nconst SCALE = BASE * 10\n\ndef scaled(x): x * SCALE\n",
+            ),
+        ],
+    );
+    let ast = load(&directory.join("main.mon"), &[]);
+    assert!(ast.globals.name_to_global.contains_key("config.SCALE"));
+    let code = write(codegen(ast));
+    assert_eq!(run(&code), Value::I64(200));
+}
+
+#[test]
+fn test_load_prelude() {
+    let directory = write_files(
+        "test_load_prelude",
+        &[(
+            "main.mon",
+            r#"
+def checked_div(x, y):
+    if y == 0: Err(1) else: Ok(x / y)
+
+def find(x):
+    if x > 3: Some(x * 10) else: None
+
+def start():
+    total = unwrap_or(find(5), 0) + unwrap_or(find(1), 7)
+    match checked_div(total, 0):
+        Ok(value): value
+        Err(code): total + code
+"#,
+        )],
+    );
+    let ast = load(&directory.join("main.mon"), &[]);
+    assert_eq!(ast.enums.names, vec!["Option", "Result"]);
+    let wasm = codegen(ast);
+    assert!(wasm.warnings.is_empty());
+    let code = write(wasm);
+    assert_eq!(run(&code), Value::I64(58));
+}
+
+#[test]
+fn test_load_prelude_fold() {
+    let directory = write_files(
+        "test_load_prelude_fold",
+        &[(
+            "main.mon",
+            r#"
+def add(x, y): x + y
+
+def start():
+    scale = 3
+    total = [1, 2, 3] |> fold(0, add)
+    [4, 5] |> fold(total, fn(total, x): total + x * scale)
+"#,
+        )],
+    );
+    let ast = load(&directory.join("main.mon"), &[]);
+    let code = write(codegen(ast));
+    assert_eq!(run(&code), Value::I64(33));
+}
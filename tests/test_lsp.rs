@@ -0,0 +1,209 @@
+use std::{env, io::BufReader};
+
+use pretty_assertions::assert_eq;
+
+use mongoose::{
+    json::{self, Json},
+    lsp::{handle, read_message, server, write_message, Server},
+};
+
+const URI: &str = "file:///mongoose/lsp/main.mon";
+
+fn message(server: Server, method: &str, params: String) -> (Server, Vec<Json>) {
+    let message = format!(
+        r#"{{"jsonrpc": "2.0", "id": 1, "method": "{}", "params": {}}}"#,
+        method, params
+    );
+    handle(server, &json::parse(&message))
+}
+
+fn open(source: &str) -> (Server, Json) {
+    let cache = env::temp_dir().join("mongoose_test_lsp_cache");
+    let text = json::write(&Json::String(source.to_string()));
+    let params = format!(
+        r#"{{"textDocument": {{"uri": "{}", "text": {}}}}}"#,
+        URI, text
+    );
+    let (server, mut outputs) = message(server(vec![], cache), "textDocument/didOpen", params);
+    (server, outputs.remove(0))
+}
+
+fn change(server: Server, source: &str) -> (Server, Json) {
+    let text = json::write(&Json::String(source.to_string()));
+    let params = format!(
+        r#"{{"textDocument": {{"uri": "{}"}}, "contentChanges": [{{"text": {}}}]}}"#,
+        URI, text
+    );
+    let (server, mut outputs) = message(server, "textDocument/didChange", params);
+    (server, outputs.remove(0))
+}
+
+fn at(server: Server, method: &str, line: usize, character: usize) -> (Server, Json) {
+    let params = format!(
+        r#"{{"textDocument": {{"uri": "{}"}}, "position": {{"line": {}, "character": {}}}}}"#,
+        URI, line, character
+    );
+    let (server, mut outputs) = message(server, method, params);
+    (server, json::get(&outputs.remove(0), "result").clone())
+}
+
+fn start(json: &Json) -> (usize, usize) {
+    let start = json::path(json, &["range", "start"]);
+    (
+        json::number(json::get(start, "line")).unwrap(),
+        json::number(json::get(start, "character")).unwrap(),
+    )
+}
+
+fn diagnostics(notification: &Json) -> Vec<(usize, String)> {
+    match json::path(notification, &["params", "diagnostics"]) {
+        Json::Array(diagnostics) => diagnostics
+            .iter()
+            .map(|diagnostic| {
+                let message = json::string(json::get(diagnostic, "message")).unwrap();
+                (start(diagnostic).0, message.to_string())
+            })
+            .collect(),
+        other => panic!("expected diagnostics, found {:?}", other),
+    }
+}
+
+fn labels(completion: &Json) -> Vec<&str> {
+    match completion {
+        Json::Array(items) => items
+            .iter()
+            .map(|item| json::string(json::get(item, "label")).unwrap())
+            .collect(),
+        other => panic!("expected completion items, found {:?}", other),
+    }
+}
+
+const SOURCE: &str = r#"
+def square(x): x * x
+
+enum Shape:
+    Circle(radius)
+    Square(side)
+
+def start():
+    y = 2.5
+    z = square(3)
+    z
+"#;
+
+#[test]
+fn test_lsp_diagnostics() {
+    let (server, published) = open(SOURCE);
+    assert_eq!(
+        json::string(json::get(&published, "method")),
+        Some("textDocument/publishDiagnostics")
+    );
+    assert_eq!(
+        diagnostics(&published),
+        vec![(7, String::from("local y in start is never read"))]
+    );
+    let broken = SOURCE.replace("z = square(3)", "z = square(3");
+    let (server, published) = change(server, &broken);
+    assert_eq!(
+        diagnostics(&published),
+        vec![(7, String::from("syntax error"))]
+    );
+    let (server, published) = change(server, &SOURCE.replace("2.5", "2.5 $"));
+    assert_eq!(
+        diagnostics(&published),
+        vec![(0, String::from("syntax error"))]
+    );
+    let (_, published) = change(server, &SOURCE.replace("square(3)", "cube(3)"));
+    let reported = diagnostics(&published);
+    assert_eq!(reported.len(), 1);
+    assert_eq!(reported[0], (9, String::from("Unknown function cube")));
+}
+
+#[test]
+fn test_lsp_definition() {
+    let (server, _) = open(SOURCE);
+    let (server, definition) = at(server, "textDocument/definition", 10, 4);
+    assert_eq!(json::string(json::get(&definition, "uri")), Some(URI));
+    assert_eq!(start(&definition), (9, 4));
+    let (server, definition) = at(server, "textDocument/definition", 9, 10);
+    assert_eq!(start(&definition), (1, 4));
+    let (server, definition) = at(server, "textDocument/definition", 1, 19);
+    assert_eq!(start(&definition), (1, 11));
+    let (_, definition) = at(server, "textDocument/definition", 8, 0);
+    assert_eq!(definition, Json::Null);
+}
+
+#[test]
+fn test_lsp_hover() {
+    let (server, _) = open(SOURCE);
+    let value = |hover: &Json| {
+        json::string(json::path(hover, &["contents", "value"]))
+            .unwrap()
+            .to_string()
+    };
+    let (server, hover) = at(server, "textDocument/hover", 9, 10);
+    assert_eq!(value(&hover), "def square(x: i64) -> i64");
+    let (server, hover) = at(server, "textDocument/hover", 8, 4);
+    assert_eq!(value(&hover), "y: f64");
+    let (_, hover) = at(server, "textDocument/hover", 4, 5);
+    assert_eq!(value(&hover), "Circle(radius)");
+}
+
+#[test]
+fn test_lsp_completion() {
+    let (server, _) = open(SOURCE);
+    let (server, completion) = at(server, "textDocument/completion", 9, 8);
+    let names = labels(&completion);
+    for label in ["Circle", "Square", "square", "start", "y", "z"] {
+        assert!(names.contains(&label), "{} missing from {:?}", label, names);
+    }
+    let (_, completion) = at(server, "textDocument/completion", 8, 4);
+    assert!(!labels(&completion).contains(&"z"));
+}
+
+#[test]
+fn test_lsp_document_symbols() {
+    let (server, _) = open(SOURCE);
+    let params = format!(r#"{{"textDocument": {{"uri": "{}"}}}}"#, URI);
+    let (_, outputs) = message(server, "textDocument/documentSymbol", params);
+    let names = |symbols: &Json| match symbols {
+        Json::Array(symbols) => symbols
+            .iter()
+            .map(|symbol| json::string(json::get(symbol, "name")).unwrap().to_string())
+            .collect::<Vec<String>>(),
+        other => panic!("expected symbols, found {:?}", other),
+    };
+    let symbols = json::get(&outputs[0], "result");
+    assert_eq!(names(symbols), vec!["square", "Shape", "start"]);
+    match symbols {
+        Json::Array(symbols) => {
+            assert_eq!(
+                names(json::get(&symbols[1], "children")),
+                vec!["Circle", "Square"]
+            );
+            assert_eq!(start(&symbols[2]), (7, 0));
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_lsp_messages() {
+    let message = json::parse(r#"{"jsonrpc": "2.0", "id": "a\"b", "method": "shutdown"}"#);
+    let mut output = vec![];
+    write_message(&mut output, &message);
+    let framed = String::from_utf8(output.clone()).unwrap();
+    assert!(framed.starts_with("Content-Length: "), "{}", framed);
+    let mut reader = BufReader::new(&output[..]);
+    let read = read_message(&mut reader).unwrap();
+    assert_eq!(read, message);
+    assert_eq!(read_message(&mut reader), None);
+    let (server, outputs) = handle(server(vec![], env::temp_dir()), &read);
+    assert_eq!(
+        json::get(&outputs[0], "id"),
+        &Json::String(String::from("a\"b"))
+    );
+    let (server, outputs) = handle(server, &json::parse(r#"{"method": "exit"}"#));
+    assert!(server.exited);
+    assert!(outputs.is_empty());
+}
@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use pretty_assertions::assert_eq;
+
+use mongoose::manifest::{parse_manifest, Dependencies, Manifest};
+
+#[test]
+fn test_parse_manifest() {
+    let source = r#"
+# shared geometry helpers
+[package]
+name = "geometry"
+version = "0.1.0"
+sources = ["src", "lib"]
+entries = ["main", "tools"]
+
+[dependencies]
+utils = { path = "../utils" } # local only
+"#;
+    assert_eq!(
+        parse_manifest(source),
+        Manifest {
+            name: String::from("geometry"),
+            version: String::from("0.1.0"),
+            sources: vec![PathBuf::from("src"), PathBuf::from("lib")],
+            entries: vec![String::from("main"), String::from("tools")],
+            dependencies: Dependencies {
+                names: vec![String::from("utils")],
+                paths: vec![PathBuf::from("../utils")],
+            },
+        }
+    );
+}
+
+#[test]
+fn test_parse_manifest_defaults() {
+    let source = r#"
+[package]
+name = "app"
+version = "1.2.3"
+"#;
+    assert_eq!(
+        parse_manifest(source),
+        Manifest {
+            name: String::from("app"),
+            version: String::from("1.2.3"),
+            sources: vec![PathBuf::from("src")],
+            entries: vec![String::from("main")],
+            dependencies: Dependencies {
+                names: vec![],
+                paths: vec![],
+            },
+        }
+    );
+}
@@ -0,0 +1,385 @@
+use pretty_assertions::assert_eq;
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use mongoose::{
+    codegen::codegen, optimizer::optimize, parser::parse, tokenizer::tokenize, writer::write,
+};
+
+fn run(code: &str) -> Value {
+    let store = Store::default();
+    let module = Module::new(&store, code).unwrap();
+    let import_object = imports! {};
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start.call(&[]).unwrap()[0].clone()
+}
+
+#[test]
+fn test_optimize_constant_folding() {
+    let tokens = tokenize("def start(): (5 + 10) * 2 - 8 / 4");
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (i64.const 28))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(28));
+}
+
+#[test]
+fn test_optimize_identities() {
+    let source = r#"
+@noinline
+def f(x): (x * 1 + 0) << 0
+@noinline
+def g(x): 0 + 1 * x
+def start(): f(3) + g(4)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $f (param $x i64) (result i64)
+    (get_local $x))"#
+    ));
+    assert!(code.contains(
+        r#"
+  (func $g (param $x i64) (result i64)
+    (get_local $x))"#
+    ));
+    assert_eq!(run(&code), Value::I64(7));
+}
+
+#[test]
+fn test_optimize_strength_reduction() {
+    let source = r#"
+@noinline
+def f(x): x * 8
+@noinline
+def g(x): x / 4
+def start(): f(5) + g(0 - 7) + g(9)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $f (param $x i64) (result i64)
+    (get_local $x)
+    (i64.const 3)
+    i64.shl)"#
+    ));
+    assert!(code.contains(
+        r#"
+  (func $g (param $x i64) (result i64)
+    (get_local $x)
+    (get_local $x)
+    (i64.const 63)
+    i64.shr_s
+    (i64.const 3)
+    i64.and
+    i64.add
+    (i64.const 2)
+    i64.shr_s)"#
+    ));
+    assert_eq!(run(&code), Value::I64(41));
+}
+
+#[test]
+fn test_optimize_constant_propagation() {
+    let source = r#"
+def start():
+    width = 6
+    height = width + 1
+    total = 0
+    for i in range(height):
+        total = total + width
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert!(!code.contains("(get_local $width)"));
+    assert!(!code.contains("(get_local $height)"));
+    assert!(code.contains("(i64.const 7)"));
+    assert_eq!(run(&code), Value::I64(42));
+}
+
+#[test]
+fn test_optimize_keeps_conditional_assignment() {
+    let source = r#"
+@noinline
+def pick(flag):
+    value = 0
+    if flag == 1:
+        value = 5
+    value
+def start(): pick(1) * 10 + pick(0)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert!(code.contains("(call $pick)"));
+    assert!(code.contains("    if\n"));
+    assert_eq!(run(&code), Value::I64(50));
+}
+
+#[test]
+fn test_optimize_constant_branches() {
+    let source = r#"
+def start():
+    total = 1
+    if 0:
+        total = total + 100
+    if 2 > 1:
+        total = total + 10
+    else:
+        total = total + 1000
+    while 1 > 2:
+        total = 0
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (i64.const 11))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(11));
+}
+
+#[test]
+fn test_optimize_dead_stores() {
+    let source = r#"
+@noinline
+def square(x): x * x
+
+def start():
+    unused = 42
+    ignored = square(3)
+    result = 5
+    if result > 4:
+        return result + 1
+        result = 0
+    result"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $start (result i64)
+    (i64.const 3)
+    (call $square)
+    drop
+    (i64.const 6)
+    return)"#
+    ));
+    assert_eq!(run(&code), Value::I64(6));
+}
+
+#[test]
+fn test_optimize_inline_pipeline() {
+    let source = r#"
+def square(x): x * x
+
+def min(x, y): if x < y: x else: y
+
+def line(m, x, b): m * x + b
+
+def start():
+    5
+    |> square
+    |> min(20)
+    |> line(10, _, 3)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (i64.const 203))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(203));
+}
+
+#[test]
+fn test_optimize_inline_attributes() {
+    let source = r#"
+@noinline
+def square(x): x * x
+
+@inline
+def total(a, b, c, d):
+    sum = a + b + c + d
+    product = a * b * c * d
+    if sum > product: sum - product else: product - sum
+
+def countdown(n): if n == 0: 0 else: 1 + countdown(n - 1)
+
+def start(x): square(x) + total(x, 2, 3, 4) + countdown(x)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    assert_eq!(
+        wasm.functions
+            .iter()
+            .map(|func| func.symbols[func.name].as_str())
+            .collect::<Vec<&str>>(),
+        vec!["start", "square", "countdown"]
+    );
+    let code = write(wasm);
+    assert!(code.contains("(call $square)"));
+    assert!(code.contains("(call $countdown)"));
+    assert!(!code.contains("(call $total)"));
+}
+
+#[test]
+fn test_optimize_coalesce_locals() {
+    let source = r#"
+@noinline
+def f(x):
+    a = x * 3
+    b = a + x
+    c = b * b
+    d = c - x
+    d
+
+def start(): f(2)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert!(code.contains(
+        r#"
+  (func $f (param $x i64) (result i64)
+    (local $.value.2 i64)
+    (get_local $x)
+    (i64.const 3)
+    i64.mul
+    (get_local $x)
+    i64.add
+    (tee_local $.value.2)
+    (get_local $.value.2)
+    i64.mul
+    (get_local $x)
+    i64.sub)"#
+    ));
+    assert_eq!(run(&code), Value::I64(62));
+}
+
+#[test]
+fn test_optimize_coalesce_loop_locals() {
+    let source = r#"
+def start():
+    a = 0
+    b = 1
+    i = 0
+    while i < 10:
+        t = a + b
+        a = b
+        b = t
+        i = i + 1
+    a"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let wasm = optimize(codegen(ast));
+    let code = write(wasm);
+    assert_eq!(code.matches("(local ").count(), 3);
+    assert_eq!(run(&code), Value::I64(55));
+}
+
+#[test]
+fn test_optimize_inline_resets_locals() {
+    let source = r#"
+def f(c):
+    if c == 1:
+        y = 7
+    y
+
+def start():
+    total = 0
+    for i in range(3):
+        total = total * 10 + f(i % 2)
+    total"#;
+    let unoptimized = write(codegen(parse(tokenize(source))));
+    let optimized = write(optimize(codegen(parse(tokenize(source)))));
+    assert!(!optimized.contains("(call $f)"));
+    assert_eq!(run(&unoptimized), Value::I64(70));
+    assert_eq!(run(&optimized), Value::I64(70));
+}
+
+#[test]
+fn test_optimize_loop_exits() {
+    let cases = [
+        (
+            r#"
+def f(n):
+    s = 0
+    for i in range(0, n):
+        s = s + i
+        if s > 100:
+            return s
+    s
+
+def start(): f(50)"#,
+            105,
+        ),
+        (
+            r#"
+def start():
+    i = 0
+    while i < 10:
+        i = i + 1
+        if i == 7:
+            break
+    i"#,
+            7,
+        ),
+        (
+            r#"
+def start():
+    i = 0
+    total = 0
+    while i < 10:
+        i = i + 1
+        if i % 2 == 0:
+            continue
+        if i == 9:
+            break
+        total = total + i
+    total * 100 + i"#,
+            1609,
+        ),
+    ];
+    for (source, expected) in cases {
+        let unoptimized = write(codegen(parse(tokenize(source))));
+        let optimized = write(optimize(codegen(parse(tokenize(source)))));
+        assert_eq!(run(&unoptimized), Value::I64(expected), "{}", source);
+        assert_eq!(run(&optimized), Value::I64(expected), "{}", source);
+    }
+}
@@ -1,7 +1,7 @@
 use pretty_assertions::assert_eq;
 
 use mongoose::{
-    parser::{parse, Ast, BinaryOp, Function, Kind},
+    parser::{parse, Ast, BinaryOp, Function, GlobalKind, Imports, Kind, Pattern},
     tokenizer::tokenize,
 };
 
@@ -17,6 +17,13 @@ fn ast_string_int(mut output: String, func: &Function, expression: usize) -> Str
     output
 }
 
+fn ast_string_float(mut output: String, func: &Function, expression: usize) -> String {
+    output.push_str("Float(");
+    output.push_str(&func.floats[func.indices[expression]]);
+    output.push_str("),\n");
+    output
+}
+
 fn ast_string_symbol(mut output: String, func: &Function, expression: usize) -> String {
     output.push_str("Symbol(");
     output.push_str(&func.symbols[func.indices[expression]]);
@@ -173,6 +180,169 @@ fn ast_string_while(
     output
 }
 
+fn ast_string_for(mut output: String, func: &Function, expression: usize, indent: usize) -> String {
+    output.push_str("For(\n");
+    let mut output = write_indent(output, indent);
+    output.push_str("variable=");
+    let index = func.indices[expression];
+    output.push_str(&func.symbols[func.indices[func.fors.variables[index]]]);
+    output.push_str(",\n");
+    let mut output = write_indent(output, indent);
+    output.push_str("iterable=");
+    let output = ast_string_expression(output, func, func.fors.iterables[index], indent);
+    let mut output = write_indent(output, indent);
+    output.push_str("body=[\n");
+    let next_indent = indent + INDENT;
+    let output = func.fors.bodies[index]
+        .iter()
+        .fold(output, |output, &parameter| {
+            let output = write_indent(output, next_indent);
+            ast_string_expression(output, func, parameter, next_indent)
+        });
+    let mut output = write_indent(output, indent);
+    output.push_str("]\n");
+    let mut output = write_indent(output, indent - INDENT);
+    output.push_str("),\n");
+    output
+}
+
+fn ast_string_array(
+    mut output: String,
+    func: &Function,
+    expression: usize,
+    indent: usize,
+) -> String {
+    output.push_str("Array([\n");
+    let index = func.indices[expression];
+    let output = func.arrays[index].iter().fold(output, |output, &element| {
+        let output = write_indent(output, indent);
+        ast_string_expression(output, func, element, indent)
+    });
+    let mut output = write_indent(output, indent - INDENT);
+    output.push_str("]),\n");
+    output
+}
+
+fn ast_string_pattern(mut output: String, func: &Function, pattern: Pattern) -> String {
+    match pattern {
+        Pattern::Int(int) => {
+            output.push_str("Int(");
+            output.push_str(&func.ints[int]);
+            output.push(')');
+        }
+        Pattern::Range(low, high) => {
+            output.push_str("Range(");
+            output.push_str(&func.ints[low]);
+            output.push_str(", ");
+            output.push_str(&func.ints[high]);
+            output.push(')');
+        }
+        Pattern::Wildcard => output.push_str("Wildcard"),
+        Pattern::Variant(variant) => output.push_str(&func.symbols[variant]),
+    }
+    output.push_str(",\n");
+    output
+}
+
+fn ast_string_match(
+    mut output: String,
+    func: &Function,
+    expression: usize,
+    indent: usize,
+) -> String {
+    output.push_str("Match(\n");
+    let mut output = write_indent(output, indent);
+    output.push_str("value=");
+    let index = func.indices[expression];
+    let output = ast_string_expression(output, func, func.matches.values[index], indent);
+    let mut output = write_indent(output, indent);
+    output.push_str("arms=[\n");
+    let arm_indent = indent + INDENT;
+    let body_indent = arm_indent + INDENT;
+    let output = func.matches.patterns[index]
+        .iter()
+        .zip(func.matches.guards[index].iter())
+        .zip(func.matches.bindings[index].iter())
+        .zip(func.matches.bodies[index].iter())
+        .fold(output, |output, (((&pattern, guard), bindings), body)| {
+            let mut output = write_indent(output, arm_indent);
+            output.push_str("Arm(\n");
+            let mut output = write_indent(output, body_indent);
+            output.push_str("pattern=");
+            let output = ast_string_pattern(output, func, pattern);
+            let output = match bindings.len() {
+                0 => output,
+                _ => {
+                    let mut output = write_indent(output, body_indent);
+                    output.push_str("bindings=[");
+                    let bindings = bindings
+                        .iter()
+                        .map(|&binding| func.symbols[binding].as_str())
+                        .collect::<Vec<&str>>();
+                    output.push_str(&bindings.join(", "));
+                    output.push_str("],\n");
+                    output
+                }
+            };
+            let output = match guard {
+                Some(guard) => {
+                    let mut output = write_indent(output, body_indent);
+                    output.push_str("guard=");
+                    ast_string_expression(output, func, *guard, body_indent)
+                }
+                None => output,
+            };
+            let mut output = write_indent(output, body_indent);
+            output.push_str("body=[\n");
+            let output = body.iter().fold(output, |output, &expression| {
+                let output = write_indent(output, body_indent + INDENT);
+                ast_string_expression(output, func, expression, body_indent + INDENT)
+            });
+            let mut output = write_indent(output, body_indent);
+            output.push_str("]\n");
+            let mut output = write_indent(output, arm_indent);
+            output.push_str("),\n");
+            output
+        });
+    let mut output = write_indent(output, indent);
+    output.push_str("]\n");
+    let mut output = write_indent(output, indent - INDENT);
+    output.push_str("),\n");
+    output
+}
+
+fn ast_string_lambda(
+    mut output: String,
+    func: &Function,
+    expression: usize,
+    indent: usize,
+) -> String {
+    output.push_str("Lambda(\n");
+    let mut output = write_indent(output, indent);
+    let index = func.indices[expression];
+    let parameters = func.lambdas.parameters[index]
+        .iter()
+        .map(|&parameter| func.symbols[parameter].as_str())
+        .collect::<Vec<&str>>();
+    output.push_str("parameters=[");
+    output.push_str(&parameters.join(", "));
+    output.push_str("],\n");
+    let mut output = write_indent(output, indent);
+    output.push_str("body=[\n");
+    let next_indent = indent + INDENT;
+    let output = func.lambdas.bodies[index]
+        .iter()
+        .fold(output, |output, &expression| {
+            let output = write_indent(output, next_indent);
+            ast_string_expression(output, func, expression, next_indent)
+        });
+    let mut output = write_indent(output, indent);
+    output.push_str("]\n");
+    let mut output = write_indent(output, indent - INDENT);
+    output.push_str("),\n");
+    output
+}
+
 fn ast_string_grouping(
     mut output: String,
     func: &Function,
@@ -188,6 +358,21 @@ fn ast_string_grouping(
     output
 }
 
+fn ast_string_return(
+    mut output: String,
+    func: &Function,
+    expression: usize,
+    indent: usize,
+) -> String {
+    output.push_str("Return(\n");
+    let output = write_indent(output, indent);
+    let index = func.indices[expression];
+    let output = ast_string_expression(output, func, func.returns[index], indent);
+    let mut output = write_indent(output, indent - INDENT);
+    output.push_str("),\n");
+    output
+}
+
 fn ast_string_expression(
     output: String,
     func: &Function,
@@ -196,13 +381,29 @@ fn ast_string_expression(
 ) -> String {
     match func.kinds[expression] {
         Kind::Int => ast_string_int(output, func, expression),
+        Kind::Float => ast_string_float(output, func, expression),
         Kind::Symbol => ast_string_symbol(output, func, expression),
         Kind::BinaryOp => ast_string_binary_op(output, func, expression, indent + INDENT),
         Kind::Assign => ast_string_assignment(output, func, expression, indent + INDENT),
         Kind::FunctionCall => ast_string_function_call(output, func, expression, indent + INDENT),
         Kind::If => ast_string_if(output, func, expression, indent + INDENT),
         Kind::While => ast_string_while(output, func, expression, indent + INDENT),
+        Kind::For => ast_string_for(output, func, expression, indent + INDENT),
+        Kind::Array => ast_string_array(output, func, expression, indent + INDENT),
+        Kind::Match => ast_string_match(output, func, expression, indent + INDENT),
+        Kind::Lambda => ast_string_lambda(output, func, expression, indent + INDENT),
         Kind::Grouping => ast_string_grouping(output, func, expression, indent + INDENT),
+        Kind::Break => {
+            let mut output = output;
+            output.push_str("Break,\n");
+            output
+        }
+        Kind::Continue => {
+            let mut output = output;
+            output.push_str("Continue,\n");
+            output
+        }
+        Kind::Return => ast_string_return(output, func, expression, indent + INDENT),
     }
 }
 
@@ -210,6 +411,10 @@ fn ast_string_function(mut output: String, func: &Function) -> String {
     output.push_str("    Function(\n");
     output.push_str("        name=");
     output.push_str(&func.symbols[func.name]);
+    if let Some(export) = func.export {
+        output.push_str(",\n        export=");
+        output.push_str(&func.symbols[export]);
+    }
     output.push_str(",\n        arguments=[\n");
     let mut output = func.arguments.iter().fold(output, |mut output, &argument| {
         output.push_str("            ");
@@ -970,3 +1175,703 @@ Ast([
 "#
     );
 }
+
+#[test]
+fn test_parse_export() {
+    let source = r#"
+export def square(x): x * x
+
+export def area(w, h) as rect_area: w * h"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=square,
+        export=square,
+        arguments=[
+            x,
+        ],
+        body=[
+            BinaryOp(
+                op=Multiply,
+                left=Symbol(x),
+                right=Symbol(x),
+            ),
+        ]
+    ),
+    Function(
+        name=area,
+        export=rect_area,
+        arguments=[
+            w,
+            h,
+        ],
+        body=[
+            BinaryOp(
+                op=Multiply,
+                left=Symbol(w),
+                right=Symbol(h),
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_imports() {
+    let source = r#"
+import math
+import geometry.shapes as shapes
+from math import square, cube
+
+def start(): square(2)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast.imports,
+        Imports {
+            modules: vec![
+                String::from("math"),
+                String::from("geometry.shapes"),
+                String::from("math"),
+            ],
+            aliases: vec![
+                Some(String::from("math")),
+                Some(String::from("shapes")),
+                None,
+            ],
+            names: vec![
+                vec![],
+                vec![],
+                vec![String::from("square"), String::from("cube")],
+            ],
+        }
+    );
+    assert_eq!(ast.functions.len(), 1);
+    assert_eq!(ast.top_level.get("start"), Some(&0));
+}
+
+#[test]
+fn test_parse_globals() {
+    let source = r#"
+const MAX = 10 * 100
+var counter = 0
+
+def start(): counter + MAX"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(ast.globals.kinds, vec![GlobalKind::Const, GlobalKind::Var]);
+    assert_eq!(ast.globals.name_to_global.get("MAX"), Some(&0));
+    assert_eq!(ast.globals.name_to_global.get("counter"), Some(&1));
+    let max = &ast.globals.values[0];
+    let expression = max.expressions[0];
+    assert_eq!(
+        remove_whitespace(&ast_string_expression(String::new(), max, expression, 0)),
+        "BinaryOp(op=Multiply,left=Int(10),right=Int(100),),"
+    );
+    assert_eq!(ast.functions.len(), 1);
+}
+
+#[test]
+fn test_parse_control_flow() {
+    let source = r#"
+def start():
+    while 1:
+        if 0: continue else: break
+    return 5"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=start,
+        arguments=[
+        ],
+        body=[
+            While(
+                condition=Int(1),
+                body=[
+                    If(
+                        condition=Int(0),
+                        then=[
+                            Continue,
+                        ],
+                        else=[
+                            Break,
+                        ]
+                    ),
+                ]
+            ),
+            Return(
+                Int(5),
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_elif() {
+    let source = r#"
+def sign(x):
+    if x < 0:
+        0 - 1
+    elif x > 0:
+        1
+    else:
+        0"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=sign,
+        arguments=[
+            x,
+        ],
+        body=[
+            If(
+                condition=BinaryOp(
+                    op=LessThan,
+                    left=Symbol(x),
+                    right=Int(0),
+                ),
+                then=[
+                    BinaryOp(
+                        op=Subtract,
+                        left=Int(0),
+                        right=Int(1),
+                    ),
+                ],
+                else=[
+                    If(
+                        condition=BinaryOp(
+                            op=GreaterThan,
+                            left=Symbol(x),
+                            right=Int(0),
+                        ),
+                        then=[
+                            Int(1),
+                        ],
+                        else=[
+                            Int(0),
+                        ]
+                    ),
+                ]
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_if_without_else() {
+    let source = r#"
+def start():
+    x = 0
+    if x == 0:
+        if x > 5:
+            x = 1
+    else:
+        x = 2
+    if x == 2: x = 3
+    x"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=start,
+        arguments=[
+        ],
+        body=[
+            Assign(
+                name=x,
+                value=Int(0),
+            ),
+            If(
+                condition=BinaryOp(
+                    op=Equal,
+                    left=Symbol(x),
+                    right=Int(0),
+                ),
+                then=[
+                    If(
+                        condition=BinaryOp(
+                            op=GreaterThan,
+                            left=Symbol(x),
+                            right=Int(5),
+                        ),
+                        then=[
+                            Assign(
+                                name=x,
+                                value=Int(1),
+                            ),
+                        ],
+                        else=[
+                        ]
+                    ),
+                ],
+                else=[
+                    Assign(
+                        name=x,
+                        value=Int(2),
+                    ),
+                ]
+            ),
+            If(
+                condition=BinaryOp(
+                    op=Equal,
+                    left=Symbol(x),
+                    right=Int(2),
+                ),
+                then=[
+                    Assign(
+                        name=x,
+                        value=Int(3),
+                    ),
+                ],
+                else=[
+                ]
+            ),
+            Symbol(x),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_for() {
+    let source = r#"
+def start():
+    total = 0
+    for i in range(0, 10):
+        total = total + i
+    for x in [1, 2]: total = total + x
+    total"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=start,
+        arguments=[
+        ],
+        body=[
+            Assign(
+                name=total,
+                value=Int(0),
+            ),
+            For(
+                variable=i,
+                iterable=FunctionCall(
+                    name=range,
+                    parameters=[
+                        Int(0),
+                        Int(10),
+                    ]
+                ),
+                body=[
+                    Assign(
+                        name=total,
+                        value=BinaryOp(
+                            op=Add,
+                            left=Symbol(total),
+                            right=Symbol(i),
+                        ),
+                    ),
+                ]
+            ),
+            For(
+                variable=x,
+                iterable=Array([
+                    Int(1),
+                    Int(2),
+                ]),
+                body=[
+                    Assign(
+                        name=total,
+                        value=BinaryOp(
+                            op=Add,
+                            left=Symbol(total),
+                            right=Symbol(x),
+                        ),
+                    ),
+                ]
+            ),
+            Symbol(total),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_match() {
+    let source = r#"
+def classify(x):
+    match x:
+        0: 1
+        1..10: 2
+        _ if x > 100:
+            y = x * 2
+            y
+        _: 4"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=classify,
+        arguments=[
+            x,
+        ],
+        body=[
+            Match(
+                value=Symbol(x),
+                arms=[
+                    Arm(
+                        pattern=Int(0),
+                        body=[
+                            Int(1),
+                        ]
+                    ),
+                    Arm(
+                        pattern=Range(1, 10),
+                        body=[
+                            Int(2),
+                        ]
+                    ),
+                    Arm(
+                        pattern=Wildcard,
+                        guard=BinaryOp(
+                            op=GreaterThan,
+                            left=Symbol(x),
+                            right=Int(100),
+                        ),
+                        body=[
+                            Assign(
+                                name=y,
+                                value=BinaryOp(
+                                    op=Multiply,
+                                    left=Symbol(x),
+                                    right=Int(2),
+                                ),
+                            ),
+                            Symbol(y),
+                        ]
+                    ),
+                    Arm(
+                        pattern=Wildcard,
+                        body=[
+                            Int(4),
+                        ]
+                    ),
+                ]
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_enum() {
+    let source = r#"
+enum Shape: Circle(r), Rect(w, h)
+
+enum Light:
+    Red
+    Green
+
+def area(shape):
+    match shape:
+        Circle(r): 3 * r * r
+        Rect(w, _): w"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(ast.enums.names, vec!["Shape", "Light"]);
+    assert_eq!(
+        ast.enums.variants,
+        vec![vec!["Circle", "Rect"], vec!["Red", "Green"]]
+    );
+    assert_eq!(
+        ast.enums.fields,
+        vec![
+            vec![
+                vec!["r".to_string()],
+                vec!["w".to_string(), "h".to_string()]
+            ],
+            vec![vec![], vec![]]
+        ]
+    );
+    assert_eq!(ast.enums.name_to_enum.get("Light"), Some(&1));
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=area,
+        arguments=[
+            shape,
+        ],
+        body=[
+            Match(
+                value=Symbol(shape),
+                arms=[
+                    Arm(
+                        pattern=Circle,
+                        bindings=[r],
+                        body=[
+                            BinaryOp(
+                                op=Multiply,
+                                left=Int(3),
+                                right=BinaryOp(
+                                    op=Multiply,
+                                    left=Symbol(r),
+                                    right=Symbol(r),
+                                ),
+                            ),
+                        ]
+                    ),
+                    Arm(
+                        pattern=Rect,
+                        bindings=[w, _],
+                        body=[
+                            Symbol(w),
+                        ]
+                    ),
+                ]
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_lambda() {
+    let source = r#"
+def start():
+    offset = 10
+    add = fn(x, y): x + y + offset
+    twice = fn(f, x):
+        y = f(x)
+        f(y)
+    twice(fn(x): x * 2, 3) |> add(1)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=start,
+        arguments=[
+        ],
+        body=[
+            Assign(
+                name=offset,
+                value=Int(10),
+            ),
+            Assign(
+                name=add,
+                value=Lambda(
+                    parameters=[x, y],
+                    body=[
+                        BinaryOp(
+                            op=Add,
+                            left=Symbol(x),
+                            right=BinaryOp(
+                                op=Add,
+                                left=Symbol(y),
+                                right=Symbol(offset),
+                            ),
+                        ),
+                    ]
+                ),
+            ),
+            Assign(
+                name=twice,
+                value=Lambda(
+                    parameters=[f, x],
+                    body=[
+                        Assign(
+                            name=y,
+                            value=FunctionCall(
+                                name=f,
+                                parameters=[
+                                    Symbol(x),
+                                ]
+                            ),
+                        ),
+                        FunctionCall(
+                            name=f,
+                            parameters=[
+                                Symbol(y),
+                            ]
+                        ),
+                    ]
+                ),
+            ),
+            FunctionCall(
+                name=add,
+                parameters=[
+                    FunctionCall(
+                        name=twice,
+                        parameters=[
+                            Lambda(
+                                parameters=[x],
+                                body=[
+                                    BinaryOp(
+                                        op=Multiply,
+                                        left=Symbol(x),
+                                        right=Int(2),
+                                    ),
+                                ]
+                            ),
+                            Int(3),
+                        ]
+                    ),
+                    Int(1),
+                ]
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_generic_function() {
+    let source = r#"
+def max[T](a: T, b: T) -> T:
+    if a > b: a else: b
+
+def scale(x: f64, factor) -> f64: x * 2.5"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let symbol = |func: &Function, symbol: &usize| func.symbols[*symbol].clone();
+    let max = &ast.functions[0];
+    assert_eq!(
+        max.type_parameters
+            .iter()
+            .map(|type_parameter| symbol(max, type_parameter))
+            .collect::<Vec<String>>(),
+        vec!["T"]
+    );
+    assert_eq!(
+        max.argument_types
+            .iter()
+            .map(|argument_type| argument_type.as_ref().map(|t| symbol(max, t)))
+            .collect::<Vec<Option<String>>>(),
+        vec![Some("T".to_string()), Some("T".to_string())]
+    );
+    assert_eq!(
+        max.return_type.as_ref().map(|t| symbol(max, t)),
+        Some("T".to_string())
+    );
+    let scale = &ast.functions[1];
+    assert!(scale.type_parameters.is_empty());
+    assert_eq!(
+        scale
+            .argument_types
+            .iter()
+            .map(|argument_type| argument_type.as_ref().map(|t| symbol(scale, t)))
+            .collect::<Vec<Option<String>>>(),
+        vec![Some("f64".to_string()), None]
+    );
+    assert_eq!(
+        ast_string(&ast),
+        r#"
+Ast([
+    Function(
+        name=max,
+        arguments=[
+            a,
+            b,
+        ],
+        body=[
+            If(
+                condition=BinaryOp(
+                    op=GreaterThan,
+                    left=Symbol(a),
+                    right=Symbol(b),
+                ),
+                then=[
+                    Symbol(a),
+                ],
+                else=[
+                    Symbol(b),
+                ]
+            ),
+        ]
+    ),
+    Function(
+        name=scale,
+        arguments=[
+            x,
+            factor,
+        ],
+        body=[
+            BinaryOp(
+                op=Multiply,
+                left=Symbol(x),
+                right=Float(2.5),
+            ),
+        ]
+    ),
+])
+"#
+    );
+}
+
+#[test]
+fn test_parse_inline_attributes() {
+    let source = r#"
+@inline
+def square(x): x * x
+
+@noinline
+export def cube(x): x * x * x
+
+def start(): square(3)"#;
+    let tokens = tokenize(source);
+    let ast = parse(tokens);
+    let inline = |name: &str| ast.functions[ast.top_level[name]].inline;
+    assert_eq!(inline("square"), Some(true));
+    assert_eq!(inline("cube"), Some(false));
+    assert_eq!(inline("start"), None);
+    assert!(ast.functions[ast.top_level["cube"]].export.is_some());
+}
+
+#[test]
+#[should_panic(expected = "Unknown attribute @fast")]
+fn test_parse_unknown_attribute() {
+    parse(tokenize("@fast\ndef start(): 0"));
+}
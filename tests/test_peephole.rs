@@ -0,0 +1,114 @@
+use pretty_assertions::assert_eq;
+use wasmer::{imports, Instance, Module, Store, Value};
+
+use mongoose::{
+    codegen::{codegen, Wasm},
+    parser::parse,
+    peephole::peephole,
+    tokenizer::tokenize,
+    writer::write,
+};
+
+fn run(code: &str) -> Value {
+    let store = Store::default();
+    let module = Module::new(&store, code).unwrap();
+    let import_object = imports! {};
+    let instance = Instance::new(&module, &import_object).unwrap();
+    let start = instance.exports.get_function("_start").unwrap();
+    start.call(&[]).unwrap()[0].clone()
+}
+
+fn compile(source: &str) -> String {
+    let wasm = codegen(parse(tokenize(source)));
+    let functions = wasm.functions.into_iter().map(peephole).collect();
+    write(Wasm { functions, ..wasm })
+}
+
+#[test]
+fn test_peephole_inverted_comparison() {
+    let source = r#"
+def start():
+    i = 0
+    while i < 10:
+        i = i + 1
+    i"#;
+    let code = compile(source);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (local $i i64)
+    (i64.const 0)
+    (set_local $i)
+    block $.label.0
+    loop $.label.1
+    (get_local $i)
+    (i64.const 10)
+    i64.ge_s
+    br_if $.label.0
+    (get_local $i)
+    (i64.const 1)
+    i64.add
+    (set_local $i)
+    br $.label.1
+    end $.label.1
+    end $.label.0
+    (get_local $i))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(10));
+}
+
+#[test]
+fn test_peephole_equals_zero() {
+    let source = r#"
+def start():
+    n = 5
+    total = 0
+    while n != 0:
+        total = total + n
+        n = n - 1
+    total"#;
+    let code = compile(source);
+    assert!(code.contains(
+        r#"
+    (get_local $n)
+    i64.eqz
+    br_if $.label.0"#
+    ));
+    assert!(!code.contains("i32.eqz"));
+    assert_eq!(run(&code), Value::I64(15));
+}
+
+#[test]
+fn test_peephole_locals() {
+    let source = r#"
+def start():
+    x = 5
+    x = x
+    y = x * 2
+    y"#;
+    let code = compile(source);
+    assert_eq!(
+        code,
+        r#"
+(module
+
+  (func $start (result i64)
+    (local $x i64)
+    (local $y i64)
+    (i64.const 5)
+    (tee_local $x)
+    (i64.const 2)
+    i64.mul
+    (tee_local $y))
+
+  (export "_start" (func $start)))
+"#
+    );
+    assert_eq!(run(&code), Value::I64(10));
+}
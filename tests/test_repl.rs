@@ -0,0 +1,105 @@
+use pretty_assertions::assert_eq;
+
+use mongoose::repl::{evaluate, session, Session};
+
+fn evaluate_all(inputs: &[&str]) -> (Session, Vec<String>) {
+    inputs.iter().fold(
+        (session(vec![]), vec![]),
+        |(session, mut outputs), input| {
+            let (session, output) = evaluate(session, input);
+            outputs.push(output);
+            (session, outputs)
+        },
+    )
+}
+
+#[test]
+fn test_repl_expressions_and_definitions() {
+    let (session, outputs) = evaluate_all(&[
+        "1 + 2 * 3",
+        "def square(x): x * x",
+        "square(4)",
+        "def square(x): x * x * x",
+        "square(4)",
+        "enum Shape:\n    Circle(r)\n    Point",
+    ]);
+    assert_eq!(
+        outputs,
+        vec![
+            "I64(7)",
+            "square",
+            "I64(16)",
+            "square",
+            "I64(64)",
+            "Circle, Point, Shape"
+        ]
+    );
+    assert_eq!(
+        session.definitions,
+        vec![
+            "def square(x): x * x * x",
+            "enum Shape:\n    Circle(r)\n    Point"
+        ]
+    );
+}
+
+#[test]
+fn test_repl_locals_carry_between_entries() {
+    let (session, outputs) = evaluate_all(&[
+        "x = 5",
+        "y = x * 2",
+        "def add(a, b): a + b",
+        "add(x, y)",
+        "x |> add(y) |> add(1)",
+    ]);
+    assert_eq!(
+        outputs,
+        vec!["I64(5)", "I64(10)", "add", "I64(15)", "I64(16)"]
+    );
+    assert_eq!(session.locals, vec!["x = 5", "y = x * 2"]);
+}
+
+#[test]
+fn test_repl_locals_changed_inside_loops() {
+    let (session, outputs) = evaluate_all(&[
+        "x = 1",
+        "while x < 10:\n    x = x * 2",
+        "x",
+        "if x > 10: x = x - 1",
+        "x + 0",
+        "1 + 1",
+    ]);
+    assert_eq!(outputs, ["I64(1)", "", "I64(16)", "", "I64(15)", "I64(2)"]);
+    assert_eq!(
+        session.locals,
+        vec![
+            "x = 1",
+            "while x < 10:\n    x = x * 2",
+            "if x > 10: x = x - 1"
+        ]
+    );
+}
+
+#[test]
+fn test_repl_commands() {
+    let (session, outputs) = evaluate_all(&[
+        "def half(x: f64) -> f64: x / 2.0",
+        ":ast 3 |> f(4, _) |> g",
+        ":ast if x > 0: (x + 1) * 2 else: 0",
+        ":type half(3.0)",
+        ":type 1 < 2",
+    ]);
+    assert_eq!(
+        outputs,
+        vec![
+            "half",
+            "(g (f 4 3))",
+            "(if (> x 0) ((* (+ x 1) 2)) (0))",
+            "f64",
+            "i64"
+        ]
+    );
+    let (_, wat) = evaluate(session, ":wat half(3.0)");
+    assert!(wat.contains("(func $start (result f64)"));
+    assert!(wat.contains("(export \"_start\" (func $start))"));
+}
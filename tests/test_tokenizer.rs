@@ -1,6 +1,6 @@
 use pretty_assertions::assert_eq;
 
-use mongoose::tokenizer::{tokenize, Kind, Tokens, TopLevel};
+use mongoose::tokenizer::{hash, tokenize, Kind, Tokens, TopLevel};
 
 fn token_string_literal(
     top_level: &TopLevel,
@@ -23,6 +23,15 @@ fn token_string_symbol(top_level: &TopLevel, token: usize, mut output: String) -
     token_string_impl(top_level, token + 1, output)
 }
 
+fn token_string_attribute(top_level: &TopLevel, token: usize, mut output: String) -> String {
+    let text = &top_level.symbols[top_level.indices[token]];
+    output.push_str("        ");
+    output.push_str("Attribute(");
+    output.push_str(text);
+    output.push_str("),\n");
+    token_string_impl(top_level, token + 1, output)
+}
+
 fn token_string_int(top_level: &TopLevel, token: usize, mut output: String) -> String {
     let text = &top_level.ints[top_level.indices[token]];
     output.push_str("        ");
@@ -32,6 +41,15 @@ fn token_string_int(top_level: &TopLevel, token: usize, mut output: String) -> S
     token_string_impl(top_level, token + 1, output)
 }
 
+fn token_string_float(top_level: &TopLevel, token: usize, mut output: String) -> String {
+    let text = &top_level.floats[top_level.indices[token]];
+    output.push_str("        ");
+    output.push_str("Float(");
+    output.push_str(text);
+    output.push_str("),\n");
+    token_string_impl(top_level, token + 1, output)
+}
+
 fn token_string_indent(top_level: &TopLevel, token: usize, mut output: String) -> String {
     let indent = &top_level.indents[top_level.indices[token]];
     output.push_str("        ");
@@ -44,10 +62,14 @@ fn token_string_indent(top_level: &TopLevel, token: usize, mut output: String) -
 fn token_string_impl(top_level: &TopLevel, token: usize, output: String) -> String {
     match top_level.kinds.get(token) {
         Some(Kind::Def) => token_string_literal(top_level, token, output, "Def"),
+        Some(Kind::Fn) => token_string_literal(top_level, token, output, "Fn"),
         Some(Kind::LeftParen) => token_string_literal(top_level, token, output, "LeftParen"),
         Some(Kind::RightParen) => token_string_literal(top_level, token, output, "RightParen"),
+        Some(Kind::LeftBracket) => token_string_literal(top_level, token, output, "LeftBracket"),
+        Some(Kind::RightBracket) => token_string_literal(top_level, token, output, "RightBracket"),
         Some(Kind::Plus) => token_string_literal(top_level, token, output, "Plus"),
         Some(Kind::Minus) => token_string_literal(top_level, token, output, "Minus"),
+        Some(Kind::Arrow) => token_string_literal(top_level, token, output, "Arrow"),
         Some(Kind::Asterisk) => token_string_literal(top_level, token, output, "Asterisk"),
         Some(Kind::Slash) => token_string_literal(top_level, token, output, "Slash"),
         Some(Kind::Percent) => token_string_literal(top_level, token, output, "Percent"),
@@ -78,11 +100,28 @@ fn token_string_impl(top_level: &TopLevel, token: usize, output: String) -> Stri
             token_string_literal(top_level, token, output, "GreaterThanGreaterThan")
         }
         Some(Kind::Comma) => token_string_literal(top_level, token, output, "Comma"),
+        Some(Kind::DotDot) => token_string_literal(top_level, token, output, "DotDot"),
         Some(Kind::If) => token_string_literal(top_level, token, output, "If"),
         Some(Kind::Else) => token_string_literal(top_level, token, output, "Else"),
+        Some(Kind::Elif) => token_string_literal(top_level, token, output, "Elif"),
         Some(Kind::While) => token_string_literal(top_level, token, output, "While"),
+        Some(Kind::For) => token_string_literal(top_level, token, output, "For"),
+        Some(Kind::In) => token_string_literal(top_level, token, output, "In"),
+        Some(Kind::Match) => token_string_literal(top_level, token, output, "Match"),
+        Some(Kind::Enum) => token_string_literal(top_level, token, output, "Enum"),
+        Some(Kind::Export) => token_string_literal(top_level, token, output, "Export"),
+        Some(Kind::As) => token_string_literal(top_level, token, output, "As"),
+        Some(Kind::Import) => token_string_literal(top_level, token, output, "Import"),
+        Some(Kind::From) => token_string_literal(top_level, token, output, "From"),
+        Some(Kind::Const) => token_string_literal(top_level, token, output, "Const"),
+        Some(Kind::Var) => token_string_literal(top_level, token, output, "Var"),
+        Some(Kind::Break) => token_string_literal(top_level, token, output, "Break"),
+        Some(Kind::Continue) => token_string_literal(top_level, token, output, "Continue"),
+        Some(Kind::Return) => token_string_literal(top_level, token, output, "Return"),
         Some(Kind::Symbol) => token_string_symbol(top_level, token, output),
+        Some(Kind::Attribute) => token_string_attribute(top_level, token, output),
         Some(Kind::Int) => token_string_int(top_level, token, output),
+        Some(Kind::Float) => token_string_float(top_level, token, output),
         Some(Kind::Indent) => token_string_indent(top_level, token, output),
         None => output,
     }
@@ -744,3 +783,480 @@ Tokens([
 "#
     );
 }
+
+#[test]
+fn test_tokenize_export() {
+    let source = r#"
+export def area(w, h) as rect_area: w * h
+"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Export,
+        Def,
+        Symbol(area),
+        LeftParen,
+        Symbol(w),
+        Comma,
+        Symbol(h),
+        RightParen,
+        As,
+        Symbol(rect_area),
+        Colon,
+        Symbol(w),
+        Asterisk,
+        Symbol(h),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_import() {
+    let source = r#"
+import math
+import geometry.shapes as shapes
+from math import square, cube
+
+def start(): math.square(shapes.area(2, 3))
+"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Import,
+        Symbol(math),
+    ]),
+    TopLevel([
+        Import,
+        Symbol(geometry.shapes),
+        As,
+        Symbol(shapes),
+    ]),
+    TopLevel([
+        From,
+        Symbol(math),
+        Import,
+        Symbol(square),
+        Comma,
+        Symbol(cube),
+    ]),
+    TopLevel([
+        Def,
+        Symbol(start),
+        LeftParen,
+        RightParen,
+        Colon,
+        Symbol(math.square),
+        LeftParen,
+        Symbol(shapes.area),
+        LeftParen,
+        Int(2),
+        Comma,
+        Int(3),
+        RightParen,
+        RightParen,
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_globals() {
+    let source = r#"
+const MAX = 1000
+var counter = 0
+"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Const,
+        Symbol(MAX),
+        Equal,
+        Int(1000),
+    ]),
+    TopLevel([
+        Var,
+        Symbol(counter),
+        Equal,
+        Int(0),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_control_flow() {
+    let source = r#"
+def start():
+    while 1:
+        if 0: continue else: break
+    return 5
+"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Def,
+        Symbol(start),
+        LeftParen,
+        RightParen,
+        Colon,
+        Indent(4),
+        While,
+        Int(1),
+        Colon,
+        Indent(8),
+        If,
+        Int(0),
+        Colon,
+        Continue,
+        Else,
+        Colon,
+        Break,
+        Indent(4),
+        Return,
+        Int(5),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_elif() {
+    let source = r#"
+def sign(x):
+    if x < 0: 0 - 1 elif x > 0: 1 else: 0"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Def,
+        Symbol(sign),
+        LeftParen,
+        Symbol(x),
+        RightParen,
+        Colon,
+        Indent(4),
+        If,
+        Symbol(x),
+        LessThan,
+        Int(0),
+        Colon,
+        Int(0),
+        Minus,
+        Int(1),
+        Elif,
+        Symbol(x),
+        GreaterThan,
+        Int(0),
+        Colon,
+        Int(1),
+        Else,
+        Colon,
+        Int(0),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_for() {
+    let source = r#"
+def start():
+    for i in range(0, 3): i
+    for x in [1, 2]: x"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Def,
+        Symbol(start),
+        LeftParen,
+        RightParen,
+        Colon,
+        Indent(4),
+        For,
+        Symbol(i),
+        In,
+        Symbol(range),
+        LeftParen,
+        Int(0),
+        Comma,
+        Int(3),
+        RightParen,
+        Colon,
+        Symbol(i),
+        Indent(4),
+        For,
+        Symbol(x),
+        In,
+        LeftBracket,
+        Int(1),
+        Comma,
+        Int(2),
+        RightBracket,
+        Colon,
+        Symbol(x),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_match() {
+    let source = r#"
+def classify(x):
+    match x:
+        0: 1
+        1..10: 2
+        _ if x > 100: 3
+        _: 4"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Def,
+        Symbol(classify),
+        LeftParen,
+        Symbol(x),
+        RightParen,
+        Colon,
+        Indent(4),
+        Match,
+        Symbol(x),
+        Colon,
+        Indent(8),
+        Int(0),
+        Colon,
+        Int(1),
+        Indent(8),
+        Int(1),
+        DotDot,
+        Int(10),
+        Colon,
+        Int(2),
+        Indent(8),
+        Symbol(_),
+        If,
+        Symbol(x),
+        GreaterThan,
+        Int(100),
+        Colon,
+        Int(3),
+        Indent(8),
+        Symbol(_),
+        Colon,
+        Int(4),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_enum() {
+    let source = "enum Shape: Circle(r), Rect(w, h)";
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Enum,
+        Symbol(Shape),
+        Colon,
+        Symbol(Circle),
+        LeftParen,
+        Symbol(r),
+        RightParen,
+        Comma,
+        Symbol(Rect),
+        LeftParen,
+        Symbol(w),
+        Comma,
+        Symbol(h),
+        RightParen,
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_lambda() {
+    let source = "double = fn(x): x * 2";
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Symbol(double),
+        Equal,
+        Fn,
+        LeftParen,
+        Symbol(x),
+        RightParen,
+        Colon,
+        Symbol(x),
+        Asterisk,
+        Int(2),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_generic_function() {
+    let source = "def max[T](a: T, b: T) -> T: a - 1.5";
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Def,
+        Symbol(max),
+        LeftBracket,
+        Symbol(T),
+        RightBracket,
+        LeftParen,
+        Symbol(a),
+        Colon,
+        Symbol(T),
+        Comma,
+        Symbol(b),
+        Colon,
+        Symbol(T),
+        RightParen,
+        Arrow,
+        Symbol(T),
+        Colon,
+        Symbol(a),
+        Minus,
+        Float(1.5),
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_attribute() {
+    let source = r#"
+@inline
+def square(x): x * x
+
+@noinline def start(): square(3)"#;
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Attribute(inline),
+        Def,
+        Symbol(square),
+        LeftParen,
+        Symbol(x),
+        RightParen,
+        Colon,
+        Symbol(x),
+        Asterisk,
+        Symbol(x),
+    ]),
+    TopLevel([
+        Attribute(noinline),
+        Def,
+        Symbol(start),
+        LeftParen,
+        RightParen,
+        Colon,
+        Symbol(square),
+        LeftParen,
+        Int(3),
+        RightParen,
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_positions() {
+    let source = "def f(x): x + 10\n\n# comment\ndef g():\n    f(2.5)";
+    let tokens = tokenize(source);
+    let texts = tokens
+        .top_level
+        .iter()
+        .map(|top_level| {
+            top_level
+                .positions
+                .iter()
+                .map(|&position| &source[position..position + 1])
+                .collect::<String>()
+        })
+        .collect::<Vec<String>>();
+    assert_eq!(texts, vec!["df(x):x+1", "dg():\nf(2)"]);
+}
+
+#[test]
+fn test_tokenize_range_after_symbol() {
+    let source = "def start(): for i in x..5: math.square(i)";
+    let tokens = tokenize(source);
+    assert_eq!(
+        token_string(&tokens),
+        r#"
+Tokens([
+    TopLevel([
+        Def,
+        Symbol(start),
+        LeftParen,
+        RightParen,
+        Colon,
+        For,
+        Symbol(i),
+        In,
+        Symbol(x),
+        DotDot,
+        Int(5),
+        Colon,
+        Symbol(math.square),
+        LeftParen,
+        Symbol(i),
+        RightParen,
+    ]),
+])
+"#
+    );
+}
+
+#[test]
+fn test_tokenize_stable_hash() {
+    let tokens = tokenize("def square(x): x * x");
+    assert_eq!(hash(&tokens.top_level[0]), 11537449180637660491);
+}